@@ -1,14 +1,25 @@
 --- @class MultibufRegion
 --- @field start_row integer 0-indexed start row
 --- @field end_row integer 0-indexed end row (inclusive)
+--- @field cols table<integer, integer>? Column hints (0-indexed byte) keyed by 0-indexed source row, e.g. search matches
+--- @field footer any[]? Virtual lines rendered below the region
+--- @field max_lines integer? Overrides `max_lines_per_region` for this region
+--- @field context { before: integer?, after: integer? }? Read-only lines shown around the region
+--- @field readonly boolean? Edits of the region are discarded on write
+--- @field transform "dedent"? Show the region with its common indentation stripped, writes put it back
+--- @field origin any? Whatever the region was made from, e.g. a quickfix item, reported by |multibuf_get_regions|
+--- @field start_col integer? 0-indexed byte column, with `end_col` the region only shows those columns of its lines
+--- @field end_col integer? 0-indexed byte column after the last one of the region (exclusive)
+--- @field label string? Name of the region listed by the region pickers and |multibuf_get_regions|
 
 --- @alias MultibufTitleRenderFunction fun(bufnr: integer): any[]
 
 --- @class MultibufAddBufOptions
 --- @field buf integer Buffer handle
---- @field regions MultibufRegion[] List of regions to include
+--- @field regions MultibufRegion[] List of regions to include, one without `end_row` is the line at `start_row`
 --- @field title any[]|nil|MultibufTitleRenderFunction
 --- @field id string|nil
+--- @field force boolean|nil Allow sources larger than `MAX_SOURCE_BYTES`
 
 --- @class MultibufBufInfo
 --- @field buf integer Buffer handle
@@ -16,17 +27,207 @@
 --- @field region_extmark_ids integer[] IDs of extmarks tracking regions in multibuffer
 --- @field virt_expand_extmark_ids integer[] IDs of extmarks for expander UI
 --- @field pending_regions MultibufRegion[]? List of regions to be set up once loaded
+--- @field readonly boolean? Source can't be written to (help files, 'nomodifiable' buffers)
+--- @field elisions table<integer, MultibufElision>? Regions showing only their first and last lines
+--- @field region_meta MultibufRegionMeta[] Per region metadata (parallel to the region lists)
 --- @field loading boolean? Whether this buffer is currently being loaded/processed
 --- @field title any[]|nil|MultibufTitleRenderFunction
 --- @field id string|nil
+--- @field fingerprints MultibufRegionFingerprint[]? Content fingerprints of the source regions at last reload
+--- @field snapshots string[][]? Source lines each region showed at the last reload
+--- @field bases MultibufRegionBase[]? Source content of each region at the last reload or write
+--- @field conflicts table<integer, boolean>? Regions whose source changed while they had unwritten edits
+--- @field source_line_count integer? Source line count at last reload
+--- @field changedtick integer? Source |b:changedtick| at last reload
+--- @field title_extmark_id integer? Extmark of the title, placed even while the title is hidden
+
+--- @class MultibufElision
+--- @field head integer Source lines shown before the elision marker
+--- @field tail integer Source lines shown after the elision marker
+--- @field hidden integer Source lines not shown
+--- @field mark_id integer|nil Multibuffer extmark on the first tail row, tracks the split while editing
+
+--- @class MultibufRegionMeta
+--- @field id integer Stable region id, unique across all multibuffers
+--- @field cols table<integer, integer>? Column hints keyed by 0-indexed source row
+--- @field unsaved_source boolean? Added from a file whose buffer had unsaved changes
+--- @field footer any[]? Virtual lines rendered below the region
+--- @field max_lines integer? Overrides `max_lines_per_region` for this region
+--- @field context { before: integer, after: integer }? Read-only lines at the edges of the region
+--- @field written boolean? Edits of the region were written and it wasn't edited since
+--- @field status_key string? Status the last |MultibufRegionStatusChanged| was emitted for
+--- @field last_write MultibufRegionWrite? Source lines replaced by the last write of the region
+--- @field readonly boolean? Edits of the region are discarded on write
+--- @field target MultibufRegionTarget? Buffer the edits of the region are written to instead of the source
+--- @field transform "dedent"? See |MultibufRegion|
+--- @field indent string? Indentation stripped from the source lines at the last reload (only with "dedent")
+--- @field origin any? See |MultibufRegion|
+--- @field label string? See |MultibufRegion|
+--- @field span MultibufRegionSpan? Columns of a region added with `start_col` and `end_col`
+
+--- @class MultibufRegionSpan
+--- @field start_col integer 0-indexed byte column at the last reload
+--- @field end_col integer 0-indexed byte column after the span at the last reload (exclusive)
+--- @field mark_id integer|nil Source extmark tracking the columns once the source is loaded
+--- @field block boolean|nil The region has several lines, the same columns of each are shown and not tracked
+
+--- @class MultibufRegionTarget
+--- @field buf integer Target buffer handle, loaded on write
+--- @field start_row integer|nil 0-indexed row the first write inserts at, nil appends
+--- @field mark_id integer|nil Extmark in the target over the lines of the last write
+
+--- @class MultibufRegionWrite
+--- @field before string[] Source lines of the region before the write
+--- @field after string[] Source lines of the region after the write
+--- @field tick integer Source |b:changedtick| right after the write
+
+--- @class MultibufRegionStatus
+--- @field clean boolean Not edited since the last reload or write
+--- @field edited boolean Edited since the last reload or write
+--- @field written boolean Edits were written and the region wasn't edited since
+--- @field conflict boolean The source changed while the region had unwritten edits
+--- @field readonly boolean The source or the region can't be written to
+--- @field hidden boolean Part of the region is hidden by an elision
+
+--- @class MultibufRegionInfo
+--- @field id integer Stable region id
+--- @field index integer Position of the region in render order (1-indexed)
+--- @field buf integer Source buffer handle
+--- @field start_row integer 0-indexed start row in the source
+--- @field end_row integer 0-indexed end row in the source (inclusive)
+--- @field unsaved_source boolean Added from a file whose buffer had unsaved changes
+--- @field target integer|nil Buffer the region is written to instead of its source
+--- @field origin any The `origin` the region was added with
+--- @field start_col integer|nil 0-indexed byte column of a column region
+--- @field end_col integer|nil 0-indexed byte column after a column region (exclusive)
+--- @field label string|nil The `label` the region was added with
+
+--- @class MultibufRegionRef
+--- @field mb integer Multibuffer handle
+--- @field id integer Stable region id
+--- @field index integer Position of the region in render order (1-indexed)
+--- @field buf integer Source buffer handle
+--- @field unsaved_source boolean Added from a file whose buffer had unsaved changes
+--- @field range fun(self: MultibufRegionRef): integer|nil, integer|nil Current 0-indexed source rows (inclusive)
+--- @field lines fun(self: MultibufRegionRef): string[]|nil Current source lines of the region
+
+--- @class MultibufContext
+--- @field buf integer Source buffer handle
+--- @field line integer 0-indexed line in the source buffer
+--- @field col integer 0-indexed byte column hint, 0 when unknown
+--- @field region_id integer|nil Stable id of the region the line belongs to
+
+--- @class MultibufSelection
+--- @field buf integer Source buffer handle
+--- @field start_row integer 0-indexed start row
+--- @field end_row integer 0-indexed end row (inclusive)
+--- @field label string|nil Title shown above the selection instead of the buffer name
+
+--- @class MultibufBatchItem
+--- @field source_buf integer Source buffer handle
+--- @field region MultibufRegion
+--- @field label string|nil Title shown above the entry of the item instead of the buffer name
+--- @field context { before: integer?, after: integer? }|nil Overrides `region.context`
+--- @field readonly boolean|nil Edits of the region are discarded on write
+--- @field group any Items of a source with the same group share an entry, nil groups by source
+
+--- @class MultibufBatchFailure
+--- @field index integer Position of the item in the batch (1-indexed)
+--- @field err string Why the item was skipped
+
+--- @class MultibufProviderEntry
+--- @field source integer|string Buffer handle or file path, loaded before the regions are added
+--- @field regions MultibufRegion[]
+--- @field label string|nil Title shown above the source instead of the buffer name
+--- @field origin any Set as the `origin` of regions that don't have one
+--- @field matches { row: integer, col: integer }[]|nil 0-indexed positions the entry was found at, used as column hints
+
+--- @alias MultibufProvider fun(opts: table): MultibufProviderEntry[]
+
+--- @class MultibufStateRegion
+--- @field start_row integer 0-indexed start row when the state was saved
+--- @field end_row integer 0-indexed end row (inclusive)
+--- @field fingerprint MultibufRegionFingerprint|nil Content the region showed
+--- @field context { before: integer, after: integer }|nil
+--- @field max_lines integer|nil
+--- @field readonly boolean|nil
+--- @field start_col integer|nil 0-indexed byte column of a column region
+--- @field end_col integer|nil 0-indexed byte column after a column region (exclusive)
+
+--- @class MultibufStateSource
+--- @field path string Absolute path of the source file
+--- @field id string|nil
+--- @field regions MultibufStateRegion[]
+
+--- @class MultibufState
+--- @field title string|nil
+--- @field titles MultibufTitleMode|nil
+--- @field sources MultibufStateSource[]
+
+--- @class MultibufRestoreReport
+--- @field relocated { path: string, from: integer, to: integer }[] Regions found at another start row
+--- @field stale { path: string, start_row: integer }[] Regions whose content wasn't found, shown at the saved rows
+--- @field missing string[] Paths of sources that couldn't be restored
+
+--- @class MultibufJumpOptions
+--- @field wrap boolean? Counts running past the first or last region wrap around instead of stopping there
+--- @field center boolean? Center the landed line in the window like |zz|
+--- @field open_folds boolean? Open the folds at the landed line like |zv|
+
+--- @class MultibufListEntry
+--- @field buf integer Multibuffer handle
+--- @field name string Buffer name
+--- @field source_count integer Number of sources
+--- @field active boolean Whether this is the active multibuffer of the current tabpage
+
+--- @class MultibufRegionFingerprint
+--- @field first string sha256 of the first line of the region
+--- @field last string sha256 of the last line of the region
+--- @field len integer Number of lines in the region
+--- @field row integer? 0-indexed start row of the region when the fingerprint was taken
+
+--- @class MultibufRegionBase
+--- @field lines string[]|nil Source lines of the region, nil when it has more than `BASE_MAX_LINES`
+--- @field hash string sha256 of the lines joined with "\n"
+--- @field start_row integer 0-indexed source row the lines started at
+--- @field end_row integer 0-indexed source row of the last line (inclusive)
+--- @field changedtick integer Source |b:changedtick| when the lines were taken
 
 --- @class MultibufInfo
 --- @field bufs MultibufBufInfo[] Info about included buffers
 --- @field header string[]? Custom header lines
+--- @field title string? Title used for the buffer name
+--- @field number_mode MultibufNumberMode? How line numbers are rendered in the sign column
+--- @field hide_titles boolean? Don't render the per source titles
+--- @field title_mode MultibufTitleMode? Overrides the `titles` setup option
+--- @field folds MultibufRegion[]? Source folds mapped to multibuffer rows (only with `fold_regions`)
+--- @field fold_levels table<integer, string>? Fold expression per 0-indexed multibuffer row
+--- @field invalid string? Set when the regions could not be made consistent again
+--- @field validating boolean? Set while recovering from an inconsistent state
+--- @field pending_sources table<integer, boolean>? Sources whose sync waits for the next write
+--- @field touched_rows { [1]: integer, [2]: integer }? Rows edited since the region status was last checked
+--- @field touched_marks table<integer, boolean>? Region extmarks edited since the last reload or write
+--- @field dirty_tick integer? Bumped on every edit, debounces the dirty signs
+--- @field suspended integer? Nesting count of |multibuf_suspend| for this multibuffer
+--- @field suspended_sources table<integer, boolean>? Sources that changed while syncing was suspended
+--- @field number_text_width integer? Digits of the line numbers at the last reload (only with virtual text numbers)
+--- @field sync_chain { bufs: integer[], tick: integer }? Multibuffers whose syncs led to the last sync of this one
+--- @field wrap boolean? Soft wrap long lines in the windows showing the multibuffer
+--- @field busy integer? Nesting count of the reloads and writes running, see `run_guarded`
+--- @field wiped boolean? Wiped out while busy, the cleanup waits for them to return
+
+--- @alias MultibufNumberMode "absolute"|"relative"|"both"
+
+--- @alias MultibufTitleMode
+--- | "always" a title above every source entry
+--- | "first_per_file" only above the first entry of a source shown more than once
+--- | "never" no titles
+--- | "auto" "never" with a single source, "first_per_file" otherwise
 
 --- @class MultibufBufListener
 --- @field multibufs integer[] List of multibuffers listening to this source
---- @field change_autocmd_id integer ID of the TextChanged autocmd
+--- @field change_autocmd_id integer ID of the `sync_events` autocmd
+--- @field poll_timer uv.uv_timer_t|nil Timer comparing |b:changedtick| every `sync_poll_ms`
 
 --- @class MultibufSetupOptions
 --- @field render_multibuf_title MultibufTitleRenderFunction|nil Custom title renderer
@@ -37,6 +238,30 @@
 --- @field region_hl_even string|nil
 --- @field region_hl_odd string|nil
 --- @field region_hl_odd_adjust integer|nil Contrast intensity for default odd region backgrounds. Higher values increase intensity/contrast. (default 12)
+--- @field fold_regions boolean|nil Mirror foldable structure of source buffers into the multibuffer (default false)
+--- @field region_padding integer|nil Real blank lines between regions (default 0)
+--- @field peek_on_hold boolean|nil Peek the source under the cursor on CursorHold (default false)
+--- @field max_lines_per_region integer|nil Longer regions only show their first and last lines (default nil)
+--- @field sync_events string[]|nil Events on source buffers that reload the multibuffers showing them
+--- @field sync_poll_ms integer|nil Also check the sources for changes this often, 0 to disable (default 0)
+--- @field on_pre_write (fun(mb: integer, summary: MultibufWriteSummary): boolean|nil)|nil Return false to cancel a write
+--- @field on_post_write fun(mb: integer, summary: MultibufWriteSummary)|nil Called after a write
+--- @field name_format (fun(mb: integer, title: string|nil): string)|nil Buffer name of a multibuffer
+--- @field line_number_width "uniform"|"region"|nil Sizing of line numbers in an "auto" 'signcolumn' (default "uniform")
+--- @field line_number_mode "signs"|"virt_text"|"inline"|nil Where line numbers are rendered (default "signs")
+--- @field delete_region_behavior "remove"|"delete_source"|"ask"|nil Deleting all lines of a region (default "remove")
+--- @field window_options table<string, any>|false|nil Window options set while a window shows a multibuffer
+--- @field titles MultibufTitleMode|nil Which source entries get a title (default "always")
+--- @field auto_merge boolean|nil Merge regions of a source that grew into each other on reload (default true)
+--- @field auto_remove_empty_regions boolean|nil Drop regions whose source lines are all deleted (default false)
+--- @field auto_write boolean|nil |:write| in a multibuffer calls |multibuf_write| (default true)
+--- @field dirty_signs boolean|nil Mark the first line of edited regions in the sign column (default false)
+--- @field dirty_sign string|nil Sign text of `dirty_signs` (default "▎")
+--- @field dirty_sign_hl string|nil Highlight group of `dirty_signs` (default "MultibufRegionDirty")
+--- @field show_region_index boolean|nil Sign with the index of each region on its first line (default false)
+--- @field region_index_hl string|nil Highlight group of `show_region_index` (default "MultibufRegionIndex")
+--- @field layout "rows"|"columns"|nil Titles above the regions or a source name column beside them (default "rows")
+--- @field highlight "live"|"mirror"|nil Project source highlights on redraw or copy them on reload (default "live")
 
 --- @class multibuffer.RenderExpandLinesOptions
 --- @field expand_direction "above"|"below"|"both"
@@ -54,6 +279,96 @@ local buf_listeners = {}
 --- @type table<integer, MultibufAddBufOptions[]>
 local pending_adds = {}
 
+local next_region_id = 1
+
+--- Region providers by name, see |multibuf_register_provider|
+--- @type table<string, MultibufProvider>
+local providers = {}
+
+--- Nesting count of |multibuf_suspend| for all multibuffers
+local suspended_all = 0
+
+--- Floating peek window per multibuffer
+--- @type table<integer, integer>
+local peek_wins = {}
+
+--- Preview window following the cursor per multibuffer
+--- @type table<integer, { win: integer, tick: integer }>
+local follow_previews = {}
+
+--- @param mb integer
+local function close_follow_preview(mb)
+	local preview = follow_previews[mb]
+	follow_previews[mb] = nil
+	if preview and vim.api.nvim_win_is_valid(preview.win) then
+		vim.api.nvim_win_close(preview.win, true)
+	end
+	pcall(vim.api.nvim_del_augroup_by_name, "MultibufFollowPreview" .. mb)
+end
+
+--- Outline panel per multibuffer, see |multibuf_outline_open|
+--- @type table<integer, { win: integer, buf: integer }>
+local outlines = {}
+
+--- @param mb integer
+local function close_outline(mb)
+	local outline = outlines[mb]
+	outlines[mb] = nil
+	-- a split can be the last window, it then stays with the empty panel
+	if outline and vim.api.nvim_win_is_valid(outline.win) then
+		pcall(vim.api.nvim_win_close, outline.win, true)
+	end
+	if outline and vim.api.nvim_buf_is_valid(outline.buf) then
+		vim.api.nvim_buf_delete(outline.buf, { force = true })
+	end
+	pcall(vim.api.nvim_del_augroup_by_name, "MultibufOutline" .. mb)
+end
+
+--- @param mb integer
+local function close_peek(mb)
+	local win = peek_wins[mb]
+	peek_wins[mb] = nil
+	if win and vim.api.nvim_win_is_valid(win) then
+		vim.api.nvim_win_close(win, true)
+	end
+	pcall(vim.keymap.del, "n", "q", { buffer = mb })
+	pcall(vim.api.nvim_del_augroup_by_name, "MultibufPeek" .. mb)
+end
+
+--- Active multibuffer per tabpage, the default target of the convenience APIs
+--- @type table<integer, integer>
+local active_multibufs = {}
+
+--- @param region MultibufRegion
+--- @return MultibufRegionMeta
+local function new_region_meta(region)
+	local meta = {
+		id = next_region_id,
+		cols = region.cols,
+		footer = region.footer,
+		max_lines = region.max_lines,
+		readonly = region.readonly,
+		transform = region.transform,
+		origin = region.origin,
+		label = region.label,
+	}
+	if region.start_col then
+		meta.span = {
+			start_col = region.start_col,
+			end_col = region.end_col,
+			block = (region.end_row or region.start_row) > region.start_row or nil,
+		}
+	end
+	if region.context then
+		meta.context = {
+			before = math.min(region.context.before or 0, region.start_row),
+			after = region.context.after or 0,
+		}
+	end
+	next_region_id = next_region_id + 1
+	return meta
+end
+
 --- @param list any[]
 --- @param item any
 local function list_insert_unique(list, item)
@@ -65,6 +380,75 @@ local function list_insert_unique(list, item)
 	table.insert(list, item)
 end
 
+--- @param list any[]
+--- @param item any
+local function list_remove(list, item)
+	for i = #list, 1, -1 do
+		if list[i] == item then
+			table.remove(list, i)
+		end
+	end
+end
+
+--- The leading whitespace all non-blank {lines} share.
+--- @param lines string[]
+--- @return string
+local function common_indent(lines)
+	local indent
+	for _, line in ipairs(lines) do
+		if line:find("%S") then
+			local ws = line:match("^%s*")
+			if not indent then
+				indent = ws
+			else
+				local n = 0
+				while n < #indent and n < #ws and indent:byte(n + 1) == ws:byte(n + 1) do
+					n = n + 1
+				end
+				indent = indent:sub(1, n)
+			end
+			if indent == "" then
+				break
+			end
+		end
+	end
+	return indent or ""
+end
+
+--- Strip {indent} from {lines}. Blank lines shorter than it become empty.
+--- @param lines string[]
+--- @param indent string
+--- @return string[]
+local function dedent_lines(lines, indent)
+	if indent == "" then
+		return lines
+	end
+	local dedented = {}
+	for i, line in ipairs(lines) do
+		if line:sub(1, #indent) == indent then
+			dedented[i] = line:sub(#indent + 1)
+		else
+			dedented[i] = line:find("%S") and line or ""
+		end
+	end
+	return dedented
+end
+
+--- Put {indent} back in front of the non-empty {lines}.
+--- @param lines string[]
+--- @param indent string
+--- @return string[]
+local function reindent_lines(lines, indent)
+	if indent == "" then
+		return lines
+	end
+	local reindented = {}
+	for i, line in ipairs(lines) do
+		reindented[i] = line ~= "" and indent .. line or line
+	end
+	return reindented
+end
+
 --- @param num number
 --- @param min number
 --- @param max number
@@ -103,1148 +487,7457 @@ local M = {
 		region_hl_even = "MultibufRegionEven",
 		region_hl_odd = "MultibufRegionOdd",
 		region_hl_odd_adjust = 12,
+		sync_events = { "TextChanged", "TextChangedI" },
+		sync_poll_ms = 0,
+		window_options = { wrap = false, spell = false },
+		titles = "always",
+		auto_merge = true,
+		delete_region_behavior = "remove",
+		line_number_width = "uniform",
+		line_number_mode = "signs",
+		dirty_signs = false,
+		dirty_sign = "▎",
+		dirty_sign_hl = "MultibufRegionDirty",
+		show_region_index = false,
+		region_index_hl = "MultibufRegionIndex",
+		layout = "rows",
+		highlight = "live",
+		auto_remove_empty_regions = false,
+		auto_write = true,
 	},
-	--- @type integer Namespace for structural elements (signs, titles)
+	--- @type integer Namespace for region tracking (source, region and elision extmarks)
 	multibuf__ns = nil,
+	--- @type integer Namespace for decorations rebuilt on reload (signs, titles, expanders)
+	multibuf_structure_ns = nil,
 	--- @type integer Namespace for live highlight projection
 	multibuf_hl_ns = nil,
+	--- @type integer Namespace for source highlights copied on reload (`highlight = "mirror"`)
+	multibuf_hl_mirror_ns = nil,
+	--- @type integer Namespace for cursor relative line number signs
+	multibuf_number_ns = nil,
+	--- @type integer Namespace for marks mirrored from sources, kept across reloads
+	multibuf_mirror_ns = nil,
+	--- @type integer Namespace for the signs of edited regions
+	multibuf_dirty_ns = nil,
+	--- @type integer Namespace for the status highlights of outline panels
+	multibuf_outline_ns = nil,
 }
 
---- @param args table
-local function multibuf_buf_changed(args)
-	local listener_info = buf_listeners[args.buf]
-	if listener_info then
-		for _, multibuf in ipairs(listener_info.multibufs) do
-			M.multibuf_reload(multibuf)
+--- Columns of a column region in {buf}, nil when its mark is gone or left
+--- the region's line {row}.
+--- @param buf integer
+--- @param meta MultibufRegionMeta|nil
+--- @param row integer
+--- @return integer|nil start_col, integer|nil end_col
+local function get_span_cols(buf, meta, row)
+	local span = meta and meta.span
+	if not span or not span.mark_id then
+		return nil, nil
+	end
+	local ok, mark = pcall(vim.api.nvim_buf_get_extmark_by_id, buf, M.multibuf__ns, span.mark_id, { details = true })
+	if not ok or not mark[1] or mark[1] ~= row or (mark[3].end_row or row) ~= row then
+		return nil, nil
+	end
+	return mark[2], math.max(mark[2], mark[3].end_col or mark[2])
+end
+
+--- Columns of a column region in one of its lines, clamped to the line and
+--- moved to the start of the characters they fall into.
+--- @param line string
+--- @param span MultibufRegionSpan
+--- @return integer start_col, integer end_col
+local function get_block_cols(line, span)
+	local function snap(col)
+		if col >= #line then
+			return #line
 		end
+		return col + vim.str_utf_start(line, col + 1)
 	end
+	return snap(span.start_col), snap(span.end_col)
 end
 
+--- Track the columns of a column region with an extmark in {buf}. Text typed
+--- at either edge stays outside of it.
 --- @param buf integer
---- @param extmark integer
---- @return integer|nil, integer|nil
-local function get_extmark_range(buf, extmark)
-	local result = vim.api.nvim_buf_get_extmark_by_id(buf, M.multibuf__ns, extmark, { details = true })
-	if not result or not result[1] then
-		return nil, nil
+--- @param meta MultibufRegionMeta
+--- @param row integer
+--- @param start_col integer
+--- @param end_col integer
+local function place_span_mark(buf, meta, row, start_col, end_col)
+	meta.span.mark_id = vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, row, start_col, {
+		id = meta.span.mark_id,
+		end_row = row,
+		end_col = end_col,
+	})
+	meta.span.start_col, meta.span.end_col = start_col, end_col
+end
+
+--- Turn a column region into a line region.
+--- @param buf integer
+--- @param meta MultibufRegionMeta
+local function clear_span(buf, meta)
+	if meta.span and meta.span.mark_id then
+		pcall(vim.api.nvim_buf_del_extmark, buf, M.multibuf__ns, meta.span.mark_id)
 	end
-	return result[1], result[3].end_row
+	meta.span = nil
 end
 
---- @param b_info MultibufBufInfo
-local function merge_buffer_regions(b_info)
-	local buf = b_info.buf
-	if b_info.pending_regions then
-		local regions = b_info.pending_regions
-		if #regions <= 1 then
-			return
-		end
+--- Bytes the lines of a region are shifted left by in the multibuffer.
+--- @param meta MultibufRegionMeta|nil
+--- @return integer
+local function get_col_offset(meta)
+	if meta and meta.indent then
+		return #meta.indent
+	end
+	return meta and meta.span and meta.span.start_col or 0
+end
 
-		table.sort(regions, function(a, b)
-			return a.start_row < b.start_row
-		end)
+--- Combine the metadata of two merged regions, keeping the id of the first.
+--- @param meta MultibufRegionMeta
+--- @param other MultibufRegionMeta
+--- @param buf integer source buffer
+local function merge_region_meta(meta, other, buf)
+	-- the merged region spans whole lines
+	clear_span(buf, meta)
+	clear_span(buf, other)
+	if other.cols then
+		meta.cols = vim.tbl_extend("keep", meta.cols or {}, other.cols)
+	end
+	-- the footer belongs to the end of the merged region
+	meta.footer = other.footer or meta.footer
+	meta.max_lines = meta.max_lines or other.max_lines
+	-- edits of a read-only part would be written otherwise
+	meta.readonly = meta.readonly or other.readonly
+	meta.transform = meta.transform or other.transform
+	meta.label = meta.label or other.label
+	-- context lines between the merged regions become writable
+	if meta.context or other.context then
+		meta.context = {
+			before = meta.context and meta.context.before or 0,
+			after = other.context and other.context.after or 0,
+		}
+	end
+end
 
-		local merged = {}
-		local current = regions[1]
+--- @alias MultibufExtmarkIssue
+--- | "deleted" the extmark is gone, no range is returned
+--- | "invalid_buffer" the buffer is gone, no range is returned
+--- | "inverted" the end was before the start, the range is empty at the start
+--- | "no_end" the extmark has no end, the range covers its line
 
-		for i = 2, #regions do
-			local next_r = regions[i]
-			if next_r.start_row <= current.end_row + 1 then
-				current.end_row = math.max(current.end_row, next_r.end_row)
-			else
-				table.insert(merged, current)
-				current = next_r
-			end
-		end
-		table.insert(merged, current)
-		b_info.pending_regions = merged
-		b_info.region_extmark_ids = {}
-	else
-		local ranges = {}
-		for _, sid in ipairs(b_info.source_extmark_ids) do
-			local s, e = get_extmark_range(buf, sid)
-			table.insert(ranges, { s = s, e = e })
-		end
+--- Range of a region extmark. Damaged ranges are normalized and reported
+--- through the third return value, which is nil for a healthy range.
+--- @param buf integer
+--- @param extmark integer
+--- @return integer|nil start_row, integer|nil end_row exclusive, MultibufExtmarkIssue|nil issue
+local function get_extmark_range(buf, extmark)
+	local ok, result =
+		pcall(vim.api.nvim_buf_get_extmark_by_id, buf, M.multibuf__ns, extmark, { details = true })
+	if not ok then
+		return nil, nil, "invalid_buffer"
+	end
+	if not result or not result[1] then
+		return nil, nil, "deleted"
+	end
+	local start_row, end_row = result[1], result[3] and result[3].end_row
+	if not end_row then
+		return start_row, start_row + 1, "no_end"
+	elseif end_row < start_row then
+		return start_row, start_row, "inverted"
+	end
+	return start_row, end_row, nil
+end
 
-		if #ranges <= 1 then
-			return
-		end
+-- ──────── Elision ────────
 
-		table.sort(ranges, function(a, b)
-			return a.s < b.s
-		end)
+--- Long regions only show their first and last lines with a marker in
+--- between. The hidden lines stay untouched in the source on write.
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param len integer number of source lines of the region
+--- @return MultibufElision|nil
+local function compute_elision(b_info, s_idx, len)
+	local meta = b_info.region_meta[s_idx]
+	local max_lines = meta and meta.max_lines or M.user_opts.max_lines_per_region
+	if not max_lines or max_lines < 2 or len <= max_lines then
+		return nil
+	end
+	local head = math.ceil(max_lines / 2)
+	return { head = head, tail = max_lines - head, hidden = len - max_lines }
+end
 
-		local merged = {}
-		local current = ranges[1]
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @return integer|nil tail_row first multibuffer row after the elision marker
+local function get_elision_tail_row(mb, b_info, s_idx)
+	local elision = b_info.elisions and b_info.elisions[s_idx]
+	if not elision or not elision.mark_id then
+		return nil
+	end
+	local mark = vim.api.nvim_buf_get_extmark_by_id(mb, M.multibuf__ns, elision.mark_id, {})
+	return mark[1]
+end
 
-		for i = 2, #ranges do
-			local next_r = ranges[i]
-			if next_r.s <= current.e then
-				current.e = math.max(current.e, next_r.e)
-			else
-				table.insert(merged, current)
-				current = next_r
-			end
-		end
-		table.insert(merged, current)
+--- Map a multibuffer row inside a region to its source row, skipping the
+--- lines hidden by an elision.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param row integer multibuffer row
+--- @param r_start integer first multibuffer row of the region
+--- @param r_end integer multibuffer row after the region
+--- @param s_start integer first source row of the region
+--- @param s_end integer source row after the region
+--- @return integer source_row
+local function region_row_to_source(mb, b_info, s_idx, row, r_start, r_end, s_start, s_end)
+	local tail_row = get_elision_tail_row(mb, b_info, s_idx)
+	if tail_row and row >= tail_row then
+		return s_end - (r_end - row)
+	end
+	return s_start + (row - r_start)
+end
 
-		-- Update extmarks
-		for _, sid in ipairs(b_info.source_extmark_ids) do
-			vim.api.nvim_buf_del_extmark(buf, M.multibuf__ns, sid)
-		end
-		b_info.source_extmark_ids = {}
-		b_info.region_extmark_ids = {}
-		for _, r in ipairs(merged) do
-			table.insert(
-				b_info.source_extmark_ids,
-				vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, r.s, 0, {
-					end_row = r.e,
-					end_right_gravity = true,
-				})
-			)
+--- Inverse of |region_row_to_source|. Hidden source lines map to the first
+--- row after the elision marker.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param source_row integer
+--- @param r_start integer first multibuffer row of the region
+--- @param r_end integer multibuffer row after the region
+--- @param s_start integer first source row of the region
+--- @param s_end integer source row after the region
+--- @return integer row multibuffer row
+local function source_row_to_region(mb, b_info, s_idx, source_row, r_start, r_end, s_start, s_end)
+	local tail_row = get_elision_tail_row(mb, b_info, s_idx)
+	if tail_row then
+		if source_row >= s_end - (r_end - tail_row) then
+			return r_end - (s_end - source_row)
+		elseif source_row >= s_start + (tail_row - r_start) then
+			return tail_row
 		end
 	end
+	return r_start + (source_row - s_start)
 end
 
---- @param mb integer
---- @param buf_info MultibufBufInfo
-local function load_source_buf(mb, buf_info)
-	local buf = buf_info.buf
-	if vim.api.nvim_buf_is_loaded(buf) and #buf_info.source_extmark_ids > 0 then
-		buf_info.loading = false
-		return false
+--- How far around the old position a region is searched for when re-anchoring
+local REANCHOR_SEARCH_RADIUS = 200
+
+--- @param lines string[] lines of a source region
+--- @param row integer|nil start row of the region
+--- @return MultibufRegionFingerprint|nil
+local function region_fingerprint(lines, row)
+	if #lines == 0 then
+		return nil
 	end
+	return {
+		first = vim.fn.sha256(lines[1]),
+		last = vim.fn.sha256(lines[#lines]),
+		len = #lines,
+		row = row,
+	}
+end
 
-	vim.fn.bufload(buf)
+-- regions with more lines only keep the hash of their base
+local BASE_MAX_LINES = 1000
+
+--- Source content a region is edited against, see |multibuf_region_base|.
+--- @param buf integer source buffer
+--- @param lines string[] lines of the source region
+--- @param row integer 0-indexed start row of the region
+--- @return MultibufRegionBase
+local function capture_base(buf, lines, row)
+	return {
+		lines = #lines <= BASE_MAX_LINES and lines or nil,
+		hash = vim.fn.sha256(table.concat(lines, "\n")),
+		start_row = row,
+		end_row = row + #lines - 1,
+		changedtick = vim.api.nvim_buf_get_changedtick(buf),
+	}
+end
 
+--- Searches for the start row of a region matching {fingerprint} closest to
+--- {near}.
+--- @param buf integer
+--- @param fingerprint MultibufRegionFingerprint
+--- @param near integer 0-indexed row the region used to start at
+--- @param radius integer|nil rows searched on each side (default `REANCHOR_SEARCH_RADIUS`)
+--- @return integer|nil start_row, boolean guessed true if the region moved or
+--- the match was ambiguous
+local function find_region_fingerprint(buf, fingerprint, near, radius)
+	radius = radius or REANCHOR_SEARCH_RADIUS
 	local line_count = vim.api.nvim_buf_line_count(buf)
-	local regions = buf_info.pending_regions or {}
-	buf_info.source_extmark_ids = {}
+	local from = math.max(0, near - radius)
+	local to = math.min(line_count, near + radius + fingerprint.len)
+	local lines = vim.api.nvim_buf_get_lines(buf, from, to, false)
+
+	local hashes = {}
+	local function hash_at(row)
+		local line = lines[row - from + 1]
+		if line == nil then
+			return nil
+		end
+		hashes[row] = hashes[row] or vim.fn.sha256(line)
+		return hashes[row]
+	end
 
-	for _, region in ipairs(regions) do
-		table.insert(
-			buf_info.source_extmark_ids,
-			vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, clamp(region.start_row, 0, line_count - 1), 0, {
-				end_row = clamp(region.end_row + 1, 0, line_count),
-				end_right_gravity = true,
-			})
-		)
+	local function matches_at(row)
+		return hash_at(row) == fingerprint.first and hash_at(row + fingerprint.len - 1) == fingerprint.last
 	end
-	buf_info.pending_regions = nil
-	buf_info.loading = false
 
-	if not buf_listeners[buf] then
-		local id = vim.api.nvim_create_autocmd({ "TextChanged", "TextChangedI" }, {
-			buffer = buf,
-			callback = multibuf_buf_changed,
-		})
-		buf_listeners[buf] = { change_autocmd_id = id, multibufs = { mb } }
-	else
-		list_insert_unique(buf_listeners[buf].multibufs, mb)
+	local found = nil
+	local match_count = 0
+	for offset = 0, radius do
+		for _, row in ipairs(offset == 0 and { near } or { near - offset, near + offset }) do
+			if row >= from and row + fingerprint.len <= to and matches_at(row) then
+				found = found or row
+				match_count = match_count + 1
+			end
+		end
 	end
 
-	return true
+	return found, found ~= near or match_count > 1
 end
 
+--- Undo in a source buffer restores its lines but not always our extmarks,
+--- which leaves a region collapsed or covering the wrong lines. A region
+--- whose length changed while the line count of the source did not after an
+--- undo is re-anchored using the fingerprint taken at the last reload.
 --- @param mb integer
-local function process_pending_adds(mb)
-	local pending = pending_adds[mb]
-	if not pending or #pending == 0 then
-		pending_adds[mb] = nil
-		return
-	end
-
+--- @param source_buf integer
+local function reanchor_source_regions(mb, source_buf)
 	local info = multibufs[mb]
 	if not info then
-		pending_adds[mb] = nil
 		return
 	end
 
-	-- For visibility-based loading, we just add them as "unloaded" entries
-	for _, opts in ipairs(pending) do
-		local b_info = {
-			buf = opts.buf,
-			source_extmark_ids = {},
-			region_extmark_ids = {},
-			virt_expand_extmark_ids = {},
-			pending_regions = opts.regions,
-			title = opts.title,
-			id = opts.id,
-		}
-		merge_buffer_regions(b_info)
-		table.insert(info.bufs, b_info)
+	local undotree = vim.fn.undotree(source_buf)
+	if undotree.seq_cur == undotree.seq_last then
+		return
 	end
 
-	pending_adds[mb] = nil
-	M.multibuf_reload(mb)
-end
-
---- @param buf integer
---- @return integer|nil
-local function get_buf_win(buf)
-	local cur_win = vim.api.nvim_get_current_win()
-	if vim.api.nvim_win_get_buf(cur_win) == buf then
-		return cur_win
-	end
-	for _, win in ipairs(vim.api.nvim_list_wins()) do
-		if vim.api.nvim_win_is_valid(win) and vim.api.nvim_win_get_buf(win) == buf then
-			return win
+	local line_count = vim.api.nvim_buf_line_count(source_buf)
+	for _, b_info in ipairs(info.bufs) do
+		if
+			b_info.buf == source_buf
+			and not b_info.pending_regions
+			and b_info.fingerprints
+			and b_info.source_line_count == line_count
+		then
+			for i, sid in ipairs(b_info.source_extmark_ids) do
+				local fingerprint = b_info.fingerprints[i]
+				local s, e = get_extmark_range(source_buf, sid)
+				if fingerprint and s and e and e - s ~= fingerprint.len then
+					local new_start, guessed = find_region_fingerprint(source_buf, fingerprint, s)
+					local name = vim.fn.fnamemodify(vim.api.nvim_buf_get_name(source_buf), ":~:.")
+					if new_start then
+						vim.api.nvim_buf_set_extmark(source_buf, M.multibuf__ns, new_start, 0, {
+							id = sid,
+							end_row = new_start + fingerprint.len,
+							end_right_gravity = true,
+						})
+						if guessed then
+							vim.notify(
+								string.format("multibuffer: re-anchored region of %s to line %i", name, new_start + 1),
+								vim.log.levels.WARN
+							)
+						end
+					else
+						vim.notify(
+							string.format("multibuffer: could not re-anchor region of %s after undo", name),
+							vim.log.levels.WARN
+						)
+					end
+				end
+			end
 		end
 	end
-	return nil
 end
 
---- @param win integer|nil
---- @return integer
-local function get_signcolumn_width(win)
-	local sc
-	if win and vim.api.nvim_win_is_valid(win) then
-		sc = vim.api.nvim_get_option_value("signcolumn", { win = win })
-	else
-		sc = vim.api.nvim_get_option_value("signcolumn", { scope = "global" })
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @return boolean dirty whether the region was edited since the last reload
+local function region_is_dirty(mb, b_info, s_idx)
+	local snapshot = b_info.snapshots and b_info.snapshots[s_idx]
+	local rid = b_info.region_extmark_ids[s_idx]
+	if b_info.pending_regions or not snapshot or not rid then
+		return false
 	end
-
-	if sc == "no" then
-		return 0
+	-- regions no edit touched can't differ from their snapshot
+	local touched = multibufs[mb] and multibufs[mb].touched_marks
+	if touched and not touched[rid] then
+		return false
 	end
-
-	local width_str = sc:match(":(%d+)$")
-	if width_str then
-		return tonumber(width_str)
+	local r_start, r_end = get_extmark_range(mb, rid)
+	if not r_start then
+		return false
 	end
-
-	return 1
+	return not vim.deep_equal(vim.api.nvim_buf_get_lines(mb, r_start, r_end, true), snapshot)
 end
 
---- Generates 2-digit sign strings for line numbers
---- @param line_num integer
---- @param width_columns integer
---- @return string[]
-local function get_line_number_signs(line_num, width_columns)
-	local str = tostring(line_num)
-	local width_cells = width_columns * 2
-	if #str < width_cells then
-		str = str .. " "
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @return MultibufRegionStatus
+local function get_region_status(mb, b_info, s_idx)
+	local meta = b_info.region_meta[s_idx]
+	local edited = region_is_dirty(mb, b_info, s_idx)
+	return {
+		clean = not edited,
+		edited = edited,
+		written = not edited and meta ~= nil and meta.written == true,
+		conflict = b_info.conflicts ~= nil and b_info.conflicts[s_idx] == true,
+		readonly = b_info.readonly == true or (meta ~= nil and meta.readonly == true),
+		hidden = b_info.elisions ~= nil and b_info.elisions[s_idx] ~= nil,
+	}
+end
+
+--- Emits |MultibufRegionStatusChanged| for the regions whose status flags
+--- changed. Only regions overlapping the rows {first} to {last} are checked
+--- when given and nothing is checked without listeners.
+--- @param mb integer
+--- @param first integer|nil
+--- @param last integer|nil
+local function update_region_status(mb, first, last)
+	local info = multibufs[mb]
+	if
+		not info
+		or #vim.api.nvim_get_autocmds({ event = "User", pattern = "MultibufRegionStatusChanged" }) == 0
+	then
+		return
 	end
 
-	local result = {}
-	for i = #str, 1, -2 do
-		local start = math.max(1, i - 1)
-		local chunk = str:sub(start, i)
-		if #chunk == 1 then
-			chunk = " " .. chunk
+	for _, b in ipairs(info.bufs) do
+		for s_idx, rid in ipairs(b.region_extmark_ids) do
+			local meta = b.region_meta[s_idx]
+			local r_start, r_end = get_extmark_range(mb, rid)
+			if meta and r_start and (not first or (r_start <= last and r_end >= first)) then
+				local status = get_region_status(mb, b, s_idx)
+				if status.edited then
+					meta.written = nil
+				end
+				local key = table.concat({
+					tostring(status.edited),
+					tostring(status.written),
+					tostring(status.conflict),
+					tostring(status.readonly),
+					tostring(status.hidden),
+				}, ",")
+				-- the first status of a region isn't a change
+				local changed = meta.status_key ~= nil and meta.status_key ~= key
+				meta.status_key = key
+				if changed then
+					vim.api.nvim_exec_autocmds("User", {
+						pattern = "MultibufRegionStatusChanged",
+						data = { buf = mb, region_id = meta.id, status = status },
+					})
+				end
+			end
 		end
-		table.insert(result, 1, chunk)
 	end
-	return result
 end
 
+-- edits are settled for this long before the dirty signs are redrawn
+local DIRTY_SIGN_DEBOUNCE_MS = 150
+
+--- Marks the first line of every region edited since the last reload or
+--- write with the `dirty_sign`.
 --- @param mb integer
---- @param line integer
---- @return integer|nil b_idx, integer|nil s_idx
-local function find_slice_index_at_line(mb, line)
+local function render_dirty_signs(mb)
 	local info = multibufs[mb]
-	if not info then
-		return nil, nil
+	if not info or not vim.api.nvim_buf_is_valid(mb) then
+		return
 	end
-	local marks = vim.api.nvim_buf_get_extmarks(
-		mb,
-		M.multibuf__ns,
-		{ line, 0 },
-		{ line, -1 },
-		{ details = true, overlap = true }
-	)
-	for _, m in ipairs(marks) do
-		local m_id, m_row, _, m_details = unpack(m)
-		local m_end_row = m_details.end_row
-		if m_end_row and line >= m_row and line < m_end_row then
-			for b_idx, b in ipairs(info.bufs) do
-				for s_idx, rid in ipairs(b.region_extmark_ids) do
-					if m_id == rid then
-						return b_idx, s_idx
-					end
-				end
+	vim.api.nvim_buf_clear_namespace(mb, M.multibuf_dirty_ns, 0, -1)
+	if not M.user_opts.dirty_signs or not vim.bo[mb].modified then
+		return
+	end
+
+	local sign = {
+		sign_text = M.user_opts.dirty_sign,
+		sign_hl_group = M.user_opts.dirty_sign_hl,
+		-- ahead of the line number signs
+		priority = 2000,
+	}
+	for _, b_info in ipairs(info.bufs) do
+		for s_idx, rid in ipairs(b_info.region_extmark_ids) do
+			if region_is_dirty(mb, b_info, s_idx) then
+				local r_start = get_extmark_range(mb, rid)
+				vim.api.nvim_buf_set_extmark(mb, M.multibuf_dirty_ns, r_start, 0, sign)
 			end
 		end
 	end
-	return nil, nil
 end
 
---- @return string[]
-local function create_multibuf_header()
-	return { " ─────── " }
+--- @param mb integer
+local function schedule_dirty_signs(mb)
+	local info = multibufs[mb]
+	if not info or not M.user_opts.dirty_signs then
+		return
+	end
+	info.dirty_tick = (info.dirty_tick or 0) + 1
+	local tick = info.dirty_tick
+	vim.defer_fn(function()
+		if info.dirty_tick == tick then
+			render_dirty_signs(mb)
+		end
+	end, DIRTY_SIGN_DEBOUNCE_MS)
 end
 
---- @param s string
---- @param tabstop integer
---- @return string
-local function expand_tabs(s, tabstop)
-	local result = ""
-	local col = 0
-	for i = 1, #s do
-		local char = s:sub(i, i)
-		if char == "\t" then
-			local spaces = tabstop - (col % tabstop)
-			result = result .. string.rep(" ", spaces)
-			col = col + spaces
-		else
-			result = result .. char
-			col = col + 1
-		end
+--- Edits in the multibuffer win over changes to the source made before they
+--- were written. When a source changes while its regions have unwritten
+--- edits the sync is queued until the next write instead of dropping them.
+--- @param mb integer
+--- @param source_buf integer
+--- @return boolean queued
+local function queue_source_sync(mb, source_buf)
+	local info = multibufs[mb]
+	if not info or not vim.api.nvim_buf_is_valid(mb) or not vim.bo[mb].modified then
+		return false
 	end
-	return result
-end
 
---- @param buf_info MultibufBufInfo
---- @return any[]
-local function render_multibuf_title(buf_info)
-	if buf_info.title then
-		local buf_title = buf_info.title
-		if type(buf_title) == "table" then
-			return buf_title
-		elseif type(buf_title) == "function" then
-			local success, lines_or_error = pcall(buf_title, buf_info.buf)
-			if success then
-				return lines_or_error
+	local queued = false
+	for _, b_info in ipairs(info.bufs) do
+		if b_info.buf == source_buf then
+			for s_idx = 1, #b_info.region_extmark_ids do
+				if region_is_dirty(mb, b_info, s_idx) then
+					b_info.conflicts = b_info.conflicts or {}
+					b_info.conflicts[s_idx] = true
+					queued = true
+				end
 			end
-			vim.notify(lines_or_error, vim.log.levels.ERROR)
-		else
-			vim.notify("bad opt title type " .. type(buf_title), vim.log.levels.ERROR)
 		end
 	end
 
-	if M.user_opts.render_multibuf_title then
-		local success, lines_or_error = pcall(M.user_opts.render_multibuf_title, buf_info.buf)
-		if success then
-			return lines_or_error
-		end
-		vim.notify(lines_or_error, vim.log.levels.ERROR)
+	if queued then
+		info.pending_sources = info.pending_sources or {}
+		info.pending_sources[source_buf] = true
+		update_region_status(mb)
 	end
-
-	return M.default_render_multibuf_title(buf_info.buf)
+	return queued
 end
 
---- @param opts multibuffer.RenderExpandLinesOptions
---- @return any[]
-local function render_expand_lines(opts)
-	if M.user_opts.render_expand_lines then
-		local success, lines_or_error = pcall(M.user_opts.render_expand_lines, opts)
-		if success then
-			return lines_or_error
+--- Whether every region of {source_buf} in the multibuffer was rendered at
+--- the current |b:changedtick| of the source, so a reload wouldn't change it.
+--- @param mb integer
+--- @param source_buf integer
+--- @param written boolean the source was just written
+--- @return boolean
+local function source_is_current(mb, source_buf, written)
+	local info = multibufs[mb]
+	if not info or not vim.api.nvim_buf_is_valid(source_buf) then
+		return false
+	end
+
+	local tick = vim.api.nvim_buf_get_changedtick(source_buf)
+	local found = false
+	for _, b_info in ipairs(info.bufs) do
+		if b_info.buf == source_buf then
+			-- writing a modified buffer resets 'modified', which counts as a change
+			if written and b_info.changedtick == tick - 1 and not vim.bo[source_buf].modified then
+				b_info.changedtick = tick
+			end
+			if b_info.pending_regions or b_info.changedtick ~= tick then
+				return false
+			end
+			found = true
 		end
-		vim.notify(lines_or_error, vim.log.levels.ERROR)
 	end
-	return M.default_render_expand_lines(opts)
+	return found
 end
 
--- ──────── Structural Rendering ────────
+--- @param chain integer[] multibuffers in the order their syncs led to each other
+local function report_sync_cycle(chain)
+	local names = {}
+	for _, buf in ipairs(chain) do
+		table.insert(names, tostring(buf))
+	end
+	vim.notify(
+		string.format(
+			"multibuffer: stopped syncing the cycle %s -> %s, a multibuffer shows itself through its sources",
+			table.concat(names, " -> "),
+			names[1]
+		),
+		vim.log.levels.WARN
+	)
+end
 
+--- Bring the regions of {source_buf} in {multibuf} up to date, unless syncing
+--- is suspended or they already are.
 --- @param multibuf integer
---- @param target_row integer
---- @param source_row integer
---- @param width integer?
---- @param special_sign string?
---- @param line_nr_hl string
---- @param expander_hl string
-local function place_line_number_signs(multibuf, target_row, source_row, width, special_sign, line_nr_hl, expander_hl)
-	local signs = get_line_number_signs(source_row + 1, width or 1)
-	if width then
-		while #signs < width do
-			table.insert(signs, 1, "  ")
-		end
+--- @param source_buf integer
+--- @param written boolean the source was just written
+--- @return boolean reloaded
+local function sync_source(multibuf, source_buf, written)
+	local info = multibufs[multibuf]
+	if info and ((info.suspended or 0) > 0 or suspended_all > 0) then
+		info.suspended_sources = info.suspended_sources or {}
+		info.suspended_sources[source_buf] = true
+		return false
 	end
-
-	local start_idx = 1
-	if special_sign and width and width > 0 then
-		vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, target_row, 0, {
-			sign_text = special_sign,
-			sign_hl_group = expander_hl,
-			cursorline_hl_group = "MultibufExpanderCursorLine",
-			priority = 1000,
-		})
-		start_idx = 2
+	if source_is_current(multibuf, source_buf, written) then
+		return false
 	end
-
-	for i = start_idx, #signs do
-		vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, target_row, 0, {
-			sign_text = signs[i],
-			sign_hl_group = line_nr_hl,
-			cursorline_hl_group = "MultibufLineNrCursorLine",
-			priority = 100 - i,
-		})
+	reanchor_source_regions(multibuf, source_buf)
+	if queue_source_sync(multibuf, source_buf) then
+		return false
 	end
+	M.multibuf_reload(multibuf, { source = source_buf })
+	return true
 end
 
+--- Sync the sources whose |b:changedtick| moved without their `sync_events`
+--- firing, e.g. edits made with 'eventignore' set or from a script.
 --- @param multibuf integer
---- @param target_row integer
---- @param opts multibuffer.RenderExpandLinesOptions
-local function place_expander(multibuf, target_row, opts)
-	if opts.count <= 0 then
+local function sync_stale_sources(multibuf)
+	local info = multibufs[multibuf]
+	if not info then
 		return
 	end
-
-	vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, target_row, 0, {
-		virt_lines = render_expand_lines(opts),
-		virt_lines_above = opts.expand_direction ~= "below",
-		virt_lines_leftcol = true,
-		priority = 20001,
-	})
+	local stale = {}
+	for _, b_info in ipairs(info.bufs) do
+		local buf = b_info.buf
+		if
+			not b_info.pending_regions
+			and b_info.changedtick
+			and vim.api.nvim_buf_is_valid(buf)
+			and vim.api.nvim_buf_get_changedtick(buf) ~= b_info.changedtick
+		then
+			list_insert_unique(stale, buf)
+		end
+	end
+	-- a reload can drop entries, don't sync while going through them
+	for _, buf in ipairs(stale) do
+		if multibufs[multibuf] then
+			sync_source(multibuf, buf, false)
+		end
+	end
 end
 
--- ──────── Highlight Projection (Live UI Mirroring) ────────
+--- @param args table
+local function multibuf_buf_changed(args)
+	local listener_info = buf_listeners[args.buf]
+	if listener_info then
+		local written = args.event:find("Write") ~= nil
+		-- a multibuffer shown by other multibuffers passes on the syncs that led
+		-- to its last reload, unless it was edited since, so cycles stop
+		local source_info = multibufs[args.buf]
+		local chain = { args.buf }
+		local source_chain = source_info and source_info.sync_chain
+		if source_chain and source_chain.tick == vim.api.nvim_buf_get_changedtick(args.buf) then
+			chain = vim.list_extend(vim.list_slice(source_chain.bufs), chain)
+		end
 
---- Projects highlights from source to multibuffer using ephemeral extmarks.
---- @param multibuf integer
---- @param source_buf integer
---- @param s_start integer 0-indexed start line in source
---- @param s_end integer 0-indexed end line in source
---- @param target_start integer 0-indexed start line in multibuffer
-local function project_highlights(multibuf, source_buf, s_start, s_end, target_start)
-	if not vim.api.nvim_buf_is_valid(source_buf) or not vim.api.nvim_buf_is_valid(multibuf) then
-		return
+		for _, multibuf in ipairs(listener_info.multibufs) do
+			local info = multibufs[multibuf]
+			if source_info and vim.tbl_contains(chain, multibuf) then
+				report_sync_cycle(chain)
+				goto continue
+			end
+			local reloaded = sync_source(multibuf, args.buf, written)
+			if reloaded and info and source_info and vim.api.nvim_buf_is_valid(multibuf) then
+				info.sync_chain = { bufs = chain, tick = vim.api.nvim_buf_get_changedtick(multibuf) }
+			end
+			::continue::
+		end
 	end
+end
 
-	-- 1. Project Treesitter Highlights
-	local ft = vim.api.nvim_get_option_value("filetype", { buf = source_buf })
-	local lang = vim.treesitter.language.get_lang(ft)
+--- @param buf integer source buffer
+--- @return integer autocmd_id
+local function create_sync_autocmd(buf)
+	return vim.api.nvim_create_autocmd(M.user_opts.sync_events, {
+		buffer = buf,
+		callback = multibuf_buf_changed,
+	})
+end
 
-	if lang and pcall(vim.treesitter.language.add, lang) then
-		pcall(function()
-			-- Use { error = false } to prevent hard crashes
-			local parser = vim.treesitter.get_parser(source_buf, lang, { error = false })
-			if not parser then
+--- Changes made where autocmds don't fire, e.g. by other RPC clients, are
+--- caught by comparing the |b:changedtick| of the source on a timer.
+--- @param buf integer source buffer
+--- @return uv.uv_timer_t|nil timer nil unless `sync_poll_ms` is set
+local function create_sync_poll(buf)
+	local interval = M.user_opts.sync_poll_ms
+	if not interval or interval == 0 then
+		return nil
+	end
+	local timer = assert(vim.uv.new_timer())
+	local tick = vim.api.nvim_buf_get_changedtick(buf)
+	timer:start(
+		interval,
+		interval,
+		vim.schedule_wrap(function()
+			if timer:is_closing() or not vim.api.nvim_buf_is_valid(buf) then
 				return
 			end
+			local current = vim.api.nvim_buf_get_changedtick(buf)
+			if current ~= tick then
+				tick = current
+				multibuf_buf_changed({ buf = buf, event = "Poll" })
+			end
+		end)
+	)
+	return timer
+end
 
-			local line_count = vim.api.nvim_buf_line_count(source_buf)
-			local safe_s_start = math.max(0, math.min(s_start, line_count - 1))
-			local safe_s_end = math.max(safe_s_start, math.min(s_end, line_count))
-
-			parser:parse({ safe_s_start, safe_s_end })
-			parser:for_each_tree(function(tstree, tree)
-				local tlang = tree:lang()
-				local query = vim.treesitter.query.get(tlang, "highlights")
-				if not query then
-					return
-				end
+--- @param listener_info MultibufBufListener
+local function stop_buf_listener(listener_info)
+	pcall(vim.api.nvim_del_autocmd, listener_info.change_autocmd_id)
+	local timer = listener_info.poll_timer
+	if timer and not timer:is_closing() then
+		timer:stop()
+		timer:close()
+	end
+	listener_info.poll_timer = nil
+end
 
-				-- Cache multibuffer lines for column clamping (performance)
-				local mb_lines =
-					vim.api.nvim_buf_get_lines(multibuf, target_start, target_start + (s_end - s_start), false)
+--- Source buffers outlive the multibuffers showing them, so the extmarks of
+--- removed regions have to go or they pile up over a session.
+--- @param source_buf integer
+--- @param ns_id integer
+--- @param ext_id integer|nil
+local function remove_source_extmark(source_buf, ns_id, ext_id)
+	if ext_id and vim.api.nvim_buf_is_valid(source_buf) then
+		pcall(vim.api.nvim_buf_del_extmark, source_buf, ns_id, ext_id)
+	end
+end
 
-				for id, node, metadata in query:iter_captures(tstree:root(), source_buf, safe_s_start, safe_s_end) do
-					local sr, sc, er, ec = node:range()
+--- @param b_info MultibufBufInfo
+local function remove_source_extmarks(b_info)
+	for _, sid in ipairs(b_info.source_extmark_ids) do
+		remove_source_extmark(b_info.buf, M.multibuf__ns, sid)
+	end
+	b_info.source_extmark_ids = {}
+end
 
-					-- Map coordinates to multibuffer
-					local tr = target_start + (sr - s_start)
-					local ter = target_start + (er - s_start)
+--- @param b_info MultibufBufInfo
+local function merge_buffer_regions(b_info)
+	local buf = b_info.buf
+	if b_info.pending_regions then
+		local regions = {}
+		for i, region in ipairs(b_info.pending_regions) do
+			table.insert(regions, { region = region, meta = b_info.region_meta[i] })
+		end
+		if #regions <= 1 then
+			return
+		end
 
-					-- Safety: Ensure we don't project outside the target slice or buffer
-					if tr >= target_start then
-						-- Column Clamping: Neovim errors if end_col > line_length
-						local line_idx = tr - target_start
-						local target_line = mb_lines[line_idx + 1] or ""
-						local max_col = #target_line
+		table.sort(regions, function(a, b)
+			return a.region.start_row < b.region.start_row
+		end)
 
-						local safe_sc = math.min(sc, max_col)
-						local safe_ec = ec and math.min(ec, max_col)
+		-- merged regions keep the metadata of the first region
+		local merged = {}
+		local current = regions[1]
 
-						pcall(vim.api.nvim_buf_set_extmark, multibuf, M.multibuf_hl_ns, tr, safe_sc, {
-							end_row = ter,
-							end_col = safe_ec,
-							hl_group = "@" .. query.captures[id] .. "." .. tlang,
-							priority = tonumber(metadata.priority) or 100,
-							ephemeral = true,
-						})
-					end
+		for i = 2, #regions do
+			local next_r = regions[i]
+			if next_r.region.start_row <= current.region.end_row + 1 then
+				current.region.end_row = math.max(current.region.end_row, next_r.region.end_row)
+				merge_region_meta(current.meta, next_r.meta, buf)
+			else
+				table.insert(merged, current)
+				current = next_r
+			end
+		end
+		table.insert(merged, current)
+		b_info.pending_regions = {}
+		b_info.region_meta = {}
+		for _, r in ipairs(merged) do
+			table.insert(b_info.pending_regions, r.region)
+			table.insert(b_info.region_meta, r.meta)
+		end
+		b_info.region_extmark_ids = {}
+	else
+		local ranges = {}
+		for i, sid in ipairs(b_info.source_extmark_ids) do
+			local s, e = get_extmark_range(buf, sid)
+			if s then
+				table.insert(ranges, { s = s, e = e, meta = b_info.region_meta[i] })
+			end
+		end
+
+		-- ranges whose source extmark is gone are dropped below
+		if #ranges <= 1 and #ranges == #b_info.source_extmark_ids then
+			return
+		end
+
+		table.sort(ranges, function(a, b)
+			return a.s < b.s
+		end)
+
+		local merged = {}
+		local current = ranges[1]
+
+		for i = 2, #ranges do
+			local next_r = ranges[i]
+			if next_r.s <= current.e then
+				current.e = math.max(current.e, next_r.e)
+				merge_region_meta(current.meta, next_r.meta, buf)
+			else
+				table.insert(merged, current)
+				current = next_r
+			end
+		end
+		table.insert(merged, current)
+
+		-- Update extmarks
+		remove_source_extmarks(b_info)
+		b_info.region_extmark_ids = {}
+		b_info.region_meta = {}
+		for _, r in ipairs(merged) do
+			table.insert(b_info.region_meta, r.meta)
+			table.insert(
+				b_info.source_extmark_ids,
+				vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, r.s, 0, {
+					end_row = r.e,
+					end_right_gravity = true,
+				})
+			)
+		end
+	end
+end
+
+--- Merges the regions of a loaded source whose ranges came to touch or
+--- overlap through edits in the source, e.g. after deleting the lines that
+--- separated them. The earlier region keeps its id.
+--- @param b_info MultibufBufInfo
+--- @return { id: integer, into: integer }[] merged regions and the region they were merged into
+local function merge_touching_regions(b_info)
+	local ranges = {}
+	for i, sid in ipairs(b_info.source_extmark_ids) do
+		local s, e = get_extmark_range(b_info.buf, sid)
+		local meta = b_info.region_meta[i]
+		-- lost extmarks are left to multibuf_reconcile
+		if not s or not meta then
+			return {}
+		end
+		table.insert(ranges, { s = s, e = e, id = meta.id })
+	end
+	table.sort(ranges, function(a, b)
+		return a.s < b.s
+	end)
+
+	-- same grouping as merge_buffer_regions
+	local merged = {}
+	local current = ranges[1]
+	for i = 2, #ranges do
+		local next_r = ranges[i]
+		if next_r.s <= current.e then
+			current.e = math.max(current.e, next_r.e)
+			table.insert(merged, { id = next_r.id, into = current.id })
+		else
+			current = next_r
+		end
+	end
+
+	if #merged > 0 then
+		merge_buffer_regions(b_info)
+	end
+	return merged
+end
+
+--- @param buf integer
+--- @return boolean
+local function is_help_buf(buf)
+	return vim.bo[buf].buftype == "help" or vim.bo[buf].filetype == "help"
+end
+
+--- Help multibuffers get the help filetype so conceal and syntax apply.
+--- @param mb integer
+local function update_multibuf_filetype(mb)
+	local info = multibufs[mb]
+	local all_help = #info.bufs > 0
+	for _, b in ipairs(info.bufs) do
+		if not vim.api.nvim_buf_is_loaded(b.buf) or not is_help_buf(b.buf) then
+			all_help = false
+			break
+		end
+	end
+
+	local filetype = all_help and "help" or "multibuffer"
+	if vim.bo[mb].filetype ~= filetype then
+		vim.api.nvim_set_option_value("filetype", filetype, { buf = mb })
+	end
+end
+
+--- Reload {mb} when {buf} changes.
+--- @param mb integer
+--- @param buf integer source buffer
+local function listen_to_source(mb, buf)
+	if not buf_listeners[buf] then
+		buf_listeners[buf] = {
+			change_autocmd_id = create_sync_autocmd(buf),
+			poll_timer = create_sync_poll(buf),
+			multibufs = { mb },
+		}
+	else
+		list_insert_unique(buf_listeners[buf].multibufs, mb)
+	end
+end
+
+--- Stop reloading {mb} when {buf} changes once none of its entries show
+--- {buf} anymore.
+--- @param mb integer
+--- @param info MultibufInfo
+--- @param buf integer source buffer
+local function release_source(mb, info, buf)
+	for _, b_info in ipairs(info.bufs) do
+		if b_info.buf == buf then
+			return
+		end
+	end
+	local listener_info = buf_listeners[buf]
+	if listener_info then
+		list_remove(listener_info.multibufs, mb)
+		if #listener_info.multibufs == 0 then
+			stop_buf_listener(listener_info)
+			buf_listeners[buf] = nil
+		end
+	end
+end
+
+--- @param mb integer
+--- @param buf_info MultibufBufInfo
+local function load_source_buf(mb, buf_info)
+	local buf = buf_info.buf
+	if vim.api.nvim_buf_is_loaded(buf) and #buf_info.source_extmark_ids > 0 then
+		buf_info.loading = false
+		return false
+	end
+
+	vim.fn.bufload(buf)
+	buf_info.readonly = is_help_buf(buf) or not vim.bo[buf].modifiable
+
+	local line_count = vim.api.nvim_buf_line_count(buf)
+	local regions = buf_info.pending_regions or {}
+	buf_info.source_extmark_ids = {}
+
+	for i, region in ipairs(regions) do
+		table.insert(
+			buf_info.source_extmark_ids,
+			vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, clamp(region.start_row, 0, line_count - 1), 0, {
+				end_row = clamp(region.end_row + 1, 0, line_count),
+				end_right_gravity = true,
+			})
+		)
+		-- context past the end of the source isn't shown
+		local meta = buf_info.region_meta[i]
+		local context = meta and meta.context
+		if context and region.end_row + 1 > line_count then
+			context.after = math.max(0, context.after - (region.end_row + 1 - line_count))
+		end
+		if meta and meta.span and not meta.span.block then
+			local row = clamp(region.start_row, 0, line_count - 1)
+			local line = vim.api.nvim_buf_get_lines(buf, row, row + 1, true)[1]
+			place_span_mark(buf, meta, row, get_block_cols(line, meta.span))
+		end
+	end
+	buf_info.pending_regions = nil
+	buf_info.loading = false
+
+	listen_to_source(mb, buf)
+	return true
+end
+
+--- @param mb integer
+local function process_pending_adds(mb)
+	local pending = pending_adds[mb]
+	if not pending or #pending == 0 then
+		pending_adds[mb] = nil
+		return
+	end
+
+	local info = multibufs[mb]
+	if not info then
+		pending_adds[mb] = nil
+		return
+	end
+
+	-- For visibility-based loading, we just add them as "unloaded" entries
+	for _, opts in ipairs(pending) do
+		local b_info = {
+			buf = opts.buf,
+			source_extmark_ids = {},
+			region_extmark_ids = {},
+			virt_expand_extmark_ids = {},
+			pending_regions = {},
+			region_meta = {},
+			title = opts.title,
+			id = opts.id,
+		}
+		for _, region in ipairs(opts.regions) do
+			local meta = new_region_meta(region)
+			local context = meta.context or { before = 0, after = 0 }
+			table.insert(b_info.pending_regions, {
+				start_row = region.start_row - context.before,
+				end_row = region.end_row + context.after,
+			})
+			table.insert(b_info.region_meta, meta)
+		end
+		merge_buffer_regions(b_info)
+		table.insert(info.bufs, b_info)
+	end
+
+	pending_adds[mb] = nil
+	M.multibuf_reload(mb)
+end
+
+--- @param buf integer
+--- @return integer|nil
+local function get_buf_win(buf)
+	local cur_win = vim.api.nvim_get_current_win()
+	if vim.api.nvim_win_get_buf(cur_win) == buf then
+		return cur_win
+	end
+	for _, win in ipairs(vim.api.nvim_list_wins()) do
+		if vim.api.nvim_win_is_valid(win) and vim.api.nvim_win_get_buf(win) == buf then
+			return win
+		end
+	end
+	return nil
+end
+
+--- @param win integer|nil
+--- @return string
+local function get_signcolumn_option(win)
+	if win and vim.api.nvim_win_is_valid(win) then
+		return vim.api.nvim_get_option_value("signcolumn", { win = win })
+	end
+	return vim.api.nvim_get_option_value("signcolumn", { scope = "global" })
+end
+
+--- @param win integer|nil
+--- @return integer
+local function get_signcolumn_width(win)
+	local sc = get_signcolumn_option(win)
+
+	if sc == "no" then
+		return 0
+	end
+
+	local width_str = sc:match(":(%d+)$")
+	if width_str then
+		return tonumber(width_str)
+	end
+
+	return 1
+end
+
+--- Generates 2-digit sign strings for line numbers
+--- @param line_num integer
+--- @param width_columns integer
+--- @return string[]
+local function get_line_number_signs(line_num, width_columns)
+	local str = tostring(line_num)
+	local width_cells = width_columns * 2
+	if #str < width_cells then
+		str = str .. " "
+	end
+
+	local result = {}
+	for i = #str, 1, -2 do
+		local start = math.max(1, i - 1)
+		local chunk = str:sub(start, i)
+		if #chunk == 1 then
+			chunk = " " .. chunk
+		end
+		table.insert(result, 1, chunk)
+	end
+	return result
+end
+
+--- Sign columns taken by the line number signs of {line_num} plus the
+--- expander sign in front of them.
+--- @param line_num integer
+--- @return integer
+local function get_line_number_width(line_num)
+	return #get_line_number_signs(line_num, 1) + 1
+end
+
+--- Largest 1-indexed source line number shown in the multibuffer.
+--- @param info MultibufInfo
+--- @return integer
+local function get_max_source_line(info)
+	local max_line = 1
+	for _, buf_info in ipairs(info.bufs) do
+		for _, r in ipairs(buf_info.pending_regions or {}) do
+			max_line = math.max(max_line, r.end_row + 1)
+		end
+		if not buf_info.pending_regions then
+			for _, sid in ipairs(buf_info.source_extmark_ids) do
+				local _, e = get_extmark_range(buf_info.buf, sid)
+				max_line = math.max(max_line, e or 0)
+			end
+		end
+	end
+	return max_line
+end
+
+--- @param mb integer
+--- @param line integer
+--- @return integer|nil b_idx, integer|nil s_idx
+local function find_slice_index_at_line(mb, line)
+	local info = multibufs[mb]
+	if not info then
+		return nil, nil
+	end
+	local marks = vim.api.nvim_buf_get_extmarks(
+		mb,
+		M.multibuf__ns,
+		{ line, 0 },
+		{ line, -1 },
+		{ details = true, overlap = true }
+	)
+	for _, m in ipairs(marks) do
+		local m_id, m_row, _, m_details = unpack(m)
+		local m_end_row = m_details.end_row
+		if m_end_row and line >= m_row and line < m_end_row then
+			for b_idx, b in ipairs(info.bufs) do
+				for s_idx, rid in ipairs(b.region_extmark_ids) do
+					if m_id == rid then
+						return b_idx, s_idx
+					end
+				end
+			end
+		end
+	end
+	return nil, nil
+end
+
+--- @param info MultibufInfo
+--- @param region_id integer
+--- @return integer|nil b_idx, integer|nil s_idx
+local function find_region_by_id(info, region_id)
+	for b_idx, b in ipairs(info.bufs) do
+		for s_idx, meta in ipairs(b.region_meta) do
+			if meta.id == region_id then
+				return b_idx, s_idx
+			end
+		end
+	end
+	return nil, nil
+end
+
+--- @generic T
+--- @param map table<integer, T>|nil
+--- @param idx integer
+--- @return table<integer, T>|nil map without {idx}, later keys moved down by one
+local function remove_index(map, idx)
+	if not map then
+		return nil
+	end
+	local shifted = {}
+	for i, v in pairs(map) do
+		if i < idx then
+			shifted[i] = v
+		elseif i > idx then
+			shifted[i - 1] = v
+		end
+	end
+	return shifted
+end
+
+--- Stop tracking a region, the multibuffer lines are left as they are. Keeps
+--- the per region lists of the source aligned so unwritten edits of the other
+--- regions can be carried over by the next reload.
+--- @param mb integer
+--- @param info MultibufInfo
+--- @param b_idx integer
+--- @param s_idx integer
+local function drop_region(mb, info, b_idx, s_idx)
+	local b_info = info.bufs[b_idx]
+	local remaining
+	if b_info.pending_regions then
+		table.remove(b_info.pending_regions, s_idx)
+		remaining = #b_info.pending_regions
+	else
+		local elision = b_info.elisions and b_info.elisions[s_idx]
+		if elision and elision.mark_id then
+			pcall(vim.api.nvim_buf_del_extmark, mb, M.multibuf__ns, elision.mark_id)
+		end
+		if b_info.region_extmark_ids[s_idx] then
+			pcall(vim.api.nvim_buf_del_extmark, mb, M.multibuf__ns, b_info.region_extmark_ids[s_idx])
+			table.remove(b_info.region_extmark_ids, s_idx)
+		end
+		remove_source_extmark(b_info.buf, M.multibuf__ns, b_info.source_extmark_ids[s_idx])
+		table.remove(b_info.source_extmark_ids, s_idx)
+		if b_info.snapshots then
+			table.remove(b_info.snapshots, s_idx)
+		end
+		if b_info.fingerprints then
+			table.remove(b_info.fingerprints, s_idx)
+		end
+		if b_info.bases then
+			table.remove(b_info.bases, s_idx)
+		end
+		b_info.elisions = remove_index(b_info.elisions, s_idx)
+		b_info.conflicts = remove_index(b_info.conflicts, s_idx)
+		remaining = #b_info.source_extmark_ids
+	end
+	if b_info.region_meta[s_idx] then
+		clear_span(b_info.buf, b_info.region_meta[s_idx])
+	end
+	table.remove(b_info.region_meta, s_idx)
+
+	if remaining == 0 then
+		table.remove(info.bufs, b_idx)
+	end
+end
+
+--- Regions whose lines were all deleted in the multibuffer, last first so
+--- they can be dropped in order.
+--- @param mb integer
+--- @param info MultibufInfo
+--- @return { b_idx: integer, s_idx: integer }[]
+local function find_deleted_regions(mb, info)
+	local deleted = {}
+	for b_idx, b_info in ipairs(info.bufs) do
+		if not b_info.pending_regions then
+			for s_idx, rid in ipairs(b_info.region_extmark_ids) do
+				local r_start, r_end = get_extmark_range(mb, rid)
+				local snapshot = b_info.snapshots and b_info.snapshots[s_idx]
+				if r_start and r_start == r_end and snapshot and #snapshot > 0 then
+					table.insert(deleted, 1, { b_idx = b_idx, s_idx = s_idx })
+				end
+			end
+		end
+	end
+	return deleted
+end
+
+--- Look up the state behind a multibuffer handle. Entries whose buffer is no
+--- longer valid are pruned so a stale handle never operates on another buffer.
+--- @param mb any
+--- @return MultibufInfo|nil
+local function resolve_multibuf(mb)
+	if type(mb) ~= "number" then
+		return nil
+	end
+	local info = multibufs[mb]
+	if not info then
+		return nil
+	end
+	if not vim.api.nvim_buf_is_valid(mb) then
+		M.multibuf__wipeout(mb)
+		return nil
+	end
+	return info
+end
+
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @return integer|nil start_row, integer|nil end_row 0-indexed source rows (inclusive)
+local function get_region_rows(b_info, s_idx)
+	if b_info.pending_regions then
+		local region = b_info.pending_regions[s_idx]
+		if region then
+			return region.start_row, region.end_row
+		end
+		return nil, nil
+	end
+
+	local sid = b_info.source_extmark_ids[s_idx]
+	local s, e = nil, nil
+	if sid then
+		s, e = get_extmark_range(b_info.buf, sid)
+	end
+	if not s then
+		return nil, nil
+	end
+	return s, e - 1
+end
+
+--- The source and multibuffer rows a region maps between. The lines hidden by
+--- an elision are left out, so an elided region has two spans.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @return { [1]: integer, [2]: integer, [3]: integer, [4]: integer }[]|nil spans `{ s_start, s_end, r_start, r_end }`
+--- with exclusive ends, nil when the region isn't mapped
+local function get_region_spans(mb, b_info, s_idx)
+	local sid, rid = b_info.source_extmark_ids[s_idx], b_info.region_extmark_ids[s_idx]
+	if b_info.pending_regions or not sid or not rid then
+		return nil
+	end
+	local r_start, r_end = get_extmark_range(mb, rid)
+	local s_start, s_end = get_extmark_range(b_info.buf, sid)
+	if not r_start or not s_start then
+		return nil
+	end
+
+	local elision = b_info.elisions and b_info.elisions[s_idx]
+	local tail_row = get_elision_tail_row(mb, b_info, s_idx)
+	if elision and tail_row then
+		return {
+			{ s_start, s_start + elision.head, r_start, tail_row },
+			{ s_end - elision.tail, s_end, tail_row, r_end },
+		}
+	end
+	return { { s_start, s_end, r_start, r_end } }
+end
+
+--- @param source integer|string buffer handle or file path
+--- @return integer|nil buf
+local function resolve_source_buf(source)
+	if type(source) == "string" then
+		local buf = vim.fn.bufnr(source)
+		return buf ~= -1 and buf or vim.fn.bufadd(source)
+	elseif type(source) == "number" and vim.api.nvim_buf_is_valid(source) then
+		return source
+	end
+	return nil
+end
+
+--- @return string[]
+local function create_multibuf_header()
+	return { " ─────── " }
+end
+
+--- @param s string
+--- @param tabstop integer
+--- @return string
+local function expand_tabs(s, tabstop)
+	local result = ""
+	local col = 0
+	for i = 1, #s do
+		local char = s:sub(i, i)
+		if char == "\t" then
+			local spaces = tabstop - (col % tabstop)
+			result = result .. string.rep(" ", spaces)
+			col = col + spaces
+		else
+			result = result .. char
+			col = col + 1
+		end
+	end
+	return result
+end
+
+--- @param buf_info MultibufBufInfo
+--- @return any[]
+local function render_multibuf_title(buf_info)
+	if buf_info.title then
+		local buf_title = buf_info.title
+		if type(buf_title) == "table" then
+			return buf_title
+		elseif type(buf_title) == "function" then
+			local success, lines_or_error = pcall(buf_title, buf_info.buf)
+			if success then
+				return lines_or_error
+			end
+			vim.notify(lines_or_error, vim.log.levels.ERROR)
+		else
+			vim.notify("bad opt title type " .. type(buf_title), vim.log.levels.ERROR)
+		end
+	end
+
+	if M.user_opts.render_multibuf_title then
+		local success, lines_or_error = pcall(M.user_opts.render_multibuf_title, buf_info.buf)
+		if success then
+			return lines_or_error
+		end
+		vim.notify(lines_or_error, vim.log.levels.ERROR)
+	end
+
+	return M.default_render_multibuf_title(buf_info.buf)
+end
+
+--- Source entries of a multibuffer that get a title.
+--- @param info MultibufInfo
+--- @return table<MultibufBufInfo, boolean>
+local function get_shown_titles(info)
+	-- the source name column replaces the titles
+	local hidden = info.hide_titles or M.user_opts.layout == "columns"
+	local mode = hidden and "never" or info.title_mode or M.user_opts.titles or "always"
+	if mode == "auto" then
+		local sources = {}
+		for _, b_info in ipairs(info.bufs) do
+			list_insert_unique(sources, b_info.buf)
+		end
+		mode = #sources > 1 and "first_per_file" or "never"
+	end
+
+	local shown, seen = {}, {}
+	for _, b_info in ipairs(info.bufs) do
+		if mode == "always" or (mode == "first_per_file" and not seen[b_info.buf]) then
+			shown[b_info] = true
+		end
+		seen[b_info.buf] = true
+	end
+	return shown
+end
+
+--- Place or update the title above the first row of a source entry. Hidden
+--- titles keep an empty mark so showing them again keeps their order with
+--- the other virtual lines of the row.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param row integer
+--- @param shown boolean
+local function place_title(mb, b_info, row, shown)
+	b_info.title_extmark_id = vim.api.nvim_buf_set_extmark(mb, M.multibuf_structure_ns, row, 0, {
+		id = b_info.title_extmark_id,
+		virt_lines = shown and render_multibuf_title(b_info) or {},
+		virt_lines_above = true,
+		virt_lines_leftcol = true,
+		priority = 20001,
+	})
+end
+
+--- Move the titles of {mb} back onto the first row of their entry once an
+--- edit deleted or moved that row, e.g. |dd| on the first line of a region.
+--- Titles of entries whose regions lost all their lines are hidden until the
+--- lines come back.
+--- @param mb integer
+--- @param info MultibufInfo
+local function reanchor_titles(mb, info)
+	local shown = get_shown_titles(info)
+	for _, b_info in ipairs(info.bufs) do
+		if not b_info.title_extmark_id then
+			goto continue
+		end
+		local title =
+			vim.api.nvim_buf_get_extmark_by_id(mb, M.multibuf_structure_ns, b_info.title_extmark_id, { details = true })
+		if not title[1] then
+			goto continue
+		end
+
+		local row, empty = nil, true
+		for _, region_id in ipairs(b_info.region_extmark_ids) do
+			local region = vim.api.nvim_buf_get_extmark_by_id(mb, M.multibuf__ns, region_id, { details = true })
+			if region[1] then
+				row = row or region[1]
+				if (region[3].end_row or region[1]) > region[1] then
+					row, empty = region[1], false
+					break
+				end
+			end
+		end
+
+		local want_shown = shown[b_info] == true and not empty
+		local is_shown = title[3].virt_lines ~= nil and #title[3].virt_lines > 0
+		if row and (row ~= title[1] or want_shown ~= is_shown) then
+			place_title(mb, b_info, row, want_shown)
+		end
+
+		::continue::
+	end
+end
+
+--- @param opts multibuffer.RenderExpandLinesOptions
+--- @return any[]
+local function render_expand_lines(opts)
+	if M.user_opts.render_expand_lines then
+		local success, lines_or_error = pcall(M.user_opts.render_expand_lines, opts)
+		if success then
+			return lines_or_error
+		end
+		vim.notify(lines_or_error, vim.log.levels.ERROR)
+	end
+	return M.default_render_expand_lines(opts)
+end
+
+-- ──────── Structural Rendering ────────
+
+--- Signs of a reload are collected first and placed in one pass at the end.
+--- Signs with the same text, highlight and priority share their extmark
+--- options and the sign chunks of a line number are only computed once.
+--- @class MultibufSignBatch
+--- @field marks { [1]: integer, [2]: table }[] row and extmark options
+--- @field opts table<string, table> shared extmark options
+--- @field numbers table<integer, string[]> padded sign chunks per line number
+
+--- @return MultibufSignBatch
+local function new_sign_batch()
+	return { marks = {}, opts = {}, numbers = {} }
+end
+
+--- @param batch MultibufSignBatch
+--- @param row integer
+--- @param text string
+--- @param hl string
+--- @param cursorline_hl string
+--- @param priority integer
+local function batch_sign(batch, row, text, hl, cursorline_hl, priority)
+	local key = text .. "\0" .. hl .. "\0" .. cursorline_hl .. "\0" .. priority
+	local opts = batch.opts[key]
+	if not opts then
+		opts = { sign_text = text, sign_hl_group = hl, cursorline_hl_group = cursorline_hl, priority = priority }
+		batch.opts[key] = opts
+	end
+	table.insert(batch.marks, { row, opts })
+end
+
+--- @param multibuf integer
+--- @param batch MultibufSignBatch
+local function place_sign_batch(multibuf, batch)
+	for _, mark in ipairs(batch.marks) do
+		vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, mark[1], 0, mark[2])
+	end
+end
+
+--- @param batch MultibufSignBatch
+--- @param target_row integer
+--- @param special_sign string
+--- @param expander_hl string
+local function place_expander_sign(batch, target_row, special_sign, expander_hl)
+	batch_sign(batch, target_row, special_sign, expander_hl, "MultibufExpanderCursorLine", 1000)
+end
+
+--- @param batch MultibufSignBatch
+--- @param target_row integer
+--- @param source_row integer
+--- @param width integer?
+--- @param special_sign string?
+--- @param line_nr_hl string
+--- @param expander_hl string
+local function place_line_number_signs(batch, target_row, source_row, width, special_sign, line_nr_hl, expander_hl)
+	local signs = batch.numbers[source_row]
+	if not signs then
+		signs = get_line_number_signs(source_row + 1, width or 1)
+		if width then
+			while #signs < width do
+				table.insert(signs, 1, "  ")
+			end
+		end
+		batch.numbers[source_row] = signs
+	end
+
+	local start_idx = 1
+	if special_sign and width and width > 0 then
+		place_expander_sign(batch, target_row, special_sign, expander_hl)
+		start_idx = 2
+	end
+
+	for i = start_idx, #signs do
+		batch_sign(batch, target_row, signs[i], line_nr_hl, "MultibufLineNrCursorLine", 100 - i)
+	end
+end
+
+--- Line number as virtual text on {row}, right aligned or in front of the
+--- content for the "virt_text" and "inline" `line_number_mode`. The line
+--- content is left untouched.
+--- @param multibuf integer
+--- @param ns integer
+--- @param row integer
+--- @param chunks [string, string][]
+local function place_line_number_text(multibuf, ns, row, chunks)
+	local inline = M.user_opts.line_number_mode == "inline"
+	local virt_text = vim.deepcopy(chunks)
+	if inline then
+		table.insert(virt_text, { " " })
+	end
+	vim.api.nvim_buf_set_extmark(multibuf, ns, row, 0, {
+		virt_text = virt_text,
+		virt_text_pos = inline and "inline" or "right_align",
+		right_gravity = false,
+		priority = 100,
+	})
+end
+
+local SOURCE_COLUMN_MAX_WIDTH = 30
+
+--- Abbreviated name of a source for the `layout = "columns"` column.
+--- @param buf integer
+--- @return string
+local function get_source_column_name(buf)
+	local name = vim.api.nvim_buf_get_name(buf)
+	if name == "" then
+		return "[No Name]"
+	end
+	name = vim.fn.pathshorten(vim.fn.fnamemodify(name, ":~:."))
+	if vim.fn.strdisplaywidth(name) > SOURCE_COLUMN_MAX_WIDTH then
+		-- keep the end, the file name matters more than its directories
+		local chars = vim.fn.strchars(name)
+		name = "…" .. vim.fn.strcharpart(name, chars - SOURCE_COLUMN_MAX_WIDTH + 1)
+	end
+	return name
+end
+
+--- Place the source name column in front of a row of the `layout =
+--- "columns"` layout. Only the first row of a region shows the name, the
+--- others are padded so the content lines up.
+--- @param multibuf integer
+--- @param row integer
+--- @param name string|nil
+--- @param width integer
+local function place_source_column(multibuf, row, name, width)
+	local text = name or ""
+	vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, row, 0, {
+		virt_text = {
+			{ text .. string.rep(" ", width - vim.fn.strdisplaywidth(text)), "MultibufSourceColumn" },
+			{ " │ ", "MultibufSeparator" },
+		},
+		virt_text_pos = "inline",
+		right_gravity = false,
+		-- left of the "inline" line numbers
+		priority = 90,
+	})
+end
+
+--- @param multibuf integer
+--- @param target_row integer
+--- @param opts multibuffer.RenderExpandLinesOptions
+local function place_expander(multibuf, target_row, opts)
+	if opts.count <= 0 then
+		return
+	end
+
+	vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, target_row, 0, {
+		virt_lines = render_expand_lines(opts),
+		virt_lines_above = opts.expand_direction ~= "below",
+		virt_lines_leftcol = true,
+		priority = 20001,
+	})
+end
+
+--- Maps region extmark ids to their slice and alternating highlight.
+--- @param info MultibufInfo
+--- @return table<integer, { b_info: MultibufBufInfo, slice_idx: integer, is_odd: boolean, region_hl: string }>
+local function build_slice_lookup(info)
+	local slice_lookup = {}
+	local global_slice_idx = 0
+	for _, b_info in ipairs(info.bufs) do
+		local has_content = false
+		if b_info.pending_regions and #b_info.pending_regions > 0 then
+			has_content = true
+		elseif #b_info.source_extmark_ids > 0 then
+			has_content = true
+		end
+		if has_content then
+			for i, reg_id in ipairs(b_info.region_extmark_ids) do
+				global_slice_idx = global_slice_idx + 1
+				local is_odd = (global_slice_idx % 2 ~= 0)
+				local region_hl = is_odd and M.user_opts.region_hl_odd or M.user_opts.region_hl_even
+				if b_info.conflicts and b_info.conflicts[i] then
+					region_hl = "MultibufRegionConflict"
+				end
+				slice_lookup[reg_id] = { b_info = b_info, slice_idx = i, is_odd = is_odd, region_hl = region_hl }
+			end
+		end
+	end
+	return slice_lookup
+end
+
+--- Renders cursor relative line numbers for the visible lines of {win}. Only
+--- the visible range is rendered so this is cheap enough for CursorMoved.
+--- @param multibuf integer
+--- @param win integer|nil
+local function render_relative_line_numbers(multibuf, win)
+	vim.api.nvim_buf_clear_namespace(multibuf, M.multibuf_number_ns, 0, -1)
+
+	local info = multibufs[multibuf]
+	local mode = info and info.number_mode or "absolute"
+	if mode == "absolute" or not win or not vim.api.nvim_win_is_valid(win) then
+		return
+	end
+
+	local as_text = M.user_opts.line_number_mode ~= "signs"
+	local sc_width = get_signcolumn_width(win)
+	if sc_width == 0 and not as_text then
+		return
+	end
+
+	local cursor_row = vim.api.nvim_win_get_cursor(win)[1] - 1
+	local top = vim.fn.line("w0", win) - 1
+	local bot = vim.fn.line("w$", win) - 1
+
+	-- in relative modes only the expander signs are placed during reload
+	local expander_rows = {}
+	local signs =
+		vim.api.nvim_buf_get_extmarks(multibuf, M.multibuf_structure_ns, { top, 0 }, { bot, -1 }, { type = "sign" })
+	for _, sign in ipairs(signs) do
+		expander_rows[sign[2]] = true
+	end
+
+	local slice_lookup = build_slice_lookup(info)
+	local regions = vim.api.nvim_buf_get_extmarks(
+		multibuf,
+		M.multibuf__ns,
+		{ top, 0 },
+		{ bot, -1 },
+		{ details = true, overlap = true }
+	)
+	for _, extmark in ipairs(regions) do
+		local lookup = slice_lookup[extmark[1]]
+		if lookup then
+			local b_info = lookup.b_info
+			local r_start, r_end = extmark[2], extmark[4].end_row or extmark[2]
+			local s_start, s_end = get_region_rows(b_info, lookup.slice_idx)
+
+			local line_nr_hl = lookup.is_odd and "MultibufLineNrOdd" or "MultibufLineNrEven"
+			local dim_hl = lookup.is_odd and "MultibufLineNrDimOdd" or "MultibufLineNrDimEven"
+
+			for row = math.max(top, r_start), math.min(bot, r_end - 1) do
+				local source_row
+				if mode == "both" and s_start and s_end then
+					source_row = region_row_to_source(
+						multibuf,
+						b_info,
+						lookup.slice_idx,
+						row,
+						r_start,
+						r_end,
+						s_start,
+						s_end + 1
+					)
+				end
+
+				if as_text then
+					local format = "%" .. (info.number_text_width or 1) .. "d"
+					local chunks = { { string.format(format, math.abs(row - cursor_row)), line_nr_hl } }
+					if source_row then
+						table.insert(chunks, 1, { string.format(format, source_row + 1) .. " ", dim_hl })
+					end
+					place_line_number_text(multibuf, M.multibuf_number_ns, row, chunks)
+				else
+					local width = expander_rows[row] and sc_width - 1 or sc_width
+					local chunks = {}
+					for _, text in ipairs(get_line_number_signs(math.abs(row - cursor_row), width)) do
+						table.insert(chunks, { text, line_nr_hl })
+					end
+
+					if source_row then
+						local abs_signs = get_line_number_signs(source_row + 1, math.huge)
+						if #chunks + #abs_signs <= width then
+							for i = #abs_signs, 1, -1 do
+								table.insert(chunks, 1, { abs_signs[i], dim_hl })
+							end
+						end
+					end
+
+					while #chunks < width do
+						table.insert(chunks, 1, { "  ", line_nr_hl })
+					end
+
+					for i, chunk in ipairs(chunks) do
+						vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_number_ns, row, 0, {
+							sign_text = chunk[1],
+							sign_hl_group = chunk[2],
+							cursorline_hl_group = "MultibufLineNrCursorLine",
+							priority = 100 - i,
+						})
+					end
+				end
+			end
+		end
+	end
+end
+
+-- ──────── Highlight Projection (Live UI Mirroring) ────────
+
+--- Projects highlights from source to multibuffer using ephemeral extmarks.
+--- With {mirror} the highlights are placed as regular extmarks in
+--- `multibuf_hl_mirror_ns` instead, and only highlight extmarks of the source
+--- are copied.
+--- @param multibuf integer
+--- @param source_buf integer
+--- @param s_start integer 0-indexed start line in source
+--- @param s_end integer 0-indexed end line in source
+--- @param target_start integer 0-indexed start line in multibuffer
+--- @param col_offset integer|nil bytes stripped from the start of the source lines
+--- @param mirror boolean|nil
+local function project_highlights(multibuf, source_buf, s_start, s_end, target_start, col_offset, mirror)
+	col_offset = col_offset or 0
+	local ns = mirror and M.multibuf_hl_mirror_ns or M.multibuf_hl_ns
+	if not vim.api.nvim_buf_is_valid(source_buf) or not vim.api.nvim_buf_is_valid(multibuf) then
+		return
+	end
+
+	-- 1. Project Treesitter Highlights
+	local ft = vim.api.nvim_get_option_value("filetype", { buf = source_buf })
+	local lang = vim.treesitter.language.get_lang(ft)
+
+	if lang and pcall(vim.treesitter.language.add, lang) then
+		pcall(function()
+			-- Use { error = false } to prevent hard crashes
+			local parser = vim.treesitter.get_parser(source_buf, lang, { error = false })
+			if not parser then
+				return
+			end
+
+			local line_count = vim.api.nvim_buf_line_count(source_buf)
+			local safe_s_start = math.max(0, math.min(s_start, line_count - 1))
+			local safe_s_end = math.max(safe_s_start, math.min(s_end, line_count))
+
+			parser:parse({ safe_s_start, safe_s_end })
+			parser:for_each_tree(function(tstree, tree)
+				local tlang = tree:lang()
+				local query = vim.treesitter.query.get(tlang, "highlights")
+				if not query then
+					return
+				end
+
+				-- Cache multibuffer lines for column clamping (performance)
+				local mb_lines =
+					vim.api.nvim_buf_get_lines(multibuf, target_start, target_start + (s_end - s_start), false)
+
+				for id, node, metadata in query:iter_captures(tstree:root(), source_buf, safe_s_start, safe_s_end) do
+					local sr, sc, er, ec = node:range()
+
+					-- Map coordinates to multibuffer
+					local tr = target_start + (sr - s_start)
+					local ter = target_start + (er - s_start)
+
+					-- Safety: Ensure we don't project outside the target slice or buffer
+					if tr >= target_start then
+						-- Column Clamping: Neovim errors if end_col > line_length
+						local line_idx = tr - target_start
+						local target_line = mb_lines[line_idx + 1] or ""
+						local max_col = #target_line
+
+						local safe_sc = math.min(math.max(0, sc - col_offset), max_col)
+						local safe_ec = ec and math.min(math.max(0, ec - col_offset), max_col)
+
+						pcall(vim.api.nvim_buf_set_extmark, multibuf, ns, tr, safe_sc, {
+							end_row = ter,
+							end_col = safe_ec,
+							hl_group = "@" .. query.captures[id] .. "." .. tlang,
+							priority = tonumber(metadata.priority) or 100,
+							ephemeral = not mirror,
+						})
+					end
+				end
+			end)
+		end)
+	end
+
+	-- 2. Project Persistent Extmarks (LSP Diagnostics, Gitsigns, etc.)
+	local persistent = vim.api.nvim_buf_get_extmarks(
+		source_buf,
+		-1,
+		{ s_start, 0 },
+		{ s_end, -1 },
+		{ details = true, type = mirror and "highlight" or nil }
+	)
+	for _, mark in ipairs(persistent) do
+		local _, r, c, d = unpack(mark)
+		if d.ns_id ~= M.multibuf__ns then
+			local tr = target_start + (r - s_start)
+			local ter = d.end_row and (target_start + (d.end_row - s_start))
+
+			-- Only project if it falls within our target range
+			if tr >= target_start then
+				d.id, d.ns_id, d.end_row, d.ephemeral = nil, nil, ter, not mirror
+				if d.end_col then
+					d.end_col = math.max(0, d.end_col - col_offset)
+				end
+				pcall(vim.api.nvim_buf_set_extmark, multibuf, ns, tr, math.max(0, c - col_offset), d)
+			end
+		end
+	end
+end
+
+-- source lines of a region mirrored with `highlight = "mirror"`, the rest is left plain
+local MIRROR_MAX_LINES = 2000
+
+--- Copy the highlights of the sources into {mb} once, for `highlight =
+--- "mirror"`. Redone on every reload, which a source change triggers.
+--- @param mb integer
+local function render_mirrored_highlights(mb)
+	local info = multibufs[mb]
+	vim.api.nvim_buf_clear_namespace(mb, M.multibuf_hl_mirror_ns, 0, -1)
+	if not info or M.user_opts.highlight ~= "mirror" then
+		return
+	end
+	for _, b_info in ipairs(info.bufs) do
+		for s_idx = 1, #b_info.region_extmark_ids do
+			local col_offset = get_col_offset(b_info.region_meta[s_idx])
+			local budget = MIRROR_MAX_LINES
+			for _, span in ipairs(get_region_spans(mb, b_info, s_idx) or {}) do
+				local len = math.min(span[2] - span[1], span[4] - span[3], budget)
+				if len > 0 then
+					project_highlights(mb, b_info.buf, span[1], span[1] + len, span[3], col_offset, true)
+					budget = budget - len
+				end
+			end
+		end
+	end
+end
+
+-- ──────── Fold Mapping ────────
+
+--- Collects foldable ranges from the source treesitter "folds" query,
+--- clipped to the given source span.
+--- @param source_buf integer
+--- @param s_start integer 0-indexed start line in source
+--- @param s_end integer 0-indexed end line in source (exclusive)
+--- @return MultibufRegion[]
+local function get_source_fold_ranges(source_buf, s_start, s_end)
+	local ranges = {}
+	local ft = vim.api.nvim_get_option_value("filetype", { buf = source_buf })
+	local lang = vim.treesitter.language.get_lang(ft)
+	if not lang or not pcall(vim.treesitter.language.add, lang) then
+		return ranges
+	end
+
+	pcall(function()
+		local parser = vim.treesitter.get_parser(source_buf, lang, { error = false })
+		if not parser then
+			return
+		end
+
+		parser:parse({ s_start, s_end })
+		parser:for_each_tree(function(tstree, tree)
+			local query = vim.treesitter.query.get(tree:lang(), "folds")
+			if not query then
+				return
+			end
+
+			for _, node in query:iter_captures(tstree:root(), source_buf, s_start, s_end) do
+				local sr, _, er, ec = node:range()
+				if ec == 0 then
+					er = er - 1
+				end
+				local fs, fe = math.max(sr, s_start), math.min(er, s_end - 1)
+				if fe > fs then
+					table.insert(ranges, { start_row = fs, end_row = fe })
+				end
+			end
+		end)
+	end)
+
+	return ranges
+end
+
+--- @param folds MultibufRegion[] 0-indexed multibuffer fold ranges
+--- @return table<integer, string>
+local function compute_fold_levels(folds)
+	local levels = {}
+	for _, fold in ipairs(folds) do
+		for row = fold.start_row, fold.end_row do
+			levels[row] = (levels[row] or 0) + 1
+		end
+	end
+
+	local exprs = {}
+	for row, level in pairs(levels) do
+		exprs[row] = tostring(level)
+	end
+	for _, fold in ipairs(folds) do
+		exprs[fold.start_row] = ">" .. levels[fold.start_row]
+	end
+	return exprs
+end
+
+-- ──────── User Extmark Preservation ────────
+
+--- @class MultibufCapturedExtmark
+--- @field ns_id integer
+--- @field id integer
+--- @field row integer
+--- @field col integer
+--- @field details table
+--- @field source_buf integer|nil
+--- @field source_row integer|nil
+--- @field source_end_row integer|nil
+
+--- @param ns_id integer
+--- @return boolean
+local function is_plugin_namespace(ns_id)
+	return ns_id == M.multibuf__ns
+		or ns_id == M.multibuf_structure_ns
+		or ns_id == M.multibuf_hl_ns
+		or ns_id == M.multibuf_hl_mirror_ns
+		or ns_id == M.multibuf_number_ns
+		or ns_id == M.multibuf_dirty_ns
+end
+
+--- Captures extmarks placed in the multibuffer by users or other plugins along
+--- with the source position they are on, so they can be put back after the
+--- multibuffer content is rebuilt.
+--- @param multibuf integer
+--- @return MultibufCapturedExtmark[]
+local function capture_user_extmarks(multibuf)
+	local captured = {}
+	local marks = vim.api.nvim_buf_get_extmarks(multibuf, -1, 0, -1, { details = true })
+	for _, mark in ipairs(marks) do
+		local id, row, col, details = unpack(mark)
+		if not is_plugin_namespace(details.ns_id) then
+			local source_buf, source_row = M.multibuf_get_buf_at_line(multibuf, row)
+			local source_end_row
+			if source_buf and details.end_row then
+				local end_buf, end_row = M.multibuf_get_buf_at_line(multibuf, details.end_row)
+				if end_buf == source_buf then
+					source_end_row = end_row
+				end
+			end
+			table.insert(captured, {
+				ns_id = details.ns_id,
+				id = id,
+				row = row,
+				col = col,
+				details = details,
+				source_buf = source_buf,
+				source_row = source_row,
+				source_end_row = source_end_row,
+			})
+		end
+	end
+	return captured
+end
+
+--- Puts captured extmarks back at the rows their source lines moved to. Marks
+--- on header lines keep their row and marks whose source line is no longer
+--- part of the multibuffer are left where the rebuild moved them.
+--- @param multibuf integer
+--- @param captured MultibufCapturedExtmark[]
+--- @param header_len integer
+local function restore_user_extmarks(multibuf, captured, header_len)
+	for _, mark in ipairs(captured) do
+		local new_row
+		if mark.source_buf and mark.source_row then
+			new_row = M.multibuf_buf_get_line(multibuf, mark.source_buf, mark.source_row)
+		elseif not mark.source_buf and mark.row < header_len then
+			new_row = mark.row
+		end
+
+		if new_row then
+			local d = mark.details
+			local new_end_row
+			if d.end_row then
+				if mark.source_end_row then
+					new_end_row = M.multibuf_buf_get_line(multibuf, mark.source_buf, mark.source_end_row)
+				end
+				new_end_row = new_end_row or (new_row + (d.end_row - mark.row))
+			end
+
+			d.id, d.ns_id, d.end_row = mark.id, nil, new_end_row
+			pcall(vim.api.nvim_buf_set_extmark, multibuf, mark.ns_id, new_row, mark.col, d)
+		end
+	end
+end
+
+-- ──────── Core Multibuffer Management ────────
+
+--- Glyph and highlight of the most important flag of a region status.
+--- @param status MultibufRegionStatus|nil
+--- @return string glyph, string|nil hl
+local function get_outline_glyph(status)
+	if not status then
+		return " ", nil
+	elseif status.conflict then
+		return "!", "DiagnosticError"
+	elseif status.edited then
+		return "*", "DiagnosticWarn"
+	elseif status.written then
+		return "✓", "DiagnosticOk"
+	elseif status.readonly then
+		return "-", "Comment"
+	end
+	return " ", nil
+end
+
+--- One line per region of {mb} in its outline panel, if it has one.
+--- @param mb integer
+local function render_outline(mb)
+	local outline = outlines[mb]
+	if not outline or not vim.api.nvim_buf_is_valid(outline.buf) then
+		return
+	end
+
+	-- the regions of multibuf_get_regions, which the <CR> mapping indexes
+	local lines, marks = {}, {}
+	local info = multibufs[mb]
+	for _, b_info in ipairs(info and info.bufs or {}) do
+		local name = vim.api.nvim_buf_get_name(b_info.buf)
+		name = name ~= "" and vim.fn.fnamemodify(name, ":~:.") or string.format("[buffer %d]", b_info.buf)
+		for s_idx in ipairs(b_info.region_meta) do
+			local start_row, end_row = get_region_rows(b_info, s_idx)
+			if start_row then
+				local glyph, hl = get_outline_glyph(get_region_status(mb, b_info, s_idx))
+				local range = string.format(":%d-%d", start_row + 1, end_row + 1)
+				table.insert(lines, glyph .. " " .. name .. range)
+				local col = #glyph + 1 + #name
+				table.insert(marks, { row = #lines - 1, glyph = glyph, hl = hl, col = col, len = #range })
+			end
+		end
+	end
+	if #lines == 0 then
+		lines = { "  no regions" }
+	end
+
+	vim.bo[outline.buf].modifiable = true
+	vim.api.nvim_buf_set_lines(outline.buf, 0, -1, true, lines)
+	vim.bo[outline.buf].modifiable = false
+	vim.api.nvim_buf_clear_namespace(outline.buf, M.multibuf_outline_ns, 0, -1)
+	for _, mark in ipairs(marks) do
+		if mark.hl then
+			vim.api.nvim_buf_set_extmark(outline.buf, M.multibuf_outline_ns, mark.row, 0, {
+				end_col = #mark.glyph,
+				hl_group = mark.hl,
+			})
+		end
+		vim.api.nvim_buf_set_extmark(outline.buf, M.multibuf_outline_ns, mark.row, mark.col, {
+			end_col = mark.col + mark.len,
+			hl_group = "LineNr",
+		})
+	end
+end
+
+--- Rebuild a multibuffer whose regions are inconsistent from its source
+--- extmarks. When that doesn't help the multibuffer is made read-only so a
+--- corrupted layout can't be written back to the sources.
+--- @param multibuf integer
+--- @param err string
+local function recover_inconsistent_multibuf(multibuf, err)
+	local info = multibufs[multibuf]
+	vim.notify("multibuffer: inconsistent regions, rebuilding (" .. err .. ")", vim.log.levels.WARN)
+
+	info.validating = true
+	for _, b_info in ipairs(info.bufs) do
+		if not b_info.pending_regions then
+			merge_buffer_regions(b_info)
+		end
+	end
+	M.multibuf_reload(multibuf)
+	info.validating = false
+
+	local ok, retry_err = M.multibuf_validate(multibuf)
+	if not ok then
+		info.invalid = retry_err
+		vim.api.nvim_set_option_value("readonly", true, { buf = multibuf })
+		vim.notify(
+			"multibuffer: regions are still inconsistent after rebuild, multibuffer is now read-only ("
+				.. retry_err
+				.. ")",
+			vim.log.levels.ERROR
+		)
+	end
+end
+
+--- @class MultibufLayoutRegion
+--- @field id integer|nil Region id, nil for regions that are still loading
+--- @field buf integer Source buffer
+--- @field start_row integer First multibuffer row of the region (0-indexed)
+--- @field end_row integer Last multibuffer row of the region (inclusive), `start_row - 1` when it shows no lines
+--- @field title boolean Whether the title of its source entry is shown above it
+--- @field elided boolean Whether only its first and last lines are shown
+
+--- @class MultibufLayout
+--- @field header_lines integer Rows taken by the header at the top
+--- @field line_count integer Rows of the whole multibuffer
+--- @field regions MultibufLayoutRegion[]
+
+--- Rows every region of {info} takes in the multibuffer. Reload lays the
+--- lines out with this, so regions it would merge or drop are merged or
+--- dropped here too without touching them.
+--- @param info MultibufInfo
+--- @param kept table<MultibufBufInfo, table<integer, { lines: string[], head: integer|nil }>>|nil carried over edits
+--- @param scope integer|nil source whose empty regions get dropped, all of them when nil
+--- @return MultibufLayout
+--- @return table<MultibufBufInfo, MultibufLayoutRegion[]> by_entry regions of every source entry in order
+local function compute_layout(info, kept, scope)
+	kept = kept or {}
+	local padding = M.user_opts.region_padding or 0
+	local shown_titles = get_shown_titles(info)
+	local header = info.header or create_multibuf_header()
+	local layout = { header_lines = #header, regions = {} }
+	local by_entry = {}
+	local row = #header
+
+	for _, b_info in ipairs(info.bufs) do
+		local ranges = {}
+		if b_info.pending_regions then
+			for s_idx, r in ipairs(b_info.pending_regions) do
+				table.insert(ranges, { s = r.start_row, e = r.end_row + 1, s_idx = s_idx })
+			end
+		else
+			for s_idx, sid in ipairs(b_info.source_extmark_ids) do
+				local s, e = get_extmark_range(b_info.buf, sid)
+				local meta = b_info.region_meta[s_idx]
+				if s then
+					table.insert(ranges, { s = s, e = e, s_idx = s_idx, id = meta and meta.id })
+				end
+			end
+			if not kept[b_info] then
+				if M.user_opts.auto_remove_empty_regions and (not scope or b_info.buf == scope) then
+					ranges = vim.tbl_filter(function(r)
+						return r.s ~= r.e
+					end, ranges)
+				end
+				-- same grouping as merge_touching_regions
+				if M.user_opts.auto_merge ~= false and #ranges == #b_info.source_extmark_ids then
+					table.sort(ranges, function(a, b)
+						return a.s < b.s
+					end)
+					local grouped = { ranges[1] }
+					for i = 2, #ranges do
+						local current = grouped[#grouped]
+						if ranges[i].s <= current.e then
+							current.e = math.max(current.e, ranges[i].e)
+						else
+							table.insert(grouped, ranges[i])
+						end
+					end
+					ranges = grouped
+				end
+			end
+		end
+
+		by_entry[b_info] = {}
+		for i, r in ipairs(ranges) do
+			local elision = compute_elision(b_info, r.s_idx, r.e - r.s)
+			local len = elision and (elision.head + elision.tail) or (r.e - r.s)
+			local keep = kept[b_info] and kept[b_info][r.s_idx]
+			if keep and (keep.head ~= nil) == (elision ~= nil) then
+				len = #keep.lines
+			end
+			local region = {
+				id = r.id,
+				buf = b_info.buf,
+				start_row = row,
+				end_row = row + len - 1,
+				title = i == 1 and shown_titles[b_info] == true,
+				elided = elision ~= nil,
+			}
+			table.insert(layout.regions, region)
+			table.insert(by_entry[b_info], region)
+			row = row + len + padding
+		end
+	end
+	if #layout.regions > 0 then
+		row = row - padding
+	end
+	layout.line_count = row
+	return layout, by_entry
+end
+
+--- Run a reload or write of {mb}. A wipeout of {mb} while it runs, e.g. by
+--- an autocmd or hook it triggered, is finished once it returns. Errors are
+--- raised again with their traceback, unless the buffer is gone by then and
+--- they came from using it.
+--- @param mb integer
+--- @param info MultibufInfo
+--- @param fn function
+--- @param ... any arguments of {fn}
+--- @return any ... results of {fn}, nothing when {mb} was wiped out
+local function run_guarded(mb, info, fn, ...)
+	info.busy = (info.busy or 0) + 1
+	local result = vim.F.pack_len(xpcall(fn, debug.traceback, ...))
+	info.busy = info.busy - 1
+	if info.wiped and info.busy == 0 then
+		M.multibuf__wipeout(mb)
+	end
+	if not result[1] and vim.api.nvim_buf_is_valid(mb) then
+		error(result[2], 0)
+	end
+	if info.wiped or not result[1] then
+		return
+	end
+	return unpack(result, 2, result.n)
+end
+
+--- @class MultibufReloadOptions
+--- @field source integer|string|nil Only refresh the regions of this source buffer (handle or path)
+--- @field cursor_buf integer|nil Source buffer to keep the cursor on instead of the one under it
+--- @field cursor_line integer|nil 0-indexed line in `cursor_buf`
+--- @field keep_edits boolean|nil Carry over the unwritten edits of every source, not only the ones outside `source`
+
+--- @param multibuf integer
+--- @param opts MultibufReloadOptions|nil
+local function reload_multibuf(multibuf, opts)
+	opts = opts or {}
+	local info = resolve_multibuf(multibuf)
+	if not info then
+		return
+	end
+	local win = get_buf_win(multibuf)
+	local sc_width = get_signcolumn_width(win)
+	local cursor_pos = win and vim.api.nvim_win_get_cursor(win)
+	local source_buf, source_line
+	if win then
+		if opts.cursor_buf then
+			source_buf, source_line = opts.cursor_buf, opts.cursor_line
+		else
+			source_buf, source_line = M.multibuf_get_buf_at_line(multibuf, cursor_pos[1] - 1)
+		end
+	end
+
+	-- edited regions of other sources are carried over as they are
+	--- @type table<MultibufBufInfo, table<integer, { lines: string[], head: integer|nil }>>
+	local kept = {}
+	local scope = opts.source and resolve_source_buf(opts.source)
+	if opts.source and not scope then
+		return
+	end
+	if opts.keep_edits then
+		-- the queued syncs still wait for the edits to be written
+	elseif not scope then
+		info.pending_sources = nil
+	elseif info.pending_sources then
+		info.pending_sources[scope] = nil
+	end
+	if (scope or opts.keep_edits) and vim.bo[multibuf].modified then
+		for _, b_info in ipairs(info.bufs) do
+			if (opts.keep_edits or b_info.buf ~= scope) and not b_info.pending_regions then
+				for s_idx, rid in ipairs(b_info.region_extmark_ids) do
+					if region_is_dirty(multibuf, b_info, s_idx) then
+						local r_start, r_end = get_extmark_range(multibuf, rid)
+						local tail_row = get_elision_tail_row(multibuf, b_info, s_idx)
+						kept[b_info] = kept[b_info] or {}
+						kept[b_info][s_idx] = {
+							lines = vim.api.nvim_buf_get_lines(multibuf, r_start, r_end, true),
+							head = tail_row and tail_row - r_start,
+						}
+					end
+				end
+			end
+		end
+	end
+
+	-- regions whose source lines were all deleted, see `auto_remove_empty_regions`.
+	-- Sources with carried over edits keep theirs so the edits stay in place.
+	local removed = {}
+	if M.user_opts.auto_remove_empty_regions then
+		for b_idx = #info.bufs, 1, -1 do
+			local b_info = info.bufs[b_idx]
+			if not b_info.pending_regions and not kept[b_info] and (not scope or b_info.buf == scope) then
+				for s_idx = #b_info.source_extmark_ids, 1, -1 do
+					local s_start, s_end = get_extmark_range(b_info.buf, b_info.source_extmark_ids[s_idx])
+					if s_start and s_start == s_end then
+						table.insert(removed, 1, b_info.region_meta[s_idx].id)
+						drop_region(multibuf, info, b_idx, s_idx)
+					end
+				end
+				release_source(multibuf, info, b_info.buf)
+			end
+		end
+	end
+
+	local user_extmarks = capture_user_extmarks(multibuf)
+
+	-- marks of the other namespaces are captured above and moved back after
+	vim.api.nvim_buf_clear_namespace(multibuf, M.multibuf__ns, 0, -1)
+	vim.api.nvim_buf_clear_namespace(multibuf, M.multibuf_structure_ns, 0, -1)
+
+	local header = info.header or create_multibuf_header()
+	local all_lines = { unpack(header) }
+	local merged = {}
+	local virt_name_indices = {}
+	local virt_expand_lnums = {}
+	local folds = {}
+
+	-- real blank lines after every region but the last one
+	local padding = M.user_opts.region_padding or 0
+	local padding_lines = {}
+	for i = 1, padding do
+		padding_lines[i] = ""
+	end
+	local region_count = 0
+
+	-- 1. Build Text Content
+	for _, buf_info in ipairs(info.bufs) do
+		local has_content = false
+		if buf_info.pending_regions and #buf_info.pending_regions > 0 then
+			has_content = true
+		elseif #buf_info.source_extmark_ids > 0 then
+			has_content = true
+		end
+
+		if has_content then
+			table.insert(virt_name_indices, #all_lines)
+			buf_info.elisions = {}
+			if buf_info.pending_regions then
+				for s_idx, region in ipairs(buf_info.pending_regions) do
+					table.insert(virt_expand_lnums, #all_lines)
+					local count = (region.end_row - region.start_row) + 1
+					local elision = compute_elision(buf_info, s_idx, count)
+					if elision then
+						buf_info.elisions[s_idx] = elision
+						count = elision.head + elision.tail
+					end
+					if count > 0 then
+						for _ = 1, count do
+							table.insert(all_lines, "") -- empty line while temporarily loading
+						end
+					end
+					vim.list_extend(all_lines, padding_lines)
+					region_count = region_count + 1
+				end
+			else
+				-- merging reorders the regions, carried over edits need them in place
+				if M.user_opts.auto_merge ~= false and not kept[buf_info] then
+					vim.list_extend(merged, merge_touching_regions(buf_info))
+				end
+				buf_info.fingerprints = {}
+				buf_info.snapshots = {}
+				buf_info.bases = {}
+				buf_info.source_line_count = vim.api.nvim_buf_line_count(buf_info.buf)
+				buf_info.changedtick = vim.api.nvim_buf_get_changedtick(buf_info.buf)
+				for s_idx, source_extmark_id in ipairs(buf_info.source_extmark_ids) do
+					local s_start, s_end = get_extmark_range(buf_info.buf, source_extmark_id)
+					if s_start and s_end then
+						table.insert(virt_expand_lnums, #all_lines)
+						local elision = compute_elision(buf_info, s_idx, s_end - s_start)
+						buf_info.elisions[s_idx] = elision
+						-- folds can't span the hidden lines of an elided region
+						if M.user_opts.fold_regions and not elision then
+							for _, fold in ipairs(get_source_fold_ranges(buf_info.buf, s_start, s_end)) do
+								table.insert(folds, {
+									start_row = #all_lines + (fold.start_row - s_start),
+									end_row = #all_lines + (fold.end_row - s_start),
+								})
+							end
+						end
+						local lines = vim.api.nvim_buf_get_lines(buf_info.buf, s_start, s_end, true)
+						buf_info.fingerprints[s_idx] = region_fingerprint(lines, s_start)
+						buf_info.bases[s_idx] = capture_base(buf_info.buf, lines, s_start)
+						local meta = buf_info.region_meta[s_idx]
+						if meta and meta.transform == "dedent" then
+							meta.indent = common_indent(lines)
+							lines = dedent_lines(lines, meta.indent)
+						end
+						if meta and meta.span and meta.span.block then
+							lines = vim.tbl_map(function(line)
+								local sc, ec = get_block_cols(line, meta.span)
+								return line:sub(sc + 1, ec)
+							end, lines)
+						elseif meta and meta.span then
+							local sc, ec
+							if s_end - s_start == 1 then
+								sc, ec = get_span_cols(buf_info.buf, meta, s_start)
+							end
+							if sc then
+								meta.span.start_col, meta.span.end_col = sc, ec
+								lines = { lines[1]:sub(sc + 1, ec) }
+							else
+								-- lines were added around it or its columns are gone
+								clear_span(buf_info.buf, meta)
+							end
+						end
+						local keep = kept[buf_info] and kept[buf_info][s_idx]
+						if keep and (keep.head ~= nil) ~= (elision ~= nil) then
+							kept[buf_info][s_idx] = nil
+							if next(kept[buf_info]) == nil then
+								kept[buf_info] = nil
+							end
+							keep = nil
+						end
+						local shown = lines
+						if elision then
+							shown = vim.list_slice(lines, 1, elision.head)
+							vim.list_extend(shown, lines, #lines - elision.tail + 1, #lines)
+						end
+						-- kept regions stay dirty against the source lines they replace
+						buf_info.snapshots[s_idx] = shown
+						if keep then
+							vim.list_extend(all_lines, keep.lines)
+						else
+							vim.list_extend(all_lines, shown)
+							if buf_info.conflicts then
+								buf_info.conflicts[s_idx] = nil
+							end
+						end
+						vim.list_extend(all_lines, padding_lines)
+						region_count = region_count + 1
+					end
+				end
+			end
+		end
+	end
+	if region_count > 0 then
+		for _ = 1, padding do
+			table.remove(all_lines)
+		end
+	end
+	table.insert(virt_expand_lnums, #all_lines)
+
+	-- folds must be known before the lines change so foldexpr sees the new layout
+	info.folds = folds
+	info.fold_levels = compute_fold_levels(folds)
+
+	-- rebuilding the layout isn't an edit, keep it out of the undo history. An
+	-- undo entry would let |u| bring back stale lines of other regions which
+	-- then get written back to their sources. This clears the whole history,
+	-- kept edits included, their undo entries would point at the old layout.
+	local undolevels = vim.bo[multibuf].undolevels
+	vim.api.nvim_set_option_value("modifiable", true, { buf = multibuf })
+	vim.api.nvim_set_option_value("undolevels", -1, { buf = multibuf })
+	local set_ok, set_err = pcall(vim.api.nvim_buf_set_lines, multibuf, 0, -1, true, all_lines)
+	vim.api.nvim_set_option_value("undolevels", undolevels, { buf = multibuf })
+	if not set_ok then
+		error(set_err)
+	end
+
+	if #header > 0 then
+		vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, 0, 0, {
+			end_row = #header - 1,
+			end_col = #header[#header],
+			hl_group = "MultibufHeader",
+		})
+	end
+
+	-- 2. Render Structure (Titles, Signs, Expanders)
+	local number_mode = info.number_mode or "absolute"
+	local current_lnum = #header
+	local virt_expand_idx = 1
+	local name_idx_cursor = 1
+	local global_slice_idx = 0
+	local sign_batch = new_sign_batch()
+	local shown_titles = get_shown_titles(info)
+	local _, layout_entries = compute_layout(info, kept, scope)
+
+	-- an "auto" 'signcolumn' is only as wide as the signs, so the line numbers
+	-- are sized to the largest one shown rather than padded to its maximum
+	local fit_numbers = sc_width > 0 and get_signcolumn_option(win):find("^auto") ~= nil
+	local per_region_width = M.user_opts.line_number_width == "region"
+	local uniform_width = sc_width
+	if fit_numbers and not per_region_width then
+		uniform_width = math.min(sc_width, get_line_number_width(get_max_source_line(info)))
+	end
+	local numbers_as_text = M.user_opts.line_number_mode ~= "signs"
+	if numbers_as_text then
+		info.number_text_width = #tostring(math.max(get_max_source_line(info), #all_lines))
+	end
+	local column_names, column_width = {}, 0
+	if M.user_opts.layout == "columns" then
+		for _, b_info in ipairs(info.bufs) do
+			column_names[b_info.buf] = column_names[b_info.buf] or get_source_column_name(b_info.buf)
+			column_width = math.max(column_width, vim.fn.strdisplaywidth(column_names[b_info.buf]))
+		end
+	end
+
+	local function get_expander_sign(direction)
+		local signs = M.user_opts.expander_signs or {}
+		local text = signs[direction]
+		if text and text ~= "" then
+			return text
+		end
+		return nil
+	end
+
+	for b_idx, buf_info in ipairs(info.bufs) do
+		local has_content = false
+		if buf_info.pending_regions and #buf_info.pending_regions > 0 then
+			has_content = true
+		elseif #buf_info.source_extmark_ids > 0 then
+			has_content = true
+		end
+
+		if has_content then
+			buf_info.region_extmark_ids = {}
+			-- titles are virtual lines so hiding them doesn't move any content
+			buf_info.title_extmark_id = nil
+			place_title(multibuf, buf_info, virt_name_indices[name_idx_cursor], shown_titles[buf_info] == true)
+			name_idx_cursor = name_idx_cursor + 1
+
+			local last_s_end = 0
+			local source_line_count = vim.api.nvim_buf_line_count(buf_info.buf)
+
+			local slices = {}
+			if buf_info.pending_regions then
+				for _, r in ipairs(buf_info.pending_regions) do
+					table.insert(slices, { s = r.start_row, e = r.end_row + 1 })
+				end
+			else
+				for _, sid in ipairs(buf_info.source_extmark_ids) do
+					local s, e = get_extmark_range(buf_info.buf, sid)
+					if s then
+						table.insert(slices, { s = s, e = e })
+					end
+				end
+			end
+
+			for s_idx, slice in ipairs(slices) do
+				local s_start, s_end = slice.s, slice.e
+				local elision = buf_info.elisions[s_idx]
+				local placed = layout_entries[buf_info][s_idx]
+				current_lnum = placed.start_row
+				local slice_len = placed.end_row - placed.start_row + 1
+				-- rows before the elision marker
+				local head_rows = elision and elision.head
+				local keep = kept[buf_info] and kept[buf_info][s_idx]
+				if keep then
+					head_rows = keep.head
+				end
+				local next_s_start = slices[s_idx + 1] and slices[s_idx + 1].s or source_line_count
+
+				global_slice_idx = global_slice_idx + 1
+				local is_odd = (global_slice_idx % 2 ~= 0)
+				local region_hl = is_odd and M.user_opts.region_hl_odd or M.user_opts.region_hl_even
+				local line_nr_hl = is_odd and "MultibufLineNrOdd" or "MultibufLineNrEven"
+				local expander_hl = is_odd and "MultibufExpanderOdd" or "MultibufExpanderEven"
+				local number_width = uniform_width
+				if fit_numbers and per_region_width then
+					number_width = math.min(sc_width, get_line_number_width(math.max(1, s_end)))
+				end
+
+				-- Signs on visible lines
+				for i = 0, slice_len - 1 do
+					local column_name = column_names[buf_info.buf]
+					if column_name then
+						place_source_column(multibuf, current_lnum + i, i == 0 and column_name or nil, column_width)
+					end
+					local special_sign = nil
+					if sc_width > 0 then
+						local is_first = (i == 0)
+						local is_last = (i == slice_len - 1)
+						local has_gap_above = (s_start > last_s_end)
+						local has_gap_below = (s_end < next_s_start)
+
+						local needs_above = is_first and has_gap_above
+						local needs_below = is_last and has_gap_below
+
+						if needs_above and needs_below then
+							special_sign = get_expander_sign("both")
+						elseif needs_above then
+							special_sign = get_expander_sign("above")
+						elseif needs_below then
+							special_sign = get_expander_sign("below")
+						end
+					end
+					local source_row = (head_rows and i >= head_rows) and (s_end - (slice_len - i)) or (s_start + i)
+					if number_mode == "absolute" and numbers_as_text then
+						if special_sign then
+							place_expander_sign(sign_batch, current_lnum + i, special_sign, expander_hl)
+						end
+						place_line_number_text(multibuf, M.multibuf_structure_ns, current_lnum + i, {
+							{ string.format("%" .. info.number_text_width .. "d", source_row + 1), line_nr_hl },
+						})
+					elseif number_mode == "absolute" then
+						place_line_number_signs(
+							sign_batch,
+							current_lnum + i,
+							source_row,
+							number_width,
+							special_sign,
+							line_nr_hl,
+							expander_hl
+						)
+					elseif special_sign then
+						place_expander_sign(sign_batch, current_lnum + i, special_sign, expander_hl)
+					end
+				end
+
+				-- Gap renderer above
+				if s_start > last_s_end then
+					place_expander(multibuf, current_lnum, {
+						expand_direction = (s_idx == 1) and "above" or "both",
+						count = s_start - last_s_end,
+						window = win or 0,
+						bufnr = buf_info.buf,
+						start_row = last_s_end,
+					})
+				end
+
+				-- Gap renderer below (only for last slice of buffer)
+				if (s_idx == #slices) and (s_end < source_line_count) then
+					place_expander(multibuf, current_lnum + slice_len - 1, {
+						expand_direction = "below",
+						count = source_line_count - s_end,
+						window = win or 0,
+						bufnr = buf_info.buf,
+						start_row = s_end,
+					})
+				end
+
+				buf_info.region_extmark_ids[s_idx] =
+					vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, current_lnum, 0, {
+						end_row = current_lnum + slice_len,
+						end_right_gravity = true,
+					})
+
+				if elision then
+					elision.mark_id =
+						vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, current_lnum + head_rows, 0, {
+							virt_lines = {
+								{ { string.format("  … %d lines hidden …", elision.hidden), "MultibufElision" } },
+							},
+							virt_lines_above = true,
+						})
+				end
+
+				-- same index as multibuf_get_regions, signs are at most two cells wide
+				if M.user_opts.show_region_index and slice_len > 0 then
+					local index = global_slice_idx
+					vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, current_lnum, 0, {
+						sign_text = index < 10 and index .. "." or index < 100 and tostring(index) or "…",
+						sign_hl_group = M.user_opts.region_index_hl,
+						priority = 1500,
+					})
+				end
+
+				local meta = buf_info.region_meta[s_idx]
+				if meta and meta.footer and slice_len > 0 then
+					vim.api.nvim_buf_set_extmark(multibuf, M.multibuf_structure_ns, current_lnum + slice_len - 1, 0, {
+						virt_lines = meta.footer,
+						virt_lines_leftcol = true,
+					})
+				end
+
+				current_lnum, last_s_end, virt_expand_idx =
+					current_lnum + slice_len + padding, s_end, virt_expand_idx + 1
+			end
+		end
+	end
+
+	place_sign_batch(multibuf, sign_batch)
+	restore_user_extmarks(multibuf, user_extmarks, #header)
+	update_multibuf_filetype(multibuf)
+
+	vim.api.nvim_set_option_value("modified", next(kept) ~= nil, { buf = multibuf })
+	if win and cursor_pos then
+		local new_line
+		if source_buf and source_line then
+			new_line = M.multibuf_buf_get_line(multibuf, source_buf, source_line)
+		end
+
+		if new_line then
+			vim.api.nvim_win_set_cursor(win, { new_line + 1, cursor_pos[2] })
+		else
+			-- fallback to old absolute pos, clamped
+			local line_count = vim.api.nvim_buf_line_count(multibuf)
+			local target_line = math.min(cursor_pos[1], line_count)
+			vim.api.nvim_win_set_cursor(win, { target_line, cursor_pos[2] })
+		end
+	end
+
+	render_relative_line_numbers(multibuf, win)
+
+	if not info.validating then
+		local ok, err = M.multibuf_validate(multibuf)
+		if not ok then
+			recover_inconsistent_multibuf(multibuf, err --[[@as string]])
+		elseif info.invalid then
+			info.invalid = nil
+			vim.api.nvim_set_option_value("readonly", false, { buf = multibuf })
+		end
+	end
+
+	-- the rewrite above isn't an edit, carried over edits still are
+	info.touched_marks = {}
+	for b_info, regions in pairs(kept) do
+		for s_idx in pairs(regions) do
+			local rid = b_info.region_extmark_ids[s_idx]
+			if rid then
+				info.touched_marks[rid] = true
+			end
+		end
+	end
+	info.touched_rows = nil
+	update_region_status(multibuf)
+	render_dirty_signs(multibuf)
+	render_mirrored_highlights(multibuf)
+	render_outline(multibuf)
+	if #merged > 0 or #removed > 0 then
+		vim.api.nvim_exec_autocmds("User", {
+			pattern = "MultibufRegionsChanged",
+			data = { buf = multibuf, merged = merged, removed = removed },
+		})
+	end
+end
+
+--- Rebuild a multibuffer from its sources. With `source` the regions of other
+--- sources keep their unwritten edits and the multibuffer stays modified if
+--- there are any. The older `multibuf_reload(mb, cursor_buf, cursor_line)`
+--- form is still accepted.
+--- @param multibuf integer
+--- @param opts MultibufReloadOptions|integer|nil
+--- @param ... integer cursor_line when {opts} is a cursor buffer
+function M.multibuf_reload(multibuf, opts, ...)
+	if type(opts) == "number" then
+		opts = { cursor_buf = opts, cursor_line = select(1, ...) }
+	end
+	local info = resolve_multibuf(multibuf)
+	if info then
+		run_guarded(multibuf, info, reload_multibuf, multibuf, opts)
+	end
+end
+
+--- Rows the regions of a multibuffer would take after |multibuf_reload()|
+--- without changing anything. Unwritten edits are not carried over, like a
+--- reload without options.
+--- @param mb integer|nil multibuf id, the active multibuffer when nil
+--- @return MultibufLayout|nil
+function M.multibuf_preview_layout(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+	return (compute_layout(info))
+end
+
+--- Apply the changed spans of one source buffer. Spans that are contiguous
+--- in the source and in the multibuffer are coalesced into a single
+--- |nvim_buf_set_lines()| call and all calls are joined into one undo step.
+--- @param buf integer source buffer
+--- @param parts { s_start: integer, s_end: integer, r_start: integer, r_end: integer, lines: string[] }[]
+local function apply_source_writes(buf, parts)
+	-- a line added at the end of a region starts where the next region does,
+	-- the insertion goes first so it's applied after the edit behind it
+	table.sort(parts, function(a, b)
+		if a.s_start ~= b.s_start then
+			return a.s_start < b.s_start
+		end
+		return a.s_end < b.s_end
+	end)
+
+	local merged = {}
+	for _, part in ipairs(parts) do
+		local prev = merged[#merged]
+		if prev and not prev.cols and not part.cols and prev.s_end == part.s_start and prev.r_end == part.r_start then
+			prev.s_end, prev.r_end = part.s_end, part.r_end
+			vim.list_extend(prev.lines, part.lines)
+		else
+			table.insert(
+				merged,
+				{ s_start = part.s_start, s_end = part.s_end, r_end = part.r_end, lines = part.lines, cols = part.cols }
+			)
+		end
+	end
+
+	-- a source without a final newline keeps it missing, whatever happens to
+	-- the regions reaching its last line
+	local eol, fixeol = vim.bo[buf].endofline, vim.bo[buf].fixendofline
+	vim.api.nvim_buf_call(buf, function()
+		-- back to front so the spans before stay valid
+		for i = #merged, 1, -1 do
+			local part = merged[i]
+			if i < #merged then
+				pcall(vim.cmd.undojoin)
+			end
+			if part.cols then
+				-- the rest of the line of a column region stays as it is
+				vim.api.nvim_buf_set_text(buf, part.s_start, part.cols[1], part.s_start, part.cols[2], part.lines)
+			else
+				vim.api.nvim_buf_set_lines(buf, part.s_start, part.s_end, true, part.lines)
+			end
+		end
+	end)
+	if vim.bo[buf].endofline ~= eol or vim.bo[buf].fixendofline ~= fixeol then
+		vim.bo[buf].endofline, vim.bo[buf].fixendofline = eol, fixeol
+	end
+end
+
+--- The write of an edited column region: its columns replaced with the text
+--- of its line. A line break typed into it can't be written as columns, the
+--- region is written as whole lines instead.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param span { [1]: integer, [2]: integer, [3]: integer, [4]: integer } see |get_region_spans|
+--- @return table[] parts, integer delta, string|nil err "conflict" when the source changed under the columns
+local function get_span_write(mb, b_info, s_idx, span)
+	local meta = b_info.region_meta[s_idx]
+	local row = span[1]
+	local lines = vim.api.nvim_buf_get_lines(mb, span[3], span[4], true)
+	local source_line = vim.api.nvim_buf_get_lines(b_info.buf, row, row + 1, true)[1]
+	local sc, ec = get_span_cols(b_info.buf, meta, row)
+	local snapshot = b_info.snapshots and b_info.snapshots[s_idx] or {}
+	-- e.g. the source line got shorter than the columns the region showed
+	if not sc or source_line:sub(sc + 1, ec) ~= snapshot[1] then
+		return {}, 0, "conflict"
+	end
+
+	local part = { s_start = row, s_end = row + 1, r_start = span[3], r_end = span[4], lines = lines }
+	if #lines <= 1 then
+		if lines[1] == snapshot[1] then
+			return {}, 0, nil
+		end
+		part.cols, part.lines = { sc, ec }, { lines[1] or "" }
+		return { part }, 0, nil
+	end
+
+	vim.notify(
+		string.format("multibuffer: region %d got a line break, writing it as whole lines", meta.id),
+		vim.log.levels.WARN
+	)
+	part.lines = vim.list_slice(lines)
+	part.lines[1] = source_line:sub(1, sc) .. part.lines[1]
+	part.lines[#lines] = part.lines[#lines] .. source_line:sub(ec + 1)
+	return { part }, #lines - 1, nil
+end
+
+--- The write of an edited multi-line column region: the columns of every
+--- changed line replaced with its text. Its lines can't be added or removed.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param spans { [1]: integer, [2]: integer, [3]: integer, [4]: integer }[] see |get_region_spans|
+--- @return table[] parts, integer delta, string|nil err "conflict" when the source changed under the columns,
+--- "lines" when lines were added or removed
+local function get_block_write(mb, b_info, s_idx, spans)
+	local span_info = b_info.region_meta[s_idx].span
+	local snapshot = b_info.snapshots and b_info.snapshots[s_idx] or {}
+	local parts = {}
+	local k = 0
+	for _, span in ipairs(spans) do
+		local lines = vim.api.nvim_buf_get_lines(mb, span[3], span[4], true)
+		local source_lines = vim.api.nvim_buf_get_lines(b_info.buf, span[1], span[2], true)
+		if #lines ~= #source_lines then
+			return {}, 0, "lines"
+		end
+		for i, line in ipairs(source_lines) do
+			k = k + 1
+			local sc, ec = get_block_cols(line, span_info)
+			if line:sub(sc + 1, ec) ~= snapshot[k] then
+				return {}, 0, "conflict"
+			end
+			if lines[i] ~= snapshot[k] then
+				local row, r_row = span[1] + i - 1, span[3] + i - 1
+				table.insert(parts, {
+					s_start = row,
+					s_end = row + 1,
+					r_start = r_row,
+					r_end = r_row + 1,
+					lines = { lines[i] },
+					cols = { sc, ec },
+				})
+			end
+		end
+	end
+	return parts, 0, nil
+end
+
+--- Leave out the context lines of a region. Edits to context lines are
+--- rejected with a warning together with the rest of the region, since the
+--- writable lines can't be told apart once the context changed.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param spans { [1]: integer, [2]: integer, [3]: integer, [4]: integer }[] see |get_region_spans|
+--- @return { [1]: integer, [2]: integer, [3]: integer, [4]: integer }[]|nil spans nil when the context was edited
+local function get_writable_spans(mb, b_info, s_idx, spans)
+	local meta = b_info.region_meta[s_idx]
+	local context = meta and meta.context
+	if not context or (context.before == 0 and context.after == 0) then
+		return spans
+	end
+
+	local snapshot = b_info.snapshots and b_info.snapshots[s_idx] or {}
+	local first, last = spans[1], spans[#spans]
+	local before = vim.api.nvim_buf_get_lines(mb, first[3], first[3] + context.before, true)
+	local after = vim.api.nvim_buf_get_lines(mb, last[4] - context.after, last[4], true)
+	if
+		not vim.deep_equal(before, vim.list_slice(snapshot, 1, context.before))
+		or not vim.deep_equal(after, vim.list_slice(snapshot, #snapshot - context.after + 1, #snapshot))
+	then
+		vim.notify(
+			string.format(
+				"multibuffer: context lines of a region of %s are read-only, discarding its edits",
+				vim.fn.fnamemodify(vim.api.nvim_buf_get_name(b_info.buf), ":~:.")
+			),
+			vim.log.levels.WARN
+		)
+		return nil
+	end
+
+	local writable = vim.deepcopy(spans)
+	first, last = writable[1], writable[#writable]
+	first[1], first[3] = first[1] + context.before, first[3] + context.before
+	last[2], last[4] = last[2] - context.after, last[4] - context.after
+	return writable
+end
+
+--- Split a changed span into the hunks that differ from the source, so marks,
+--- folds and extmarks on the unchanged lines of a region survive a write.
+--- @param span { [1]: integer, [2]: integer, [3]: integer, [4]: integer } see |get_region_spans|
+--- @param lines string[] lines of the span in the multibuffer
+--- @param source_lines string[] lines of the span in the source
+--- @return { s_start: integer, s_end: integer, r_start: integer, r_end: integer, lines: string[] }[]
+local function diff_span(span, lines, source_lines)
+	local function join(list)
+		return #list > 0 and table.concat(list, "\n") .. "\n" or ""
+	end
+	local diff = vim.text.diff or vim.diff
+	local hunks = diff(join(source_lines), join(lines), { result_type = "indices" }) --[[@as integer[][] ]]
+
+	local parts = {}
+	for _, hunk in ipairs(hunks) do
+		local a_start, a_count, b_start, b_count = unpack(hunk)
+		-- an empty side of a hunk starts after the line it names
+		local s_start = span[1] + (a_count == 0 and a_start or a_start - 1)
+		local r_start = span[3] + (b_count == 0 and b_start or b_start - 1)
+		table.insert(parts, {
+			s_start = s_start,
+			s_end = s_start + a_count,
+			r_start = r_start,
+			r_end = r_start + b_count,
+			lines = vim.list_slice(lines, b_start, b_start + b_count - 1),
+		})
+	end
+	return parts
+end
+
+--- @class MultibufWriteSummary
+--- @field sources integer[] Source buffers that change (before) or changed (after the write)
+--- @field regions integer[] Ids of the regions that are or were written
+--- @field targets { region: integer, buf: integer }[] Regions written to a target instead of their source
+
+--- Run a write hook, errors are reported and cancel a pending write.
+--- @param name "on_pre_write"|"on_post_write"
+--- @param mb integer
+--- @param summary MultibufWriteSummary
+--- @return boolean proceed
+local function run_write_hook(name, mb, summary)
+	local hook = M.user_opts[name]
+	if not hook then
+		return true
+	end
+	local ok, result = pcall(hook, mb, summary)
+	if not ok then
+		vim.notify(string.format("multibuffer: %s failed: %s", name, result), vim.log.levels.ERROR)
+		return false
+	end
+	return result ~= false
+end
+
+--- The edited lines of a region with the lines hidden by an elision taken
+--- from the source.
+--- @param mb integer
+--- @param b_info MultibufBufInfo
+--- @param s_idx integer
+--- @param spans { [1]: integer, [2]: integer, [3]: integer, [4]: integer }[] see |get_region_spans|
+--- @return string[]
+local function get_region_text(mb, b_info, s_idx, spans)
+	local meta = b_info.region_meta[s_idx]
+	local indent = meta and meta.indent or ""
+	local lines = {}
+	for i, span in ipairs(spans) do
+		if i > 1 then
+			vim.list_extend(lines, vim.api.nvim_buf_get_lines(b_info.buf, spans[i - 1][2], span[1], true))
+		end
+		vim.list_extend(lines, reindent_lines(vim.api.nvim_buf_get_lines(mb, span[3], span[4], true), indent))
+	end
+	return lines
+end
+
+--- Replace the lines of the last write to {target}, the first write inserts
+--- at `start_row` or appends.
+--- @param target MultibufRegionTarget
+--- @param lines string[]
+local function write_region_target(target, lines)
+	local buf = target.buf
+	vim.fn.bufload(buf)
+	local s_start, s_end = get_extmark_range(buf, target.mark_id)
+	if not s_start then
+		local line_count = vim.api.nvim_buf_line_count(buf)
+		local empty = line_count == 1 and vim.api.nvim_buf_get_lines(buf, 0, 1, true)[1] == ""
+		if empty then
+			s_start, s_end = 0, 1
+		else
+			s_start = target.start_row and clamp(target.start_row, 0, line_count) or line_count
+			s_end = s_start
+		end
+	end
+	vim.api.nvim_buf_set_lines(buf, s_start, s_end, true, lines)
+	target.mark_id = vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, s_start, 0, {
+		id = target.mark_id,
+		end_row = s_start + #lines,
+		end_right_gravity = true,
+	})
+end
+
+--- @class MultibufWriteOptions
+--- @field source integer|string|nil Only write the regions of this source buffer (handle or path)
+
+--- @class MultibufRegionWriteResult
+--- @field region integer Region id
+--- @field buf integer Source buffer handle
+--- @field status "written"|"skipped"|"failed"
+--- @field reason string|nil Why the region was skipped ("clean", "loading", "context", "readonly", "removed",
+--- "cancelled") or the error it failed with ("conflict" for a column region whose line changed, "lines" for a
+--- column region of several lines that got lines added or removed, "unresolved" when its extmarks are gone)
+
+--- @param mb integer
+--- @param opts MultibufWriteOptions|nil
+--- @return integer[] written, MultibufRegionWriteResult[] results see |multibuf_write|
+local function write_multibuf(mb, opts)
+	opts = opts or {}
+	local info = resolve_multibuf(mb)
+	if not info then
+		return {}, {}
+	end
+	local scope = opts.source and resolve_source_buf(opts.source)
+	if opts.source and not scope then
+		return {}, {}
+	end
+	if info.invalid then
+		vim.notify("multibuffer: refusing to write inconsistent regions (" .. info.invalid .. ")", vim.log.levels.ERROR)
+		return {}, {}
+	end
+
+	--- @type MultibufRegionWriteResult[]
+	local results = {}
+	--- @param b_info MultibufBufInfo
+	--- @param s_idx integer
+	--- @param status "written"|"skipped"|"failed"
+	--- @param reason string|nil
+	--- @return MultibufRegionWriteResult|nil
+	local function report(b_info, s_idx, status, reason)
+		local meta = b_info.region_meta[s_idx]
+		if not meta then
+			return nil
+		end
+		local result = { region = meta.id, buf = b_info.buf, status = status, reason = reason }
+		table.insert(results, result)
+		return result
+	end
+
+	local written = {}
+	--- @type { b_info: MultibufBufInfo, s_idx: integer, r_start: integer, r_end: integer, lines: string[] }[]
+	local redirected = {}
+	--- @type table<integer, table[]> changed spans per source buffer
+	local parts_by_buf = {}
+	--- @type table<integer, table[]> changed regions per source buffer
+	local regions_by_buf = {}
+
+	-- sources that changed since the last reload, e.g. when their `sync_events`
+	-- didn't fire yet. Their regions show stale lines, only the edited ones are
+	-- written and the rest is refreshed after the write.
+	for _, b_info in ipairs(info.bufs) do
+		if
+			(not scope or b_info.buf == scope)
+			and not b_info.pending_regions
+			and b_info.changedtick
+			and vim.api.nvim_buf_get_changedtick(b_info.buf) ~= b_info.changedtick
+		then
+			info.pending_sources = info.pending_sources or {}
+			info.pending_sources[b_info.buf] = true
+		end
+	end
+
+	-- regions whose lines were all deleted, see `delete_region_behavior`
+	local deleted = {}
+	local is_deleted = {}
+	local delete_lines = M.user_opts.delete_region_behavior == "delete_source"
+	for _, region in ipairs(find_deleted_regions(mb, info)) do
+		local b_info = info.bufs[region.b_idx]
+		if not scope or b_info.buf == scope then
+			table.insert(deleted, region)
+			is_deleted[b_info] = is_deleted[b_info] or {}
+			is_deleted[b_info][region.s_idx] = true
+		end
+	end
+	if #deleted > 0 and M.user_opts.delete_region_behavior == "ask" then
+		local files = {}
+		for _, region in ipairs(deleted) do
+			local b_info = info.bufs[region.b_idx]
+			list_insert_unique(files, vim.fn.fnamemodify(vim.api.nvim_buf_get_name(b_info.buf), ":~:."))
+		end
+		local choice = vim.fn.confirm(
+			string.format(
+				"Delete the lines of %d removed region(s) from their sources?\n%s",
+				#deleted,
+				table.concat(files, "\n")
+			),
+			"&Delete lines\n&Only remove the regions\n&Cancel",
+			2
+		)
+		if choice == 0 or choice == 3 then
+			return {}, {}
+		end
+		delete_lines = choice == 1
+	end
+
+	-- regions of other sources keep their edits and the multibuffer stays modified
+	local unwritten = false
+	-- regions whose extmarks are gone, e.g. a wiped source. Their edits can't
+	-- be mapped back, so they are never taken as clean.
+	local unresolved = 0
+
+	--- Queue the changed spans of an edited region, unless it or its source is read-only.
+	--- @param b_info MultibufBufInfo
+	--- @param s_idx integer
+	--- @param parts table[]
+	--- @param delta integer
+	local function queue_region(b_info, s_idx, parts, delta, s_start, s_end, r_start, r_end)
+		local meta = b_info.region_meta[s_idx]
+		if #parts == 0 then
+			-- edited back to what the source has
+			report(b_info, s_idx, "skipped", "clean")
+		elseif b_info.readonly then
+			vim.notify(
+				string.format(
+					"multibuffer: %s is read-only, discarding edits",
+					vim.fn.fnamemodify(vim.api.nvim_buf_get_name(b_info.buf), ":~:.")
+				),
+				vim.log.levels.WARN
+			)
+			report(b_info, s_idx, "skipped", "readonly")
+		elseif meta and meta.readonly then
+			vim.notify(
+				string.format("multibuffer: region %d is read-only, discarding edits", meta.id),
+				vim.log.levels.WARN
+			)
+			report(b_info, s_idx, "skipped", "readonly")
+		else
+			-- the columns a column region covers after the write, false once it became whole lines
+			local span
+			if meta and meta.span and not meta.span.block then
+				local cols = parts[1].cols
+				span = cols and { cols[1], cols[1] + #parts[1].lines[1] } or false
+			end
+			parts_by_buf[b_info.buf] = parts_by_buf[b_info.buf] or {}
+			vim.list_extend(parts_by_buf[b_info.buf], parts)
+			regions_by_buf[b_info.buf] = regions_by_buf[b_info.buf] or {}
+			table.insert(regions_by_buf[b_info.buf], {
+				b_info = b_info,
+				s_idx = s_idx,
+				s_start = s_start,
+				s_end = s_end,
+				r_start = r_start,
+				r_end = r_end,
+				delta = delta,
+				span = span,
+				before = vim.api.nvim_buf_get_lines(b_info.buf, s_start, s_end, true),
+				result = report(b_info, s_idx, "written"),
+			})
+			list_insert_unique(written, b_info.buf)
+		end
+	end
+	for _, b_info in ipairs(info.bufs) do
+		if scope and b_info.buf ~= scope then
+			for s_idx = 1, #b_info.region_extmark_ids do
+				unwritten = unwritten or region_is_dirty(mb, b_info, s_idx)
+			end
+		-- regions that never loaded only show placeholder lines
+		elseif b_info.pending_regions then
+			for s_idx = 1, #b_info.region_meta do
+				report(b_info, s_idx, "skipped", "loading")
+			end
+		else
+			for s_idx = 1, #b_info.region_extmark_ids do
+				-- the lines hidden by an elision are left untouched
+				local spans = get_region_spans(mb, b_info, s_idx)
+				local s_start, s_end = spans and spans[1][1], spans and spans[#spans][2]
+				local r_start, r_end = spans and spans[1][3], spans and spans[#spans][4]
+
+				-- a region deleted as a whole collapses, don't wipe its source lines.
+				-- Unedited regions are skipped so stale lines never revert a source.
+				local dirty = spans and r_end > r_start and region_is_dirty(mb, b_info, s_idx)
+				local resolved = spans ~= nil
+				if dirty then
+					spans = get_writable_spans(mb, b_info, s_idx, spans --[[@as table]])
+				end
+				local meta = b_info.region_meta[s_idx]
+				if is_deleted[b_info] and is_deleted[b_info][s_idx] then
+					-- reported with the other deleted regions below
+					dirty = false
+				elseif not resolved then
+					report(b_info, s_idx, "failed", "unresolved")
+					unresolved = unresolved + 1
+				elseif not dirty then
+					report(b_info, s_idx, "skipped", "clean")
+				elseif not spans then
+					report(b_info, s_idx, "skipped", "context")
+				end
+				if dirty and spans and meta and meta.target and not meta.readonly then
+					table.insert(redirected, {
+						b_info = b_info,
+						s_idx = s_idx,
+						r_start = r_start,
+						r_end = r_end,
+						lines = get_region_text(mb, b_info, s_idx, spans),
+						result = report(b_info, s_idx, "written"),
+					})
+				elseif dirty and spans and meta and meta.span then
+					local parts, delta, err
+					if meta.span.block then
+						parts, delta, err = get_block_write(mb, b_info, s_idx, spans)
+					else
+						parts, delta, err = get_span_write(mb, b_info, s_idx, spans[1])
+					end
+					if err then
+						local message = err == "lines"
+								and "multibuffer: lines were added to or removed from column region %d, not writing it"
+							or "multibuffer: the source of region %d changed under its columns, not writing it"
+						vim.notify(string.format(message, meta.id), vim.log.levels.WARN)
+						report(b_info, s_idx, "failed", err)
+						unwritten = true
+					else
+						queue_region(b_info, s_idx, parts, delta, s_start, s_end, r_start, r_end)
+					end
+				elseif dirty and spans then
+					local parts = {}
+					local delta = 0
+					for _, span in ipairs(spans) do
+						local lines = vim.api.nvim_buf_get_lines(mb, span[3], span[4], true)
+						local source_lines = vim.api.nvim_buf_get_lines(b_info.buf, span[1], span[2], true)
+						-- lines of a dedented region are compared as shown, so the
+						-- unchanged ones keep their exact indentation
+						local indent = meta and meta.indent or ""
+						source_lines = dedent_lines(source_lines, indent)
+						if not vim.deep_equal(lines, source_lines) then
+							for _, part in ipairs(diff_span(span, lines, source_lines)) do
+								part.lines = reindent_lines(part.lines, indent)
+								table.insert(parts, part)
+							end
+							delta = delta + #lines - (span[2] - span[1])
+						end
+					end
+
+					queue_region(b_info, s_idx, parts, delta, s_start, s_end, r_start, r_end)
+				end
+			end
+		end
+	end
+
+	for _, region in ipairs(deleted) do
+		local b_info = info.bufs[region.b_idx]
+		local meta = b_info.region_meta[region.s_idx] or {}
+		local context = meta.context
+		local s_start, s_end = get_extmark_range(b_info.buf, b_info.source_extmark_ids[region.s_idx])
+		local r_start = get_extmark_range(mb, b_info.region_extmark_ids[region.s_idx])
+		-- a redirected region never changes its source
+		if delete_lines and not b_info.readonly and not meta.readonly and not meta.target and s_start and r_start then
+			-- context lines are read-only, only the lines of the region itself go
+			s_start, s_end = s_start + (context and context.before or 0), s_end - (context and context.after or 0)
+			local buf = b_info.buf
+			local parts = { { s_start = s_start, s_end = s_end, r_start = r_start, r_end = r_start, lines = {} } }
+			local delta = s_start - s_end
+			-- a column region only takes its columns with it
+			local sc, ec = get_span_cols(buf, meta, s_start)
+			if sc then
+				s_end, delta = s_start + 1, 0
+				parts[1].s_end, parts[1].lines, parts[1].cols = s_end, { "" }, { sc, ec }
+			elseif meta.span and meta.span.block then
+				parts, delta = {}, 0
+				for row, line in ipairs(vim.api.nvim_buf_get_lines(buf, s_start, s_end, true)) do
+					local row_sc, row_ec = get_block_cols(line, meta.span)
+					local s_row = s_start + row - 1
+					table.insert(parts, {
+						s_start = s_row,
+						s_end = s_row + 1,
+						r_start = r_start,
+						r_end = r_start,
+						lines = { "" },
+						cols = { row_sc, row_ec },
+					})
+				end
+			end
+			parts_by_buf[buf] = parts_by_buf[buf] or {}
+			vim.list_extend(parts_by_buf[buf], parts)
+			regions_by_buf[buf] = regions_by_buf[buf] or {}
+			table.insert(regions_by_buf[buf], {
+				b_info = b_info,
+				s_idx = region.s_idx,
+				s_start = s_start,
+				s_end = s_end,
+				r_start = r_start,
+				r_end = r_start,
+				delta = delta,
+				before = vim.api.nvim_buf_get_lines(buf, s_start, s_end, true),
+				result = report(b_info, region.s_idx, "written"),
+			})
+			list_insert_unique(written, buf)
+		else
+			report(b_info, region.s_idx, "skipped", (b_info.readonly or meta.readonly) and "readonly" or "removed")
+		end
+	end
+
+	if unresolved > 0 then
+		vim.notify(
+			string.format(
+				"multibuffer: %d region(s) could not be resolved and were not written, "
+					.. "check them with multibuf_validate() or multibuf_reload() to drop their edits",
+				unresolved
+			),
+			vim.log.levels.ERROR
+		)
+		-- the edits may be in those regions, don't pretend they were saved
+		unwritten = unwritten or vim.bo[mb].modified
+	end
+
+	local summary = { sources = vim.list_slice(written), regions = {}, targets = {} }
+	for _, buf in ipairs(written) do
+		for _, region in ipairs(regions_by_buf[buf]) do
+			local meta = region.b_info.region_meta[region.s_idx]
+			if meta then
+				table.insert(summary.regions, meta.id)
+			end
+		end
+	end
+	for _, region in ipairs(redirected) do
+		local meta = region.b_info.region_meta[region.s_idx]
+		table.insert(summary.regions, meta.id)
+		table.insert(summary.targets, { region = meta.id, buf = meta.target.buf })
+	end
+	local changes = #written + #redirected
+	if changes > 0 and not run_write_hook("on_pre_write", mb, summary) then
+		for _, result in ipairs(results) do
+			if result.status == "written" then
+				result.status, result.reason = "skipped", "cancelled"
+			end
+		end
+		return {}, results
+	end
+
+	for _, region in ipairs(redirected) do
+		local b_info = region.b_info
+		local meta = b_info.region_meta[region.s_idx]
+		local ok, err = pcall(write_region_target, meta.target, region.lines)
+		if not ok then
+			vim.notify(string.format("multibuffer: writing region %d failed: %s", meta.id, err), vim.log.levels.ERROR)
+			if region.result then
+				region.result.status, region.result.reason = "failed", tostring(err)
+			end
+			unwritten = true
+		else
+			meta.written = true
+			-- the source keeps its lines, the region is clean until the next reload
+			if b_info.snapshots then
+				b_info.snapshots[region.s_idx] = vim.api.nvim_buf_get_lines(mb, region.r_start, region.r_end, true)
+			end
+		end
+	end
+
+	for i = #written, 1, -1 do
+		local buf = written[i]
+		local ok, err = pcall(apply_source_writes, buf, parts_by_buf[buf])
+		if not ok then
+			for _, region in ipairs(regions_by_buf[buf]) do
+				if region.result then
+					region.result.status, region.result.reason = "failed", tostring(err)
+				end
+			end
+			vim.notify(string.format("multibuffer: writing buffer %d failed: %s", buf, err), vim.log.levels.ERROR)
+			unwritten = true
+			table.remove(written, i)
+			regions_by_buf[buf] = {}
+		end
+	end
+
+	for _, buf in ipairs(written) do
+		-- coalesced writes collapse the source extmarks of adjacent regions, so
+		-- place every written region from the accumulated line deltas
+		local regions = regions_by_buf[buf]
+		table.sort(regions, function(a, b)
+			return a.s_start < b.s_start
+		end)
+		local shift = 0
+		for _, region in ipairs(regions) do
+			local b_info = region.b_info
+			b_info.source_extmark_ids[region.s_idx] =
+				vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, region.s_start + shift, 0, {
+					id = b_info.source_extmark_ids[region.s_idx],
+					end_row = region.s_end + shift + region.delta,
+					end_right_gravity = true,
+				})
+			-- and the multibuffer side exactly the lines that were written, so
+			-- lines added at the end of a region stay part of it
+			local rid = b_info.region_extmark_ids[region.s_idx]
+			if rid and region.r_end > region.r_start then
+				vim.api.nvim_buf_set_extmark(mb, M.multibuf__ns, region.r_start, 0, {
+					id = rid,
+					end_row = region.r_end,
+					end_right_gravity = true,
+				})
+			end
+			local meta = b_info.region_meta[region.s_idx]
+			if meta and region.span then
+				place_span_mark(buf, meta, region.s_start + shift, region.span[1], region.span[2])
+			elseif meta and region.span == false then
+				clear_span(buf, meta)
+			end
+			local after =
+				vim.api.nvim_buf_get_lines(buf, region.s_start + shift, region.s_end + shift + region.delta, true)
+			if meta then
+				meta.written = true
+				meta.last_write = {
+					before = region.before,
+					after = after,
+					tick = vim.api.nvim_buf_get_changedtick(buf),
+				}
+			end
+			-- later edits are made against what was written
+			if b_info.bases then
+				b_info.bases[region.s_idx] = capture_base(buf, after, region.s_start + shift)
+			end
+			-- the written lines are what the region shows now
+			if b_info.snapshots then
+				b_info.snapshots[region.s_idx] = vim.api.nvim_buf_get_lines(mb, region.r_start, region.r_end, true)
+			end
+			if not (info.pending_sources and info.pending_sources[buf]) then
+				b_info.changedtick = vim.api.nvim_buf_get_changedtick(buf)
+			end
+			shift = shift + region.delta
+		end
+	end
+
+	-- last first, so the indices of the other deleted regions stay valid
+	for _, region in ipairs(deleted) do
+		drop_region(mb, info, region.b_idx, region.s_idx)
+	end
+
+	vim.api.nvim_set_option_value("modified", unwritten, { buf = mb })
+
+	-- written and reverted regions are clean again
+	for _, b_info in ipairs(info.bufs) do
+		for s_idx, rid in ipairs(b_info.region_extmark_ids) do
+			if info.touched_marks and info.touched_marks[rid] and not region_is_dirty(mb, b_info, s_idx) then
+				info.touched_marks[rid] = nil
+			end
+		end
+	end
+
+	-- source syncs queued behind the edits that were just written
+	for buf in pairs(info.pending_sources or {}) do
+		if not scope or buf == scope then
+			if info.suspended_sources then
+				info.suspended_sources[buf] = nil
+			end
+			M.multibuf_reload(mb, { source = buf })
+		end
+	end
+
+	-- other multibuffers showing the changed buffers would keep the old lines
+	-- until their sources fire a sync event, and writing them would revert the
+	-- change. Their regions with unwritten edits are marked as conflicts.
+	local changed = vim.list_slice(written)
+	for _, target in ipairs(summary.targets) do
+		list_insert_unique(changed, target.buf)
+	end
+	for _, buf in ipairs(changed) do
+		local listener_info = buf_listeners[buf]
+		for _, other in ipairs(listener_info and vim.list_slice(listener_info.multibufs) or {}) do
+			if other ~= mb then
+				sync_source(other, buf, false)
+			end
+		end
+	end
+
+	update_region_status(mb)
+	render_dirty_signs(mb)
+	if changes > 0 then
+		run_write_hook("on_post_write", mb, summary)
+	end
+	return written, results
+end
+
+--- Apply the edits made in a multibuffer to its source buffers. Each source
+--- buffer changes in a single undo step. The sources are not written to disk.
+--- @param mb integer multibuf id
+--- @param opts MultibufWriteOptions|nil
+--- @return integer[] written source buffers that changed
+--- @return MultibufRegionWriteResult[] results what happened to each region of the written sources
+function M.multibuf_write(mb, opts)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return {}, {}
+	end
+	local written, results = run_guarded(mb, info, write_multibuf, mb, opts)
+	return written or {}, results or {}
+end
+
+--- Revert the last write of a region by putting back the source lines it
+--- replaced. Only possible while the source wasn't changed since that write
+--- and none of its regions have unwritten edits.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @return boolean ok, string|nil err why the write can't be reverted
+function M.multibuf_undo_region_write(mb, region_id)
+	vim.validate("region_id", region_id, "number")
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false, "not a multibuffer"
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return false, "region is not part of the multibuffer"
+	end
+	local b_info = info.bufs[b_idx]
+	local meta = b_info.region_meta[s_idx]
+	local last_write = meta and meta.last_write
+	if not last_write then
+		return false, "region was not written"
+	end
+
+	local buf = b_info.buf
+	local tick = vim.api.nvim_buf_get_changedtick(buf)
+	-- writing the source to disk resets 'modified', which counts as a change
+	local saved = tick == last_write.tick + 1 and not vim.bo[buf].modified
+	local s_start, s_end = get_extmark_range(buf, b_info.source_extmark_ids[s_idx])
+	if
+		not s_start
+		or (tick ~= last_write.tick and not saved)
+		or not vim.deep_equal(vim.api.nvim_buf_get_lines(buf, s_start, s_end, true), last_write.after)
+	then
+		return false, "source changed since the write"
+	end
+	for i = 1, #b_info.region_extmark_ids do
+		if region_is_dirty(mb, b_info, i) then
+			return false, "regions of the source have unwritten edits"
+		end
+	end
+
+	vim.api.nvim_buf_set_lines(buf, s_start, s_end, true, last_write.before)
+	b_info.source_extmark_ids[s_idx] = vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, s_start, 0, {
+		id = b_info.source_extmark_ids[s_idx],
+		end_row = s_start + #last_write.before,
+		end_right_gravity = true,
+	})
+	meta.last_write = nil
+	meta.written = nil
+	M.multibuf_reload(mb, { source = buf })
+	return true, nil
+end
+
+--- Write the edits of a region to another buffer or file instead of its
+--- source, e.g. a copy under `patches/`. Reloads still show the source.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @param target { buf: integer|nil, path: string|nil, start_row: integer|nil }|nil nil writes to the source again
+function M.multibuf_set_region_target(mb, region_id, target)
+	vim.validate("region_id", region_id, "number")
+	vim.validate("target", target, { "table", "nil" })
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		error(string.format("multibuffer: region %d is not part of the multibuffer", region_id), 2)
+	end
+	local b_info = info.bufs[b_idx]
+	local meta = b_info.region_meta[s_idx]
+
+	if not target then
+		meta.target = nil
+		return
+	end
+	vim.validate("target.buf", target.buf, { "number", "nil" })
+	vim.validate("target.path", target.path, { "string", "nil" })
+	vim.validate("target.start_row", target.start_row, { "number", "nil" })
+
+	local buf = target.buf or (target.path and vim.fn.bufadd(vim.fn.fnamemodify(target.path, ":p")))
+	if not buf or not vim.api.nvim_buf_is_valid(buf) then
+		error("multibuffer: target needs a valid `buf` or a `path`", 2)
+	end
+	if multibufs[buf] or buf == b_info.buf then
+		error("multibuffer: target must be a buffer other than the multibuffer and the source", 2)
+	end
+	-- a new target starts over instead of replacing the lines of the old one
+	if meta.target and meta.target.mark_id and meta.target.buf ~= buf then
+		pcall(vim.api.nvim_buf_del_extmark, meta.target.buf, M.multibuf__ns, meta.target.mark_id)
+	end
+	meta.target = {
+		buf = buf,
+		start_row = target.start_row,
+		mark_id = meta.target and meta.target.buf == buf and meta.target.mark_id or nil,
+	}
+	vim.bo[buf].buflisted = true
+end
+
+--- @return integer mb the active multibuffer, a new one if there is none
+local function get_or_create_active()
+	local mb = M.multibuf_get_active()
+	if not mb then
+		mb = M.create_multibuf()
+		M.multibuf_set_active(mb)
+	end
+	return mb
+end
+
+--- Add a range of lines of the current buffer to a multibuffer. When {mb} is
+--- nil the active multibuffer is used, creating one if there is none.
+--- @param mb integer|nil multibuf id
+--- @param start_row integer 0-indexed start row
+--- @param end_row integer 0-indexed end row (inclusive)
+--- @param opts { title: any[]|nil|MultibufTitleRenderFunction }|nil
+--- @return integer|nil mb the multibuffer the lines were added to
+local function add_current_buf_lines(mb, start_row, end_row, opts)
+	opts = opts or {}
+	local buf = vim.api.nvim_get_current_buf()
+	if multibufs[buf] then
+		vim.notify("multibuffer: cannot add lines of a multibuffer to a multibuffer", vim.log.levels.WARN)
+		return nil
+	end
+
+	mb = mb or get_or_create_active()
+	M.multibuf_add_buf(mb, {
+		buf = buf,
+		regions = { { start_row = math.min(start_row, end_row), end_row = math.max(start_row, end_row) } },
+		title = opts.title,
+	})
+	return mb
+end
+
+--- Parse the 1-indexed `start-end` or `line` answer of the `:Multibuf addbuf`
+--- prompt.
+--- @param text string
+--- @param line_count integer
+--- @return integer|nil start_row, integer|nil end_row 0-indexed, inclusive
+--- @return string|nil err
+local function parse_line_range(text, line_count)
+	local first, last = text:match("^%s*(%d+)%s*[-,]%s*(%d+)%s*$")
+	if not first then
+		first = text:match("^%s*(%d+)%s*$")
+		last = first
+	end
+	if not first then
+		return nil, nil, string.format("%q is not a line range, expected e.g. 10-20", text)
+	end
+	local start_row, end_row = tonumber(first) - 1, tonumber(last) - 1
+	if start_row > end_row then
+		start_row, end_row = end_row, start_row
+	end
+	if start_row < 0 or end_row >= line_count then
+		return nil, nil, string.format("lines %s are outside 1-%d", text, line_count)
+	end
+	return start_row, end_row, nil
+end
+
+--- Pick a loaded buffer and a range of its lines and add them to the active
+--- multibuffer.
+local function select_add_buf()
+	local bufs = vim.tbl_filter(function(buf)
+		return vim.api.nvim_buf_is_loaded(buf) and vim.bo[buf].buflisted and not multibufs[buf]
+	end, vim.api.nvim_list_bufs())
+	if #bufs == 0 then
+		vim.notify("multibuffer: no loaded buffers to add", vim.log.levels.WARN)
+		return
+	end
+	vim.ui.select(bufs, {
+		prompt = "Add buffer",
+		format_item = function(buf)
+			local name = vim.api.nvim_buf_get_name(buf)
+			return name ~= "" and vim.fn.fnamemodify(name, ":~:.") or string.format("[No Name] %d", buf)
+		end,
+	}, function(buf)
+		if not buf or not vim.api.nvim_buf_is_valid(buf) then
+			return
+		end
+		local line_count = vim.api.nvim_buf_line_count(buf)
+		local default = string.format("1-%d", line_count)
+		local visual_start, visual_end = vim.api.nvim_buf_get_mark(buf, "<"), vim.api.nvim_buf_get_mark(buf, ">")
+		if visual_start[1] > 0 and visual_end[1] > 0 then
+			default = string.format("%d-%d", visual_start[1], visual_end[1])
+		end
+		vim.ui.input({ prompt = "Lines: ", default = default }, function(text)
+			if not text then
+				return
+			end
+			local start_row, end_row, err = parse_line_range(text, vim.api.nvim_buf_line_count(buf))
+			if err then
+				vim.notify("multibuffer: " .. err, vim.log.levels.ERROR)
+				return
+			end
+			local ok, add_err = pcall(M.multibuf_add_buf, get_or_create_active(), {
+				buf = buf,
+				regions = { { start_row = start_row, end_row = end_row } },
+			})
+			if not ok then
+				vim.notify(tostring(add_err), vim.log.levels.ERROR)
+			end
+		end)
+	end)
+end
+
+--- `path:start-end`, the `origin` of the region when it is a string, its
+--- first line and its label, as listed by the region pickers.
+--- @param region MultibufRegionInfo
+--- @return string
+local function format_region_item(region)
+	local item = string.format(
+		"%s:%d-%d",
+		vim.fn.fnamemodify(vim.api.nvim_buf_get_name(region.buf), ":~:."),
+		region.start_row + 1,
+		region.end_row + 1
+	)
+	if type(region.origin) == "string" then
+		item = item .. "  [" .. region.origin .. "]"
+	end
+	local first = vim.api.nvim_buf_get_lines(region.buf, region.start_row, region.start_row + 1, false)[1]
+	if first and vim.trim(first) ~= "" then
+		item = item .. "  " .. vim.trim(first)
+	end
+	if region.label then
+		item = item .. "  (" .. region.label .. ")"
+	end
+	return item
+end
+
+--- Pick regions of the current or active multibuffer to remove. The list
+--- comes back after every removal until it is dismissed.
+local function select_remove_regions()
+	local mb = M.multibuf_handle_of(vim.api.nvim_get_current_buf()) or M.multibuf_get_active()
+	if not mb then
+		vim.notify("multibuffer: no multibuffer to remove regions from", vim.log.levels.WARN)
+		return
+	end
+	local regions = M.multibuf_get_regions(mb)
+	if #regions == 0 then
+		vim.notify("multibuffer: no regions left", vim.log.levels.INFO)
+		return
+	end
+	vim.ui.select(regions, {
+		prompt = "Remove region",
+		format_item = format_region_item,
+	}, function(region)
+		if not region or not M.multibuf_is_valid(mb) then
+			return
+		end
+		if not M.multibuf_remove_region(mb, region.id) then
+			vim.notify(string.format("multibuffer: region %d is already gone", region.id), vim.log.levels.WARN)
+		end
+		select_remove_regions()
+	end)
+end
+
+--- Pick a multibuffer to show, jumping to a window already showing it.
+local function select_multibuf()
+	local entries = M.multibuf_list()
+	if #entries == 0 then
+		vim.notify("multibuffer: no multibuffers", vim.log.levels.WARN)
+		return
+	end
+	vim.ui.select(entries, {
+		prompt = "Multibuffer",
+		format_item = function(entry)
+			return string.format("%s %s (%d source(s))", entry.active and "%" or " ", entry.name, entry.source_count)
+		end,
+	}, function(entry)
+		if not entry or not M.multibuf_is_valid(entry.buf) then
+			return
+		end
+		local win = get_buf_win(entry.buf)
+		if win then
+			vim.api.nvim_set_current_win(win)
+		else
+			M.win_set_multibuf(0, entry.buf)
+		end
+		M.multibuf_set_active(entry.buf)
+	end)
+end
+
+--- Subcommands of `:Multibuf`
+--- @type table<string, { run: fun(args: table) }>
+local multibuf_commands = {
+	add = {
+		run = function(args)
+			local mb = add_current_buf_lines(nil, args.line1 - 1, args.line2 - 1)
+			if mb and not get_buf_win(mb) then
+				vim.notify(string.format("multibuffer: added lines %d-%d", args.line1, args.line2))
+			end
+		end,
+	},
+	addbuf = {
+		run = select_add_buf,
+	},
+	remove = {
+		run = select_remove_regions,
+	},
+	pick = {
+		run = select_multibuf,
+	},
+	new = {
+		run = function()
+			local mb = M.create_multibuf()
+			vim.api.nvim_win_set_buf(0, mb)
+			M.multibuf_set_active(mb)
+		end,
+	},
+	list = {
+		run = function()
+			local lines = {}
+			for _, entry in ipairs(M.multibuf_list()) do
+				table.insert(
+					lines,
+					string.format("%s %3d %d source(s)", entry.active and "%" or " ", entry.buf, entry.source_count)
+				)
+			end
+			vim.api.nvim_echo({ { table.concat(lines, "\n") } }, false, {})
+		end,
+	},
+}
+
+--- Checks for the setup options. `check` returns an error message for values
+--- of the right type that are still invalid. Highlight groups are only
+--- warned about since a colorscheme may define them after setup.
+--- @class MultibufOptionSchema
+--- @field type string
+--- @field check (fun(v: any): string|nil)|nil
+--- @field hl boolean|nil Warn when the highlight group doesn't exist
+--- @field allow_false boolean|nil Also accept false, e.g. to disable a table option
+
+--- @type table<string, MultibufOptionSchema>
+local setup_schema = {
+	render_multibuf_title = { type = "function" },
+	render_expand_lines = { type = "function" },
+	expander_max_lines = {
+		type = "number",
+		check = function(v)
+			if v < 0 or v ~= math.floor(v) then
+				return "must be a non-negative integer"
+			end
+		end,
+	},
+	expander_signs = {
+		type = "table",
+		check = function(v)
+			for key, sign in pairs(v) do
+				if key ~= "above" and key ~= "below" and key ~= "both" then
+					return string.format("unknown key %q, expected 'above', 'below' or 'both'", key)
+				end
+				if type(sign) ~= "string" then
+					return string.format("%s must be a string", key)
+				end
+			end
+		end,
+	},
+	expander_sign_hl = { type = "string", hl = true },
+	region_hl_even = { type = "string", hl = true },
+	region_hl_odd = { type = "string", hl = true },
+	region_hl_odd_adjust = {
+		type = "number",
+		check = function(v)
+			if v < 0 or v > 255 then
+				return "must be between 0 and 255"
+			end
+		end,
+	},
+	fold_regions = { type = "boolean" },
+	peek_on_hold = { type = "boolean" },
+	max_lines_per_region = {
+		type = "number",
+		check = function(v)
+			if v < 2 or v ~= math.floor(v) then
+				return "must be an integer of at least 2"
+			end
+		end,
+	},
+	region_padding = {
+		type = "number",
+		check = function(v)
+			if v < 0 or v ~= math.floor(v) then
+				return "must be a non-negative integer"
+			end
+		end,
+	},
+	name_format = { type = "function" },
+	on_pre_write = { type = "function" },
+	on_post_write = { type = "function" },
+	window_options = {
+		type = "table",
+		allow_false = true,
+		check = function(v)
+			for name in pairs(v) do
+				local ok, info = pcall(vim.api.nvim_get_option_info2, name, {})
+				if not ok or info.scope ~= "win" then
+					return string.format("%s is not a window option", vim.inspect(name))
+				end
+			end
+		end,
+	},
+	titles = {
+		type = "string",
+		check = function(v)
+			if v ~= "auto" and v ~= "always" and v ~= "first_per_file" and v ~= "never" then
+				return "must be 'auto', 'always', 'first_per_file' or 'never'"
+			end
+		end,
+	},
+	auto_merge = { type = "boolean" },
+	auto_remove_empty_regions = { type = "boolean" },
+	auto_write = { type = "boolean" },
+	delete_region_behavior = {
+		type = "string",
+		check = function(v)
+			if v ~= "remove" and v ~= "delete_source" and v ~= "ask" then
+				return "must be 'remove', 'delete_source' or 'ask'"
+			end
+		end,
+	},
+	line_number_width = {
+		type = "string",
+		check = function(v)
+			if v ~= "uniform" and v ~= "region" then
+				return "must be 'uniform' or 'region'"
+			end
+		end,
+	},
+	line_number_mode = {
+		type = "string",
+		check = function(v)
+			if v ~= "signs" and v ~= "virt_text" and v ~= "inline" then
+				return "must be 'signs', 'virt_text' or 'inline'"
+			end
+		end,
+	},
+	dirty_signs = { type = "boolean" },
+	dirty_sign = {
+		type = "string",
+		check = function(v)
+			local width = vim.fn.strdisplaywidth(v)
+			if width < 1 or width > 2 then
+				return "must be 1 or 2 cells wide"
+			end
+		end,
+	},
+	dirty_sign_hl = { type = "string", hl = true },
+	show_region_index = { type = "boolean" },
+	layout = {
+		type = "string",
+		check = function(v)
+			if v ~= "rows" and v ~= "columns" then
+				return "must be 'rows' or 'columns'"
+			end
+		end,
+	},
+	highlight = {
+		type = "string",
+		check = function(v)
+			if v ~= "live" and v ~= "mirror" then
+				return "must be 'live' or 'mirror'"
+			end
+		end,
+	},
+	region_index_hl = { type = "string", hl = true },
+	sync_poll_ms = {
+		type = "number",
+		check = function(v)
+			if v < 0 or v % 1 ~= 0 then
+				return "must be a non-negative integer"
+			end
+		end,
+	},
+	sync_events = {
+		type = "table",
+		check = function(v)
+			for _, event in ipairs(v) do
+				if type(event) ~= "string" or vim.fn.exists("##" .. event) ~= 1 then
+					return string.format("%s is not an autocmd event", vim.inspect(event))
+				end
+			end
+		end,
+	},
+}
+
+--- @param opts table
+--- @return string[] errors, string[] warnings
+local function validate_setup_opts(opts)
+	local errors, warnings = {}, {}
+	local keys = vim.tbl_keys(opts)
+	table.sort(keys)
+	for _, key in ipairs(keys) do
+		local value = opts[key]
+		local schema = setup_schema[key]
+		if not schema then
+			table.insert(errors, string.format("%s: unknown option", key))
+		elseif type(value) ~= schema.type and not (schema.allow_false and value == false) then
+			table.insert(errors, string.format("%s: expected %s, got %s", key, schema.type, type(value)))
+		elseif value ~= false then
+			local err = schema.check and schema.check(value)
+			if err then
+				table.insert(errors, string.format("%s: %s", key, err))
+			end
+			-- the plugin's own groups are defined below
+			if schema.hl and not vim.startswith(value, "Multibuf") and vim.fn.hlexists(value) == 0 then
+				table.insert(warnings, string.format("%s: highlight group %q does not exist", key, value))
+			end
+		end
+	end
+	return errors, warnings
+end
+
+--- The `window_options` with the fold options of `fold_regions` added.
+--- @return table<string, any>
+--- @param info MultibufInfo|nil
+local function get_window_options(info)
+	local options = M.user_opts.window_options
+	options = options and vim.deepcopy(options) or {}
+	if M.user_opts.window_options ~= false and M.user_opts.fold_regions and options.foldmethod == nil then
+		options.foldmethod = "expr"
+		options.foldexpr = "v:lua.require'multibuffer'.multibuf_foldexpr(v:lnum)"
+	end
+	-- wrapped lines continue below the indentation of their line
+	if info and info.wrap then
+		options.wrap = true
+		options.breakindent = true
+		if options.showbreak == nil then
+			options.showbreak = "↪ "
+		end
+	end
+	return options
+end
+
+--- @param win integer
+local function restore_window_options(win)
+	local saved = vim.w[win].multibuffer_saved_options
+	if not saved then
+		return
+	end
+	for name, value in pairs(saved) do
+		pcall(vim.api.nvim_set_option_value, name, value, { win = win, scope = "local" })
+	end
+	vim.w[win].multibuffer_saved_options = nil
+end
+
+--- Apply the `window_options` of multibuffer {mb} to a window showing it,
+--- saving the values they replace. Moving between multibuffers keeps the
+--- first saved values.
+--- @param win integer
+--- @param mb integer
+local function apply_window_options(win, mb)
+	-- options of the multibuffer shown before, e.g. one with `wrap`
+	restore_window_options(win)
+	local saved = {}
+	for name, value in pairs(get_window_options(multibufs[mb])) do
+		saved[name] = vim.api.nvim_get_option_value(name, { win = win })
+		vim.api.nvim_set_option_value(name, value, { win = win, scope = "local" })
+	end
+	vim.w[win].multibuffer_saved_options = saved
+end
+
+--- @param opts MultibufSetupOptions
+function M.setup(opts)
+	opts = opts or {}
+	local errors, warnings = validate_setup_opts(opts)
+	if #errors > 0 then
+		error("multibuffer: invalid setup options:\n  " .. table.concat(errors, "\n  "), 2)
+	end
+	for _, warning in ipairs(warnings) do
+		vim.notify("multibuffer: " .. warning, vim.log.levels.WARN)
+	end
+
+	M.user_opts = vim.tbl_deep_extend("force", M.user_opts, opts)
+	-- lists are replaced rather than merged by index
+	if opts.sync_events then
+		M.user_opts.sync_events = opts.sync_events
+	end
+	if opts.sync_events or opts.sync_poll_ms then
+		for buf, listener_info in pairs(buf_listeners) do
+			stop_buf_listener(listener_info)
+			listener_info.change_autocmd_id = create_sync_autocmd(buf)
+			listener_info.poll_timer = create_sync_poll(buf)
+		end
+	end
+	M.multibuf__ns = vim.api.nvim_create_namespace("Multibuf")
+	M.multibuf_structure_ns = vim.api.nvim_create_namespace("MultibufStructure")
+	M.multibuf_hl_ns = vim.api.nvim_create_namespace("MultibufHighlights")
+	M.multibuf_number_ns = vim.api.nvim_create_namespace("MultibufNumbers")
+	M.multibuf_mirror_ns = vim.api.nvim_create_namespace("MultibufMirror")
+	M.multibuf_dirty_ns = vim.api.nvim_create_namespace("MultibufDirty")
+	M.multibuf_outline_ns = vim.api.nvim_create_namespace("MultibufOutline")
+	M.multibuf_hl_mirror_ns = vim.api.nvim_create_namespace("MultibufHighlightMirror")
+	if opts.dirty_signs ~= nil or opts.dirty_sign or opts.dirty_sign_hl then
+		for mb in pairs(multibufs) do
+			render_dirty_signs(mb)
+		end
+	end
+	if
+		opts.show_region_index ~= nil
+		or opts.region_index_hl
+		or opts.line_number_mode
+		or opts.layout
+		or opts.highlight
+	then
+		for mb in pairs(multibufs) do
+			M.multibuf_reload(mb, { keep_edits = true })
+		end
+	end
+
+	local function update_highlights()
+		-- Define default highlight groups
+		-- We use 'default = true' so users can override them in their config,
+		-- but we always attempt to define them to ensure they exist and are up to date.
+		vim.api.nvim_set_hl(0, "MultibufRegionEven", { link = "Normal", default = true })
+		vim.api.nvim_set_hl(0, "MultibufElision", { link = "Comment", default = true })
+		vim.api.nvim_set_hl(0, "MultibufHeader", { link = "Title", default = true })
+		vim.api.nvim_set_hl(0, "MultibufHeaderIcon", { link = "Special", default = true })
+		vim.api.nvim_set_hl(0, "MultibufTitle", { link = "TabLine", default = true })
+		vim.api.nvim_set_hl(0, "MultibufLineNr", { link = "LineNr", default = true })
+		vim.api.nvim_set_hl(0, "MultibufSeparator", { link = "Folded", default = true })
+		vim.api.nvim_set_hl(0, "MultibufMatch", { link = "Search", default = true })
+		vim.api.nvim_set_hl(0, "MultibufContext", { link = "Comment", default = true })
+		vim.api.nvim_set_hl(0, "MultibufRegionReadonly", { link = "Comment", default = true })
+		vim.api.nvim_set_hl(0, "MultibufRegionConflict", { link = "DiffDelete", default = true })
+		vim.api.nvim_set_hl(0, "MultibufRegionDirty", { link = "Changed", default = true })
+		vim.api.nvim_set_hl(0, "MultibufRegionIndex", { link = "Special", default = true })
+		vim.api.nvim_set_hl(0, "MultibufSourceColumn", { link = "Directory", default = true })
+
+		local normal = vim.api.nvim_get_hl(0, { name = "Normal", link = false })
+		if normal.bg then
+			-- Always try to make it darker (subtract from RGB values)
+			local amount = -(M.user_opts.region_hl_odd_adjust or 12)
+			-- If background is already extremely dark (black), we have to go lighter
+			if vim.o.background == "dark" and normal.bg < 0x101010 then
+				amount = math.abs(amount)
+			end
+			local new_bg = adjust_color(normal.bg, amount)
+			vim.api.nvim_set_hl(0, "MultibufRegionOdd", { bg = new_bg, default = true })
+		else
+			vim.api.nvim_set_hl(0, "MultibufRegionOdd", { link = "CursorLine", default = true })
+		end
+
+		local function get_hl_attr(name, attr)
+			local hl = vim.api.nvim_get_hl(0, { name = name, link = false })
+			if hl[attr] then
+				return hl[attr]
+			end
+			-- fallback to Normal
+			if name ~= "Normal" then
+				return normal[attr]
+			end
+			return nil
+		end
+
+		-- These groups MUST be redefined on every colorscheme change because they
+		-- depend on the colors of Normal, MultibufLineNr, and Folded which just changed.
+		local even_bg = get_hl_attr("MultibufRegionEven", "bg")
+		local odd_bg = get_hl_attr("MultibufRegionOdd", "bg")
+		local line_nr_fg = get_hl_attr("MultibufLineNr", "fg")
+		local line_nr_dim_fg = get_hl_attr("Comment", "fg")
+		local folded_fg = get_hl_attr(M.user_opts.expander_sign_hl or "Folded", "fg")
+		local line_nr = vim.api.nvim_get_hl(0, { name = "MultibufLineNr", link = false })
+		local folded = vim.api.nvim_get_hl(0, { name = M.user_opts.expander_sign_hl or "Folded", link = false })
+		local cursor_line = vim.api.nvim_get_hl(0, { name = "CursorLine", link = false })
+
+		vim.api.nvim_set_hl(0, "MultibufLineNrEven", { fg = line_nr_fg, bg = even_bg, bold = line_nr.bold })
+		vim.api.nvim_set_hl(0, "MultibufLineNrOdd", { fg = line_nr_fg, bg = odd_bg, bold = line_nr.bold })
+		vim.api.nvim_set_hl(0, "MultibufLineNrDimEven", { fg = line_nr_dim_fg, bg = even_bg })
+		vim.api.nvim_set_hl(0, "MultibufLineNrDimOdd", { fg = line_nr_dim_fg, bg = odd_bg })
+		vim.api.nvim_set_hl(0, "MultibufExpanderEven", { fg = folded_fg, bg = even_bg, bold = folded.bold })
+		vim.api.nvim_set_hl(0, "MultibufExpanderOdd", { fg = folded_fg, bg = odd_bg, bold = folded.bold })
+
+		-- Sign column highlights when cursor is on the line (CursorLine background)
+		vim.api.nvim_set_hl(0, "MultibufLineNrCursorLine", {
+			fg = line_nr_fg,
+			bg = cursor_line.bg,
+			bold = line_nr.bold,
+			sp = cursor_line.sp,
+			special = cursor_line.special,
+		})
+		vim.api.nvim_set_hl(0, "MultibufExpanderCursorLine", {
+			fg = folded_fg,
+			bg = cursor_line.bg,
+			bold = folded.bold,
+			sp = cursor_line.sp,
+			special = cursor_line.special,
+		})
+	end
+
+	update_highlights()
+
+	vim.api.nvim_create_autocmd({ "ColorScheme", "OptionSet" }, {
+		callback = function(args)
+			if args.event == "OptionSet" and args.match ~= "background" then
+				return
+			end
+			update_highlights()
+		end,
+	})
+
+	-- sources edited while autocmds were ignored or in another program
+	vim.api.nvim_create_autocmd("FocusGained", {
+		callback = function()
+			for mb in pairs(multibufs) do
+				sync_stale_sources(mb)
+			end
+		end,
+	})
+
+	vim.api.nvim_create_autocmd("WinScrolled", {
+		callback = function(args)
+			local win = tonumber(args.match)
+			if not win or not vim.api.nvim_win_is_valid(win) then
+				return
+			end
+			local buf = vim.api.nvim_win_get_buf(win)
+			local info = multibufs[buf]
+			if info and (info.number_mode or "absolute") ~= "absolute" then
+				render_relative_line_numbers(buf, win)
+			end
+		end,
+	})
+
+	-- options of the previous buffer (e.g. spell, a foldexpr) break the rendering
+	vim.api.nvim_create_autocmd("BufWinEnter", {
+		callback = function(args)
+			local win = vim.api.nvim_get_current_win()
+			if vim.api.nvim_win_get_buf(win) ~= args.buf then
+				return
+			end
+			if multibufs[args.buf] then
+				apply_window_options(win, args.buf)
+			else
+				restore_window_options(win)
+			end
+		end,
+	})
+
+	-- a session can't restore a multibuffer, don't leave its name behind as an empty buffer
+	vim.api.nvim_create_autocmd("BufReadCmd", {
+		pattern = "multibuf://*",
+		callback = function(args)
+			if not multibufs[args.buf] then
+				vim.bo[args.buf].buftype = "nofile"
+				vim.bo[args.buf].bufhidden = "wipe"
+				vim.bo[args.buf].buflisted = false
+			end
+		end,
+	})
+
+	vim.api.nvim_create_user_command("Multibuf", function(args)
+		local subcommand = multibuf_commands[args.fargs[1] or "list"]
+		if not subcommand then
+			vim.notify("multibuffer: unknown subcommand " .. args.fargs[1], vim.log.levels.ERROR)
+			return
+		end
+		subcommand.run(args)
+	end, {
+		nargs = "*",
+		range = true,
+		complete = function(arg_lead, cmdline)
+			if #vim.split(cmdline, "%s+", { trimempty = false }) > 2 then
+				return {}
+			end
+			local names = vim.tbl_keys(multibuf_commands)
+			table.sort(names)
+			return vim.tbl_filter(function(name)
+				return vim.startswith(name, arg_lead)
+			end, names)
+		end,
+	})
+
+	-- Decoration provider mirrors source highlights into the multibuffer viewport
+	local function incremental_load_source_and_update(winid, multibuf, top, bot)
+		if not M.multibuf_is_valid(multibuf) then
+			return false
+		end
+		local info = multibufs[multibuf]
+		if not info then
+			return false
+		end
+
+		local need_loadbufs = {}
+
+		local slice_lookup = build_slice_lookup(info)
+
+		local cursor_row = -1
+		pcall(function()
+			cursor_row = vim.api.nvim_win_get_cursor(winid)[1] - 1
+		end)
+
+		local slices = vim.api.nvim_buf_get_extmarks(
+			multibuf,
+			M.multibuf__ns,
+			{ top, 0 },
+			{ bot, -1 },
+			{ details = true, overlap = true }
+		)
+		for _, extmark in ipairs(slices) do
+			local lookup = slice_lookup[extmark[1]]
+			if lookup then
+				local b_info = lookup.b_info
+				local i = lookup.slice_idx
+				local region_hl = lookup.region_hl
+
+				if b_info.pending_regions then
+					if not b_info.loading then
+						b_info.loading = true
+						table.insert(need_loadbufs, b_info)
+					end
+					goto next_extmark
+				end
+
+				local r_start, r_end = get_extmark_range(multibuf, extmark[1])
+				if not r_start then
+					goto next_extmark
+				end
+
+				local v_start, v_end = math.max(top, r_start), math.min(bot + 1, r_end)
+				if v_start < v_end then
+					-- 1. Apply alternating background (skipping cursor line)
+					local function set_region_hl(s, e)
+						if s >= e then
+							return
+						end
+						pcall(vim.api.nvim_buf_set_extmark, multibuf, M.multibuf_hl_ns, s, 0, {
+							end_row = e,
+							hl_group = region_hl,
+							hl_eol = true,
+							line_hl_group = region_hl,
+							ephemeral = true,
+							priority = 0,
+						})
+					end
+
+					if cursor_row >= v_start and cursor_row < v_end then
+						set_region_hl(v_start, cursor_row)
+						set_region_hl(cursor_row + 1, v_end)
+					else
+						set_region_hl(v_start, v_end)
+					end
+
+					-- Context lines are read-only, dim them over the projected highlights
+					local context = b_info.region_meta[i] and b_info.region_meta[i].context
+					if context then
+						local context_rows = { { r_start, r_start + context.before }, { r_end - context.after, r_end } }
+						for _, rows in ipairs(context_rows) do
+							local s, e = math.max(v_start, rows[1]), math.min(v_end, rows[2])
+							if s < e then
+								pcall(vim.api.nvim_buf_set_extmark, multibuf, M.multibuf_hl_ns, s, 0, {
+									end_row = e,
+									hl_group = "MultibufContext",
+									hl_eol = true,
+									ephemeral = true,
+									priority = 10000,
+								})
+							end
+						end
+					end
+
+					-- 2. Project highlights from source, copied on reload instead with `highlight = "mirror"`
+					local s_ext_id = b_info.source_extmark_ids[i]
+					if s_ext_id and M.user_opts.highlight ~= "mirror" then
+						local s_start, s_end = get_extmark_range(b_info.buf, s_ext_id)
+						if s_start and s_end then
+							local s_ft = vim.api.nvim_get_option_value("filetype", { buf = b_info.buf })
+							local s_lang = vim.treesitter.language.get_lang(s_ft)
+							if s_lang then
+								-- the head and tail of an elided region map to separate source spans
+								local tail_row = get_elision_tail_row(multibuf, b_info, i) or r_end
+								local segments = {
+									{ math.max(v_start, r_start), math.min(v_end, tail_row) },
+									{ math.max(v_start, tail_row), v_end },
+								}
+								for _, segment in ipairs(segments) do
+									if segment[1] < segment[2] then
+										local s_range_start = region_row_to_source(
+											multibuf,
+											b_info,
+											i,
+											segment[1],
+											r_start,
+											r_end,
+											s_start,
+											s_end
+										)
+										local s_range_end = s_range_start + (segment[2] - segment[1])
+										project_highlights(
+											multibuf,
+											b_info.buf,
+											s_range_start,
+											s_range_end,
+											segment[1],
+											get_col_offset(b_info.region_meta[i])
+										)
+									end
+								end
+							end
+						end
+					end
+				end
+			end
+			::next_extmark::
+		end
+
+		-- Reload to replace placeholders with real content
+		if #need_loadbufs > 0 then
+			vim.schedule(function()
+				-- wiped out in the meantime, don't start listening to its sources again
+				if not M.multibuf_is_valid(multibuf) then
+					return
+				end
+				for _, b_info in ipairs(need_loadbufs) do
+					load_source_buf(multibuf, b_info)
+				end
+				M.multibuf_reload(multibuf)
+			end)
+		end
+
+		return true
+	end
+
+	vim.api.nvim_set_decoration_provider(M.multibuf_hl_ns, {
+		on_win = function(_, winid, multibuf, top, bot)
+			return incremental_load_source_and_update(winid, multibuf, top, bot)
+		end,
+	})
+end
+
+--- @class CreateMultibufOptions
+--- @field header string[]|nil
+--- @field title string|nil Used for the buffer name and |b:multibuffer_title|
+--- @field titles MultibufTitleMode|nil Overrides the `titles` setup option
+--- @field wrap boolean|nil Soft wrap long lines, see |multibuf_set_wrap|
+--- @field auto_write boolean|nil Overrides the `auto_write` setup option
+
+--- Name a multibuffer after its title. A name taken by another buffer gets a
+--- counter appended, a name that can't be set at all falls back to the
+--- handle.
+--- @param mb integer
+--- @param title string|nil
+--- @return string name the name that was set
+local function set_multibuf_name(mb, title)
+	local name
+	if M.user_opts.name_format then
+		local ok, result = pcall(M.user_opts.name_format, mb, title)
+		if ok and type(result) == "string" and result ~= "" then
+			name = result
+		else
+			vim.notify("multibuffer: name_format failed: " .. tostring(result), vim.log.levels.ERROR)
+		end
+	end
+	-- control characters (e.g. newlines from a search pattern) can't be part of a name
+	name = (name or ("multibuf://" .. (title or tostring(mb)))):gsub("%c", " ")
+
+	local old_name = vim.api.nvim_buf_get_name(mb)
+	local candidate = name
+	local named = false
+	for n = 2, 100 do
+		if candidate == old_name or pcall(vim.api.nvim_buf_set_name, mb, candidate) then
+			named = true
+			break
+		end
+		candidate = string.format("%s (%d)", name, n)
+	end
+	if not named and old_name == "" then
+		pcall(vim.api.nvim_buf_set_name, mb, "multibuf://" .. mb)
+	end
+
+	-- renaming leaves the old name behind as an unlisted alternate buffer
+	if old_name ~= "" and old_name ~= vim.api.nvim_buf_get_name(mb) then
+		for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+			if buf ~= mb and not vim.bo[buf].buflisted and vim.api.nvim_buf_get_name(buf) == old_name then
+				pcall(vim.api.nvim_buf_delete, buf, { force = true })
+			end
+		end
+	end
+
+	multibufs[mb].title = title
+	vim.b[mb].multibuffer_title = title
+	return vim.api.nvim_buf_get_name(mb)
+end
+
+--- @param opts CreateMultibufOptions|nil
+--- @return integer mbufnr, string name the buffer name, see `name_format`
+function M.create_multibuf(opts)
+	opts = opts or {}
+	vim.validate("opts.header", opts.header, { "table", "nil" })
+	vim.validate("opts.title", opts.title, { "string", "nil" })
+	vim.validate("opts.titles", opts.titles, { "string", "nil" })
+	vim.validate("opts.wrap", opts.wrap, { "boolean", "nil" })
+	vim.validate("opts.auto_write", opts.auto_write, { "boolean", "nil" })
+
+	local id = vim.api.nvim_create_buf(true, true)
+	local header = opts.header or create_multibuf_header()
+	local info = { bufs = {}, header = header, title_mode = opts.titles, wrap = opts.wrap }
+	vim.api.nvim_set_option_value("buftype", "acwrite", { buf = id })
+	-- a 'bufhidden' of "wipe" leaking from a window plugin would drop it with its window
+	vim.api.nvim_set_option_value("bufhidden", "hide", { buf = id })
+	vim.api.nvim_set_option_value("filetype", "multibuffer", { buf = id })
+	vim.api.nvim_set_option_value("modifiable", false, { buf = id })
+	vim.b[id].multibuffer = true
+	multibufs[id] = info
+	local name = set_multibuf_name(id, opts.title)
+
+	vim.api.nvim_create_autocmd("BufReadCmd", {
+		buffer = id,
+		callback = function(args)
+			pcall(vim.treesitter.stop, args.buf)
+			M.multibuf_reload(args.buf)
+		end,
+	})
+	-- without it saving is left to the user, e.g. their own BufWriteCmd
+	local auto_write = opts.auto_write
+	if auto_write == nil then
+		auto_write = M.user_opts.auto_write ~= false
+	end
+	if auto_write then
+		vim.api.nvim_create_autocmd("BufWriteCmd", {
+			buffer = id,
+			callback = function(args)
+				pcall(vim.treesitter.stop, args.buf)
+				M.multibuf_write(args.buf)
+				-- a cancelled write keeps the edits
+				if not vim.bo[args.buf].modified then
+					M.multibuf_reload(args.buf)
+				end
+			end,
+		})
+	end
+	vim.api.nvim_create_autocmd("BufWipeout", {
+		buffer = id,
+		callback = function(args)
+			M.multibuf__wipeout(args.buf)
+		end,
+	})
+	vim.api.nvim_create_autocmd("BufEnter", {
+		buffer = id,
+		callback = function(args)
+			M.multibuf_set_active(args.buf)
+			sync_stale_sources(args.buf)
+		end,
+	})
+	vim.api.nvim_create_autocmd("CursorHold", {
+		buffer = id,
+		callback = function(args)
+			if M.user_opts.peek_on_hold and not peek_wins[args.buf] then
+				M.multibuf_peek(args.buf)
+			end
+		end,
+	})
+	vim.api.nvim_create_autocmd({ "TextChanged", "TextChangedI" }, {
+		buffer = id,
+		callback = function(args)
+			schedule_dirty_signs(args.buf)
+		end,
+	})
+	vim.api.nvim_create_autocmd({ "CursorMoved", "CursorMovedI" }, {
+		buffer = id,
+		callback = function(args)
+			local mb_info = multibufs[args.buf]
+			if mb_info and (mb_info.number_mode or "absolute") ~= "absolute" then
+				render_relative_line_numbers(args.buf, vim.api.nvim_get_current_win())
+			end
+		end,
+	})
+	-- edits only recheck the status of the regions they touched, once per tick
+	vim.api.nvim_buf_attach(id, false, {
+		on_lines = function(_, buf, _, first, last_old, last_new)
+			local mb_info = multibufs[buf]
+			if not mb_info then
+				return true
+			end
+			-- regions next to the change count too, only the touched ones are compared on write
+			if mb_info.touched_marks then
+				local ok, marks = pcall(
+					vim.api.nvim_buf_get_extmarks,
+					buf,
+					M.multibuf__ns,
+					{ math.max(0, first - 1), 0 },
+					{ math.max(last_old, last_new) + 1, 0 },
+					{ overlap = true }
+				)
+				for _, mark in ipairs(ok and marks or {}) do
+					mb_info.touched_marks[mark[1]] = true
+				end
+			end
+			local touched = mb_info.touched_rows
+			if touched then
+				touched[1], touched[2] = math.min(touched[1], first), math.max(touched[2], last_new)
+				return
+			end
+			mb_info.touched_rows = { first, last_new }
+			vim.schedule(function()
+				local rows = mb_info.touched_rows
+				mb_info.touched_rows = nil
+				if rows and multibufs[buf] and vim.api.nvim_buf_is_valid(buf) then
+					update_region_status(buf, rows[1], rows[2])
+					reanchor_titles(buf, mb_info)
+					if M.user_opts.delete_region_behavior == "remove" then
+						local deleted = find_deleted_regions(buf, mb_info)
+						for _, region in ipairs(deleted) do
+							drop_region(buf, mb_info, region.b_idx, region.s_idx)
+						end
+						if #deleted > 0 then
+							M.multibuf_reload(buf, { keep_edits = true })
+						end
+					end
+				end
+			end)
+		end,
+	})
+
+	return id, name
+end
+
+--- Rename a multibuffer after {title}, see `name_format`.
+--- @param mb integer
+--- @param title string|nil
+--- @return string|nil name the buffer name that was set
+function M.multibuf_rename(mb, title)
+	vim.validate("title", title, { "string", "nil" })
+	if resolve_multibuf(mb) then
+		return set_multibuf_name(mb, title)
+	end
+	return nil
+end
+
+--- @param mb integer
+--- @param header string[]
+function M.multibuf_set_header(mb, header)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	info.header = header
+	M.multibuf_reload(mb)
+end
+
+--- Set how line numbers are rendered in the sign column. "relative" shows the
+--- distance from the cursor and "both" shows the dimmed absolute source line
+--- number next to the relative distance. The mode persists across reloads.
+--- @param mb integer
+--- @param mode MultibufNumberMode
+function M.multibuf_set_number_mode(mb, mode)
+	vim.validate("mode", mode, function(v)
+		return v == "absolute" or v == "relative" or v == "both", "'absolute', 'relative' or 'both'"
+	end)
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	info.number_mode = mode
+	M.multibuf_reload(mb)
+end
+
+--- Soft wrap the long lines of a multibuffer with a continuation marker,
+--- whatever 'wrap' the windows showing it have. The options are window local
+--- and restored once a window shows another buffer.
+--- @param mb integer
+--- @param enabled boolean
+function M.multibuf_set_wrap(mb, enabled)
+	vim.validate("enabled", enabled, "boolean")
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	info.wrap = enabled
+	for _, win in ipairs(vim.fn.win_findbuf(mb)) do
+		apply_window_options(win, mb)
+	end
+end
+
+--- Show or hide the per source titles of a multibuffer. A {mode} picks which
+--- source entries get a title, see the `titles` setup option. Only the titles
+--- are rendered again, unwritten edits are kept.
+--- @param mb integer multibuf id
+--- @param mode boolean|MultibufTitleMode
+function M.multibuf_set_titles(mb, mode)
+	vim.validate("mode", mode, { "boolean", "string" })
+	if type(mode) == "string" then
+		local err = setup_schema.titles.check(mode)
+		if err then
+			error("multibuffer: mode " .. err, 2)
+		end
+	end
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	if type(mode) == "boolean" then
+		info.hide_titles = not mode
+	else
+		info.hide_titles = nil
+		info.title_mode = mode
+	end
+
+	local shown = get_shown_titles(info)
+	for _, b_info in ipairs(info.bufs) do
+		local mark = b_info.title_extmark_id
+			and vim.api.nvim_buf_get_extmark_by_id(mb, M.multibuf_structure_ns, b_info.title_extmark_id, {})
+		if mark and mark[1] then
+			place_title(mb, b_info, mark[1], shown[b_info] == true)
+		end
+	end
+end
+
+--- @param buf integer
+--- @return boolean
+function M.multibuf_is_valid(buf)
+	return resolve_multibuf(buf) ~= nil
+end
+
+--- Handle of {buf} when it is a multibuffer. Only a lookup in the multibuffer
+--- map, so it is cheap enough for statuslines and autocmd guards and doesn't
+--- depend on |b:multibuffer|. Multibuffers wiped out without |BufWipeout|
+--- are forgotten on lookup.
+--- @param buf integer|nil buffer handle, 0 or nil for the current buffer
+--- @return integer|nil mb
+function M.multibuf_handle_of(buf)
+	if buf == nil or buf == 0 then
+		buf = vim.api.nvim_get_current_buf()
+	end
+	return resolve_multibuf(buf) and buf or nil
+end
+
+--- Whether {buf} is a multibuffer, see |multibuf_handle_of|.
+--- @param buf integer|nil buffer handle, 0 or nil for the current buffer
+--- @return boolean
+function M.is_multibuf(buf)
+	return M.multibuf_handle_of(buf) ~= nil
+end
+
+--- @param mb integer
+--- @param opts MultibufAddBufOptions
+function M.multibuf_add_buf(mb, opts)
+	M.multibuf_add_bufs(mb, { opts })
+end
+
+--- Add regions of a file. An already open buffer of the file is always used,
+--- so when it has unsaved changes the multibuffer shows those rather than
+--- the file on disk. Such regions are flagged with `unsaved_source`.
+--- @param mb integer
+--- @param path string
+--- @param regions MultibufRegion[]
+--- @param opts { title: any[]|nil|MultibufTitleRenderFunction, id: string|nil, force: boolean|nil }|nil
+--- @return integer|nil bufnr the buffer the regions were taken from
+function M.multibuf_add_file(mb, path, regions, opts)
+	vim.validate("path", path, "string")
+	vim.validate("regions", regions, "table")
+	vim.validate("opts", opts, { "table", "nil" })
+	opts = opts or {}
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+
+	local buf = vim.fn.bufadd(vim.fn.fnamemodify(path, ":p"))
+	local unsaved = vim.api.nvim_buf_is_loaded(buf) and vim.bo[buf].modified
+	if unsaved then
+		vim.notify(
+			string.format("multibuffer: %s has unsaved changes, showing the buffer instead of the file", path),
+			vim.log.levels.INFO
+		)
+	end
+
+	M.multibuf_add_buf(mb, { buf = buf, regions = regions, title = opts.title, id = opts.id, force = opts.force })
+
+	if unsaved then
+		local b_info = info.bufs[#info.bufs]
+		for _, meta in ipairs(b_info.region_meta) do
+			meta.unsaved_source = true
+		end
+	end
+	return buf
+end
+
+--- @param mb integer
+function M.multibuf_clear_bufs(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+	for _, b_info in ipairs(info.bufs) do
+		remove_source_extmarks(b_info)
+		for _, meta in ipairs(b_info.region_meta) do
+			clear_span(b_info.buf, meta)
+		end
+	end
+	info.bufs = {}
+	pending_adds[mb] = nil
+	M.multibuf_reload(mb)
+end
+
+-- reading a range of a source this large stalls the editor
+local MAX_SOURCE_BYTES = 50 * 1024 * 1024
+
+--- Binary sources render as garbage and huge ones stall every reload, so
+--- they are refused before anything is added.
+--- @param buf integer
+--- @param force boolean|nil allow large text sources
+--- @return string|nil err
+local function check_source_buf(buf, force)
+	if not vim.api.nvim_buf_is_valid(buf) then
+		return string.format("buffer %d is not valid", buf)
+	end
+	local name = vim.api.nvim_buf_get_name(buf)
+	local display = name ~= "" and vim.fn.fnamemodify(name, ":~:.") or string.format("buffer %d", buf)
+	if vim.bo[buf].binary then
+		return string.format("%s has 'binary' set, binary sources are not supported", display)
+	end
+
+	local size
+	if vim.api.nvim_buf_is_loaded(buf) then
+		size = vim.api.nvim_buf_get_offset(buf, vim.api.nvim_buf_line_count(buf))
+	else
+		size = vim.fn.getfsize(name)
+		local file = size > 0 and io.open(name, "rb")
+		if file then
+			local sample = file:read(8192) or ""
+			file:close()
+			if sample:find("\0", 1, true) then
+				return string.format("%s looks like a binary file, binary sources are not supported", display)
+			end
+		end
+	end
+
+	if size > MAX_SOURCE_BYTES and not force then
+		return string.format(
+			"%s is %d MiB, sources over %d MiB need `force = true` (consider `max_lines` to cap the regions)",
+			display,
+			math.floor(size / 1024 / 1024),
+			MAX_SOURCE_BYTES / 1024 / 1024
+		)
+	end
+	return nil
+end
+
+--- @param v any
+--- @return boolean
+local function is_index(v)
+	return type(v) == "number" and v >= 0 and v == math.floor(v)
+end
+
+--- Check the fields of a region passed in by a caller, naming the field that
+--- is wrong rather than failing somewhere in the reload.
+--- @param region any
+--- @return string|nil err
+local function validate_region(region)
+	if type(region) ~= "table" then
+		return "expected a table, got " .. type(region)
+	end
+	if region.start_row == nil then
+		return "start_row is missing"
+	elseif not is_index(region.start_row) then
+		return "start_row must be a non-negative integer, got " .. vim.inspect(region.start_row)
+	end
+	if region.end_row ~= nil and not is_index(region.end_row) then
+		return "end_row must be a non-negative integer, got " .. vim.inspect(region.end_row)
+	elseif region.end_row and region.end_row < region.start_row then
+		return string.format("end_row %d is before start_row %d", region.end_row, region.start_row)
+	end
+	if region.start_col ~= nil or region.end_col ~= nil then
+		if not is_index(region.start_col) or not is_index(region.end_col) then
+			return "start_col and end_col must both be non-negative integers"
+		elseif region.end_col < region.start_col then
+			return string.format("end_col %d is before start_col %d", region.end_col, region.start_col)
+		elseif region.context or region.transform then
+			return "a region with start_col and end_col can't have context or a transform"
+		end
+	end
+	local fields = {
+		cols = "table",
+		footer = "table",
+		max_lines = "number",
+		context = "table",
+		readonly = "boolean",
+		label = "string",
+	}
+	for _, field in ipairs({ "cols", "footer", "max_lines", "context", "readonly", "label" }) do
+		if region[field] ~= nil and type(region[field]) ~= fields[field] then
+			return string.format("%s must be a %s, got %s", field, fields[field], type(region[field]))
+		end
+	end
+	if region.transform ~= nil and region.transform ~= "dedent" then
+		return "transform must be \"dedent\", got " .. vim.inspect(region.transform)
+	end
+	return nil
+end
+
+--- A region without an `end_row` is the single line at `start_row`.
+--- @param region MultibufRegion
+--- @return MultibufRegion
+local function normalize_region(region)
+	if region.end_row then
+		return region
+	end
+	return vim.tbl_extend("force", region, { end_row = region.start_row })
+end
+
+--- @param opts any
+--- @return string|nil err
+local function validate_add_opts(opts)
+	if type(opts) ~= "table" then
+		return "expected a table, got " .. type(opts)
+	end
+	if type(opts.buf) ~= "number" then
+		return "buf must be a buffer handle, got " .. type(opts.buf)
+	end
+	if type(opts.regions) ~= "table" then
+		return "regions must be a list of regions, got " .. type(opts.regions)
+	end
+	for i, region in ipairs(opts.regions) do
+		local err = validate_region(region)
+		if err then
+			return string.format("regions[%d]: %s", i, err)
+		end
+	end
+	return check_source_buf(opts.buf, opts.force)
+end
+
+--- @param mb integer
+--- @param opts_list MultibufAddBufOptions[]
+function M.multibuf_add_bufs(mb, opts_list)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+
+	for i, opts in ipairs(opts_list) do
+		local err = validate_add_opts(opts)
+		if err then
+			-- name the entry when several sources are added at once
+			if #opts_list > 1 then
+				err = string.format("opts_list[%d]: %s", i, err)
+			end
+			error("multibuffer: " .. err, 2)
+		end
+	end
+
+	if not pending_adds[mb] then
+		pending_adds[mb] = {}
+	end
+
+	for _, opts in ipairs(opts_list) do
+		local regions = {}
+		for _, region in ipairs(opts.regions) do
+			table.insert(regions, normalize_region(region))
+		end
+		table.insert(pending_adds[mb], vim.tbl_extend("force", opts, { regions = regions }))
+	end
+
+	process_pending_adds(mb)
+end
+
+-- matches of a glob loaded before progress is shown
+local GLOB_PROGRESS_STEP = 100
+
+--- @class MultibufGlobSkipped
+--- @field path string
+--- @field reason string
+
+--- Add every file matching {pattern} in one batch, e.g. all manifests of a
+--- workspace with `**/Cargo.toml`. Binary files, files over the size limit,
+--- files {region_spec} returns no regions for and files with an invalid
+--- region are skipped.
+--- @param mb integer multibuf id
+--- @param pattern string |glob()| pattern, relative to the current directory
+--- @param region_spec (fun(buf: integer): MultibufRegion[])|nil regions of a loaded match, the whole file when nil
+--- @return integer[] bufs buffers added, MultibufGlobSkipped[] skipped
+function M.multibuf_add_glob(mb, pattern, region_spec)
+	vim.validate("pattern", pattern, "string")
+	vim.validate("region_spec", region_spec, { "function", "nil" })
+	if not resolve_multibuf(mb) then
+		return {}, {}
+	end
+
+	local paths = vim.tbl_filter(function(path)
+		return vim.fn.isdirectory(path) == 0
+	end, vim.fn.glob(pattern, false, true))
+	local opts_list, bufs, skipped = {}, {}, {}
+	for i, path in ipairs(paths) do
+		if #paths >= GLOB_PROGRESS_STEP and i % GLOB_PROGRESS_STEP == 0 then
+			vim.api.nvim_echo({ { string.format("multibuffer: loading %d/%d files", i, #paths) } }, false, {})
+			vim.cmd.redraw()
+		end
+		local full = vim.fn.fnamemodify(path, ":p")
+		local existed = vim.fn.bufnr(full) ~= -1
+		local buf = vim.fn.bufadd(full)
+		local err = check_source_buf(buf)
+		local regions
+		if not err then
+			vim.fn.bufload(buf)
+			if region_spec then
+				local ok, result = pcall(region_spec, buf)
+				if not ok then
+					err = tostring(result)
+				elseif type(result) ~= "table" or #result == 0 then
+					err = "no regions"
+				else
+					regions = result
+					for j, region in ipairs(regions) do
+						local region_err = validate_region(region)
+						if region_err then
+							err = string.format("%s: regions[%d]: %s", path, j, region_err)
+							break
+						end
+					end
+				end
+			else
+				regions = { { start_row = 0, end_row = vim.api.nvim_buf_line_count(buf) - 1 } }
+			end
+		end
+		if err then
+			table.insert(skipped, { path = path, reason = err })
+			if not existed then
+				pcall(vim.api.nvim_buf_delete, buf, { force = true })
+			end
+		else
+			table.insert(opts_list, { buf = buf, regions = regions })
+			table.insert(bufs, buf)
+		end
+	end
+
+	if #opts_list > 0 then
+		M.multibuf_add_bufs(mb, opts_list)
+	end
+	local not_added = vim.tbl_filter(function(entry)
+		return entry.reason ~= "no regions"
+	end, skipped)
+	if #not_added > 0 then
+		local lines = {}
+		for _, entry in ipairs(not_added) do
+			table.insert(lines, "  " .. entry.reason)
+		end
+		local message = string.format("multibuffer: skipped %d file(s) matching %s:\n", #not_added, pattern)
+		vim.notify(message .. table.concat(lines, "\n"), vim.log.levels.WARN)
+	end
+	return bufs, skipped
+end
+
+--- Set the active multibuffer of the current tabpage. The active multibuffer
+--- is the default target of |multibuf_add_selection|.
+--- @param mb integer|nil multibuf id, nil to clear
+function M.multibuf_set_active(mb)
+	vim.validate("mb", mb, { "number", "nil" })
+	if mb and not resolve_multibuf(mb) then
+		error(string.format("multibuffer: %d is not a multibuffer", mb))
+	end
+	active_multibufs[vim.api.nvim_get_current_tabpage()] = mb
+end
+
+--- @return integer|nil mb active multibuffer of the current tabpage
+function M.multibuf_get_active()
+	local tab = vim.api.nvim_get_current_tabpage()
+	local mb = active_multibufs[tab]
+	if mb and not resolve_multibuf(mb) then
+		active_multibufs[tab] = nil
+		return nil
+	end
+	return mb
+end
+
+--- @return MultibufListEntry[]
+function M.multibuf_list()
+	local active = M.multibuf_get_active()
+	local entries = {}
+	for mb, info in pairs(multibufs) do
+		table.insert(entries, {
+			buf = mb,
+			name = vim.api.nvim_buf_get_name(mb),
+			source_count = #info.bufs,
+			active = mb == active,
+		})
+	end
+	table.sort(entries, function(a, b)
+		return a.buf < b.buf
+	end)
+	return entries
+end
+
+--- Move the regions of {old_source} onto {new_source}, e.g. after the file was
+--- opened again in a new buffer. Regions keep their rows and are looked up
+--- by their content when it moved. Regions that can't be placed are removed.
+--- @param mb integer
+--- @param old_source integer|string buffer handle, valid or not, or file path
+--- @param new_source integer|string buffer handle or file path
+--- @return integer count regions moved to {new_source}
+function M.multibuf_rebind_source(mb, old_source, new_source)
+	vim.validate("old_source", old_source, { "number", "string" })
+	vim.validate("new_source", new_source, { "number", "string" })
+	local info = resolve_multibuf(mb)
+	if not info then
+		return 0
+	end
+	-- the old buffer may be gone, its handle is only compared
+	local old_buf = type(old_source) == "string" and vim.fn.bufnr(old_source) or old_source
+	local new_buf = resolve_source_buf(new_source)
+	local err = not new_buf and "new source is not a valid buffer"
+		or multibufs[new_buf] and "new source is a multibuffer"
+		or check_source_buf(new_buf --[[@as integer]])
+	if err then
+		error("multibuffer: " .. err, 2)
+	end
+	--- @cast new_buf integer
+	local shown = false
+	for _, b_info in ipairs(info.bufs) do
+		shown = shown or b_info.buf == old_buf
+	end
+	if old_buf == new_buf or not shown then
+		return 0
+	end
+	vim.fn.bufload(new_buf)
+	local line_count = vim.api.nvim_buf_line_count(new_buf)
+	local old_loaded = vim.api.nvim_buf_is_valid(old_buf) and vim.api.nvim_buf_is_loaded(old_buf)
+
+	local count = 0
+	-- drop_region can remove entries, so walk them back to front
+	for b_idx = #info.bufs, 1, -1 do
+		local b_info = info.bufs[b_idx]
+		if b_info.buf ~= old_buf then
+			goto next_buf
+		end
+
+		if b_info.pending_regions then
+			count = count + #b_info.pending_regions
+		else
+			-- rows from the old extmarks, or from the last reload once they are gone
+			local ranges = {}
+			for s_idx = 1, #b_info.region_meta do
+				local s, e
+				if old_loaded then
+					s, e = get_extmark_range(old_buf, b_info.source_extmark_ids[s_idx])
+				end
+				local fingerprint = b_info.fingerprints and b_info.fingerprints[s_idx]
+				if not s and fingerprint and fingerprint.row then
+					s, e = fingerprint.row, fingerprint.row + fingerprint.len
+				end
+				if s and fingerprint then
+					local found = find_region_fingerprint(new_buf, fingerprint, s)
+					if found then
+						s, e = found, found + fingerprint.len
+					end
+				end
+				ranges[s_idx] = s and s < line_count and { s, math.min(e, line_count) } or false
+			end
+
+			for s_idx = #ranges, 1, -1 do
+				if not ranges[s_idx] then
+					drop_region(mb, info, b_idx, s_idx)
+					table.remove(ranges, s_idx)
+				end
+			end
+			if info.bufs[b_idx] == b_info then
+				remove_source_extmarks(b_info)
+				for _, range in ipairs(ranges) do
+					table.insert(
+						b_info.source_extmark_ids,
+						vim.api.nvim_buf_set_extmark(new_buf, M.multibuf__ns, range[1], 0, {
+							end_row = range[2],
+							end_right_gravity = true,
+						})
+					)
+				end
+				count = count + #ranges
+			end
+		end
+
+		if info.bufs[b_idx] == b_info then
+			b_info.buf = new_buf
+			b_info.changedtick = nil
+			b_info.conflicts = nil
+			b_info.readonly = is_help_buf(new_buf) or not vim.bo[new_buf].modifiable
+		end
+
+		::next_buf::
+	end
+
+	local listener_info = buf_listeners[old_buf]
+	if listener_info then
+		list_remove(listener_info.multibufs, mb)
+		if #listener_info.multibufs == 0 then
+			stop_buf_listener(listener_info)
+			buf_listeners[old_buf] = nil
+		end
+	end
+	listen_to_source(mb, new_buf)
+	M.multibuf_reload(mb, { keep_edits = true })
+	return count
+end
+
+--- Multibuffers showing regions of a source, including regions that haven't
+--- loaded yet. Edits of the source reload these.
+--- @param source integer|string buffer handle or file path
+--- @return integer[] mbs multibuffer handles in ascending order
+function M.multibuf_referencing(source)
+	vim.validate("source", source, { "number", "string" })
+	-- looking up a path must not create a buffer for it
+	local source_buf = type(source) == "string" and vim.fn.bufnr(source) or source
+	if source_buf == -1 then
+		return {}
+	end
+
+	local mbs = {}
+	for mb, info in pairs(multibufs) do
+		if vim.api.nvim_buf_is_valid(mb) then
+			for _, b_info in ipairs(info.bufs) do
+				if b_info.buf == source_buf then
+					table.insert(mbs, mb)
+					break
+				end
+			end
+		end
+	end
+	table.sort(mbs)
+	return mbs
+end
+
+--- Add the last visual selection ('< and '> marks) of the current buffer as a
+--- region. When {mb} is nil the active multibuffer is used, creating one if
+--- there is none.
+--- @param mb integer|nil multibuf id
+--- @param opts { title: any[]|nil|MultibufTitleRenderFunction }|nil
+--- @return integer|nil mb the multibuffer the selection was added to
+function M.multibuf_add_selection(mb, opts)
+	vim.validate("mb", mb, { "number", "nil" })
+	vim.validate("opts", opts, { "table", "nil" })
+
+	local start_pos = vim.api.nvim_buf_get_mark(0, "<")
+	local end_pos = vim.api.nvim_buf_get_mark(0, ">")
+	if start_pos[1] == 0 or end_pos[1] == 0 then
+		vim.notify("multibuffer: no visual selection", vim.log.levels.WARN)
+		return nil
+	end
+	return add_current_buf_lines(mb, start_pos[1] - 1, end_pos[1] - 1, opts)
+end
+
+--- Add the cursor line of the current buffer and {context} lines around it.
+--- A region of the same source that the lines overlap or touch is extended
+--- instead of adding another one. When {mb} is nil the active multibuffer is
+--- used, creating one if there is none.
+--- @param mb integer|nil multibuf id
+--- @param context integer|nil lines above and below the cursor line, 0 by default
+--- @return integer|nil mb the multibuffer the line was added to
+function M.multibuf_add_current_line(mb, context)
+	vim.validate("mb", mb, { "number", "nil" })
+	vim.validate("context", context, { "number", "nil" })
+
+	local buf = vim.api.nvim_get_current_buf()
+	local row = vim.api.nvim_win_get_cursor(0)[1] - 1
+	context = math.max(0, context or 0)
+	local start_row = math.max(0, row - context)
+	local end_row = math.min(vim.api.nvim_buf_line_count(buf) - 1, row + context)
+
+	mb = mb or M.multibuf_get_active()
+	local info = mb and not multibufs[buf] and resolve_multibuf(mb)
+	local b_info
+	for _, b in ipairs(info and info.bufs or {}) do
+		if b.buf == buf then
+			b_info = b
+			break
+		end
+	end
+	if not b_info then
+		return add_current_buf_lines(mb, start_row, end_row)
+	end
+	--- @cast mb integer
+
+	local touching
+	for s_idx = 1, #b_info.region_meta do
+		local s, e = get_region_rows(b_info, s_idx)
+		if s and s <= end_row + 1 and e >= start_row - 1 then
+			touching = s_idx
+			start_row = math.min(start_row, s)
+			end_row = math.max(end_row, e)
+			break
+		end
+	end
+
+	if b_info.pending_regions then
+		local region = { start_row = start_row, end_row = end_row }
+		if touching then
+			b_info.pending_regions[touching] = region
+		else
+			table.insert(b_info.pending_regions, region)
+			table.insert(b_info.region_meta, new_region_meta(region))
+		end
+	else
+		local sid = vim.api.nvim_buf_set_extmark(buf, M.multibuf__ns, start_row, 0, {
+			id = touching and b_info.source_extmark_ids[touching] or nil,
+			end_row = end_row + 1,
+			end_right_gravity = true,
+		})
+		if not touching then
+			table.insert(b_info.source_extmark_ids, sid)
+			table.insert(b_info.region_meta, new_region_meta({ start_row = start_row, end_row = end_row }))
+		end
+	end
+	-- the extended region may now reach the regions after it
+	merge_buffer_regions(b_info)
+	M.multibuf_reload(mb)
+	return mb
+end
+
+--- Add the lines shown in window {win} as regions, one for every run of
+--- lines between closed folds. Lines hidden in a closed fold aren't added.
+--- When {mb} is nil the active multibuffer is used, creating one if there is
+--- none.
+--- @param mb integer|nil multibuf id
+--- @param win integer|nil window handle, 0 or nil for the current window
+--- @return integer|nil mb the multibuffer the lines were added to
+function M.multibuf_add_visible(mb, win)
+	vim.validate("mb", mb, { "number", "nil" })
+	vim.validate("win", win, { "number", "nil" })
+	win = (win == nil or win == 0) and vim.api.nvim_get_current_win() or win
+	if not vim.api.nvim_win_is_valid(win) then
+		vim.notify(string.format("multibuffer: %d is not a valid window", win), vim.log.levels.WARN)
+		return nil
+	end
+	local buf = vim.api.nvim_win_get_buf(win)
+	if multibufs[buf] then
+		vim.notify("multibuffer: cannot add lines of a multibuffer to a multibuffer", vim.log.levels.WARN)
+		return nil
+	end
+
+	local regions = {}
+	vim.api.nvim_win_call(win, function()
+		local lnum, last = vim.fn.line("w0"), vim.fn.line("w$")
+		local segment_start
+		while lnum <= last do
+			local fold_end = vim.fn.foldclosedend(lnum)
+			if fold_end ~= -1 then
+				if segment_start then
+					table.insert(regions, { start_row = segment_start - 1, end_row = lnum - 2, origin = "visible" })
+					segment_start = nil
+				end
+				lnum = fold_end + 1
+			else
+				segment_start = segment_start or lnum
+				lnum = lnum + 1
+			end
+		end
+		if segment_start then
+			table.insert(regions, { start_row = segment_start - 1, end_row = last - 1, origin = "visible" })
+		end
+	end)
+	if #regions == 0 then
+		vim.notify("multibuffer: every visible line is in a closed fold", vim.log.levels.WARN)
+		return nil
+	end
+
+	mb = mb or get_or_create_active()
+	M.multibuf_add_buf(mb, { buf = buf, regions = regions })
+	return mb
+end
+
+--- 'operatorfunc' used by |multibuf_add_operator|
+--- @param _ string motion type
+function M.multibuf__add_operatorfunc(_)
+	local start_pos = vim.api.nvim_buf_get_mark(0, "[")
+	local end_pos = vim.api.nvim_buf_get_mark(0, "]")
+	add_current_buf_lines(nil, start_pos[1] - 1, end_pos[1] - 1)
+end
+
+--- Operator that adds the lines covered by a motion to the active
+--- multibuffer. Meant to be mapped with `expr = true`.
+--- @return string keys
+function M.multibuf_add_operator()
+	vim.o.operatorfunc = "v:lua.require'multibuffer'.multibuf__add_operatorfunc"
+	return "g@"
+end
+
+--- @param selection MultibufSelection
+--- @return string|nil err reason the selection is invalid
+local function validate_selection(selection)
+	if type(selection) ~= "table" then
+		return "expected a table, got " .. type(selection)
+	end
+	if type(selection.buf) ~= "number" or not vim.api.nvim_buf_is_valid(selection.buf) then
+		return "invalid buffer " .. tostring(selection.buf)
+	end
+	if multibufs[selection.buf] then
+		return "buffer " .. selection.buf .. " is a multibuffer"
+	end
+	if type(selection.start_row) ~= "number" or type(selection.end_row) ~= "number" then
+		return "start_row and end_row must be numbers"
+	end
+	if selection.start_row < 0 or selection.end_row < selection.start_row then
+		return string.format("invalid range %d-%d", selection.start_row, selection.end_row)
+	end
+	if vim.api.nvim_buf_is_loaded(selection.buf) then
+		local line_count = vim.api.nvim_buf_line_count(selection.buf)
+		if selection.end_row >= line_count then
+			return string.format("end_row %d is past the end of buffer %d", selection.end_row, selection.buf)
+		end
+	end
+	return nil
+end
+
+--- Create a multibuffer from a list of selections, show it in the current
+--- window and make it the active multibuffer. Invalid selections are skipped
+--- with a warning.
+--- @param selections MultibufSelection[]
+--- @return integer mb
+function M.multibuf_from_selections(selections)
+	vim.validate("selections", selections, "table")
+
+	local add_opts = {}
+	for i, selection in ipairs(selections) do
+		local err = validate_selection(selection)
+		if err then
+			vim.notify(string.format("multibuffer: skipping selection %d: %s", i, err), vim.log.levels.WARN)
+		else
+			table.insert(add_opts, {
+				buf = selection.buf,
+				regions = {
+					{ start_row = selection.start_row, end_row = selection.end_row, label = selection.label },
+				},
+				title = selection.label
+					and { { { "" } }, { { " " .. selection.label .. "  ", "TabLine" } }, { { "" } } },
+			})
+		end
+	end
+
+	local mb = M.create_multibuf()
+	M.multibuf_add_bufs(mb, add_opts)
+	vim.api.nvim_win_set_buf(0, mb)
+	M.multibuf_set_active(mb)
+	return mb
+end
+
+--- @param item MultibufBatchItem
+--- @return string|nil err reason the item is invalid
+local function validate_batch_item(item)
+	if type(item) ~= "table" then
+		return "expected a table, got " .. type(item)
+	end
+	if type(item.region) ~= "table" then
+		return "region must be a table"
+	end
+	local err = validate_region(item.region)
+	if err then
+		return "region: " .. err
+	end
+	err = validate_selection({
+		buf = item.source_buf,
+		start_row = item.region.start_row,
+		end_row = item.region.end_row or item.region.start_row,
+	}) or check_source_buf(item.source_buf)
+	if err then
+		return err
+	end
+	if item.label ~= nil and type(item.label) ~= "string" then
+		return "label must be a string"
+	end
+	if item.context ~= nil and type(item.context) ~= "table" then
+		return "context must be a table"
+	end
+	if item.readonly ~= nil and type(item.readonly) ~= "boolean" then
+		return "readonly must be a boolean"
+	end
+	return nil
+end
+
+--- Add regions of any number of sources with a single reload. Items of the
+--- same source and group become one entry, in the order of their first item.
+--- Invalid items are skipped with a warning and returned.
+--- @param mb integer
+--- @param items MultibufBatchItem[]
+--- @return integer[] region_ids ids of the added regions, overlapping items of an entry share one
+--- @return MultibufBatchFailure[] failures
+function M.multibuf_add_batch(mb, items)
+	vim.validate("items", items, "table")
+	local info = resolve_multibuf(mb)
+	if not info then
+		return {}, {}
+	end
+
+	local entries = {}
+	local entry_by_key = {}
+	local failures = {}
+	for i, item in ipairs(items) do
+		local err = validate_batch_item(item)
+		if err then
+			table.insert(failures, { index = i, err = err })
+			goto continue
+		end
+
+		local key = tostring(item.source_buf) .. ":" .. tostring(item.group)
+		local entry = entry_by_key[key]
+		if not entry then
+			entry = { buf = item.source_buf, regions = {} }
+			entry_by_key[key] = entry
+			table.insert(entries, entry)
+		end
+		if item.label and not entry.title then
+			entry.title = { { { "" } }, { { " " .. item.label .. "  ", "TabLine" } }, { { "" } } }
+		end
+		local region = vim.tbl_extend("force", normalize_region(item.region), {
+			context = item.context or item.region.context,
+			readonly = item.readonly or item.region.readonly,
+			label = item.region.label or item.label,
+		})
+		table.insert(entry.regions, region)
+
+		::continue::
+	end
+
+	for _, failure in ipairs(failures) do
+		vim.notify(
+			string.format("multibuffer: skipping batch item %d: %s", failure.index, failure.err),
+			vim.log.levels.WARN
+		)
+	end
+
+	if #entries == 0 then
+		return {}, failures
+	end
+
+	local first = #info.bufs + 1
+	pending_adds[mb] = pending_adds[mb] or {}
+	for _, entry in ipairs(entries) do
+		table.insert(pending_adds[mb], { buf = entry.buf, regions = entry.regions, title = entry.title })
+	end
+	process_pending_adds(mb)
+
+	local ids = {}
+	for b_idx = first, #info.bufs do
+		for _, meta in ipairs(info.bufs[b_idx].region_meta) do
+			table.insert(ids, meta.id)
+		end
+	end
+	return ids, failures
+end
+
+--- Register a function returning regions for |multibuf_populate|. Registering
+--- an existing name replaces the provider.
+--- @param name string
+--- @param provider MultibufProvider
+function M.multibuf_register_provider(name, provider)
+	vim.validate("name", name, "string")
+	vim.validate("provider", provider, "function")
+	providers[name] = provider
+end
+
+--- Run a provider and add the regions it returns with a single reload, see
+--- |multibuf_add_batch|. Entries of the same source share a title.
+--- @param mb integer
+--- @param name string
+--- @param opts table|nil Passed to the provider
+--- @return integer[] region_ids
+--- @return MultibufBatchFailure[] failures indexed by region in the order the provider returned them
+function M.multibuf_populate(mb, name, opts)
+	vim.validate("name", name, "string")
+	vim.validate("opts", opts, "table", true)
+	local provider = providers[name]
+	if not provider then
+		error("multibuffer: unknown provider " .. name)
+	end
+
+	local ok, entries = pcall(provider, opts or {})
+	if not ok or type(entries) ~= "table" then
+		vim.notify(string.format("multibuffer: provider %s failed: %s", name, tostring(entries)), vim.log.levels.ERROR)
+		return {}, {}
+	end
+
+	local items = {}
+	for _, entry in ipairs(entries) do
+		local buf = resolve_source_buf(entry.source)
+		if buf and not multibufs[buf] then
+			vim.fn.bufload(buf)
+		end
+
+		local cols
+		for _, match in ipairs(entry.matches or {}) do
+			cols = cols or {}
+			cols[match.row] = cols[match.row] or match.col
+		end
+
+		for _, region in ipairs(entry.regions or {}) do
+			table.insert(items, {
+				source_buf = buf,
+				region = vim.tbl_extend("keep", region, { cols = cols, origin = entry.origin }),
+				label = entry.label,
+			})
+		end
+	end
+	return M.multibuf_add_batch(mb, items)
+end
+
+--- @param lines integer|nil
+--- @return { before: integer, after: integer }|nil
+local function provider_context(lines)
+	if lines and lines > 0 then
+		return { before = lines, after = lines }
+	end
+	return nil
+end
+
+--- Items of a quickfix or location list. `opts.win` uses the location list of
+--- that window, `opts.id` or `opts.nr` select a list other than the current
+--- one and `opts.context` adds read-only lines around each item.
+M.multibuf_register_provider("quickfix", function(opts)
+	local what = { id = opts.id, nr = opts.nr, items = 1 }
+	local list = opts.win and vim.fn.getloclist(opts.win, what) or vim.fn.getqflist(what)
+
+	local entries = {}
+	for _, item in ipairs(list.items or {}) do
+		if item.valid == 1 and item.bufnr > 0 and item.lnum > 0 then
+			local row = item.lnum - 1
+			table.insert(entries, {
+				source = item.bufnr,
+				regions = {
+					{
+						start_row = row,
+						end_row = math.max(row, item.end_lnum - 1),
+						context = provider_context(opts.context),
+					},
+				},
+				origin = item,
+				matches = { { row = row, col = math.max(item.col - 1, 0) } },
+			})
+		end
+	end
+	return entries
+end)
+
+--- Diagnostics of `opts.buf` or of all buffers, filtered by `opts.severity`
+--- and `opts.namespace` like |vim.diagnostic.get()|. The message is shown
+--- below each region and `opts.context` adds read-only lines around it.
+M.multibuf_register_provider("diagnostics", function(opts)
+	local diagnostics = vim.diagnostic.get(opts.buf, { severity = opts.severity, namespace = opts.namespace })
+
+	local entries = {}
+	for _, diagnostic in ipairs(diagnostics) do
+		local severity = vim.diagnostic.severity[diagnostic.severity] or "ERROR"
+		local hl = "Diagnostic" .. severity:sub(1, 1) .. severity:sub(2):lower()
+		local message = vim.split(diagnostic.message, "\n", { plain = true })[1]
+		table.insert(entries, {
+			source = diagnostic.bufnr,
+			regions = {
+				{
+					start_row = diagnostic.lnum,
+					end_row = math.max(diagnostic.lnum, diagnostic.end_lnum or diagnostic.lnum),
+					context = provider_context(opts.context),
+					footer = { { { " " .. message, hl } } },
+				},
+			},
+			origin = diagnostic,
+			matches = { { row = diagnostic.lnum, col = diagnostic.col } },
+		})
+	end
+	return entries
+end)
+
+--- Describe the regions of a multibuffer by file path and rows, with a
+--- fingerprint of their content to find them again once the files changed.
+--- Sources without a file are left out. The state can be encoded with
+--- |vim.json.encode()|.
+--- @param mb integer
+--- @return MultibufState|nil
+function M.multibuf_serialize(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+
+	local state = { title = info.title, titles = info.title_mode, sources = {} }
+	for _, b_info in ipairs(info.bufs) do
+		local path = vim.api.nvim_buf_get_name(b_info.buf)
+		if path == "" or vim.bo[b_info.buf].buftype ~= "" then
+			goto continue
+		end
+		vim.fn.bufload(b_info.buf)
+
+		local source = { path = path, id = b_info.id, regions = {} }
+		for s_idx, meta in ipairs(b_info.region_meta) do
+			local start_row, end_row = get_region_rows(b_info, s_idx)
+			if start_row then
+				local context = meta.context or { before = 0, after = 0 }
+				start_row, end_row = start_row + context.before, end_row - context.after
+				local lines = vim.api.nvim_buf_get_lines(b_info.buf, start_row, end_row + 1, false)
+				table.insert(source.regions, {
+					start_row = start_row,
+					end_row = end_row,
+					fingerprint = region_fingerprint(lines),
+					context = meta.context,
+					max_lines = meta.max_lines,
+					readonly = meta.readonly,
+					start_col = meta.span and meta.span.start_col,
+					end_col = meta.span and meta.span.end_col,
+				})
+			end
+		end
+		table.insert(state.sources, source)
+
+		::continue::
+	end
+	return state
+end
+
+--- Create a multibuffer from a |multibuf_serialize| state. Regions whose lines
+--- changed since are searched near the saved rows and then in the whole file.
+--- Regions that can't be found are shown at the saved rows with a stale marker.
+--- @param state MultibufState
+--- @return integer mb, MultibufRestoreReport report
+function M.multibuf_restore(state)
+	vim.validate("state", state, "table")
+	vim.validate("state.sources", state.sources, "table")
+
+	local report = { relocated = {}, stale = {}, missing = {} }
+	local add_opts = {}
+	for _, source in ipairs(state.sources) do
+		local buf = type(source.path) == "string" and vim.fn.bufadd(source.path)
+		local err = not buf and "no path" or check_source_buf(buf)
+		if err or (vim.fn.filereadable(source.path) == 0 and not vim.api.nvim_buf_is_loaded(buf)) then
+			table.insert(report.missing, tostring(source.path))
+			goto continue
+		end
+		vim.fn.bufload(buf)
+
+		local line_count = vim.api.nvim_buf_line_count(buf)
+		local regions = {}
+		for _, saved in ipairs(source.regions or {}) do
+			local start_row = clamp(saved.start_row, 0, line_count - 1)
+			local end_row = clamp(saved.end_row, start_row, line_count - 1)
+			local footer
+			local fingerprint = saved.fingerprint
+			local lines = vim.api.nvim_buf_get_lines(buf, start_row, end_row + 1, false)
+			if fingerprint and not vim.deep_equal(region_fingerprint(lines), fingerprint) then
+				local row = find_region_fingerprint(buf, fingerprint, start_row)
+					or find_region_fingerprint(buf, fingerprint, start_row, line_count)
+				if row then
+					table.insert(report.relocated, { path = source.path, from = saved.start_row, to = row })
+					start_row, end_row = row, row + fingerprint.len - 1
+				else
+					table.insert(report.stale, { path = source.path, start_row = saved.start_row })
+					footer = { { { " stale: these lines changed since the multibuffer was saved", "DiagnosticWarn" } } }
+				end
+			end
+			table.insert(regions, {
+				start_row = start_row,
+				end_row = end_row,
+				footer = footer,
+				context = saved.context,
+				max_lines = saved.max_lines,
+				readonly = saved.readonly,
+				start_col = saved.start_col,
+				end_col = saved.end_col,
+			})
+		end
+		table.insert(add_opts, { buf = buf, regions = regions, id = source.id })
+
+		::continue::
+	end
+
+	local mb = M.create_multibuf({ title = state.title, titles = state.titles })
+	M.multibuf_add_bufs(mb, add_opts)
+	return mb, report
+end
+
+--- @param win integer window handle
+--- @param mb integer multibuffer handle
+function M.win_set_multibuf(win, mb)
+	vim.api.nvim_win_set_buf(win, mb)
+end
+
+--- @param mb integer
+--- @return boolean
+function M.multibuf_is_loading(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false
+	end
+	if pending_adds[mb] and #pending_adds[mb] > 0 then
+		return true
+	end
+	for _, b in ipairs(info.bufs) do
+		if b.pending_regions then
+			return true
+		end
+	end
+	return false
+end
+
+--- Reload right away instead of waiting for the `sync_events` of the sources
+--- and for the regions to scroll into view. Unwritten edits are kept and the
+--- sources changed under them are queued for the next write as usual.
+--- @param mb integer
+function M.multibuf_force_reload(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return
+	end
+
+	process_pending_adds(mb)
+	for _, b_info in ipairs(info.bufs) do
+		if b_info.pending_regions then
+			load_source_buf(mb, b_info)
+		end
+	end
+
+	local sources = {}
+	for _, b_info in ipairs(info.bufs) do
+		list_insert_unique(sources, b_info.buf)
+	end
+	for _, buf in ipairs(sources) do
+		reanchor_source_regions(mb, buf)
+		queue_source_sync(mb, buf)
+	end
+
+	-- drops a pending debounced redraw, the reload draws the signs
+	info.dirty_tick = (info.dirty_tick or 0) + 1
+	M.multibuf_reload(mb, { keep_edits = vim.bo[mb].modified })
+end
+
+--- Reload the sources that changed while syncing was suspended, once it is no
+--- longer suspended. Sources with unwritten edits are queued like a sync.
+--- @param mb integer
+local function flush_suspended_sources(mb)
+	local info = multibufs[mb]
+	if not info or not info.suspended_sources or (info.suspended or 0) > 0 or suspended_all > 0 then
+		return
+	end
+	local sources = info.suspended_sources
+	info.suspended_sources = nil
+
+	local changed = false
+	for buf in pairs(sources) do
+		if not source_is_current(mb, buf, false) then
+			reanchor_source_regions(mb, buf)
+			queue_source_sync(mb, buf)
+			changed = true
+		end
+	end
+	if changed then
+		M.multibuf_reload(mb, { keep_edits = vim.bo[mb].modified })
+	end
+end
+
+--- Stop reloading on source changes, e.g. while a script edits many sources.
+--- The changed sources are reloaded at once by |multibuf_resume|. Calls nest,
+--- every suspend needs a resume.
+--- @param mb integer|nil multibuf id, nil for all multibuffers
+function M.multibuf_suspend(mb)
+	vim.validate("mb", mb, { "number", "nil" })
+	if mb == nil then
+		suspended_all = suspended_all + 1
+		return
+	end
+	local info = resolve_multibuf(mb)
+	if info then
+		info.suspended = (info.suspended or 0) + 1
+	end
+end
+
+--- Undo a |multibuf_suspend|. Once nothing is suspended anymore the sources
+--- that changed in the meantime are reloaded with a single reload.
+--- @param mb integer|nil multibuf id, nil for all multibuffers
+function M.multibuf_resume(mb)
+	vim.validate("mb", mb, { "number", "nil" })
+	if mb == nil then
+		suspended_all = math.max(0, suspended_all - 1)
+		for _, buf in ipairs(vim.tbl_keys(multibufs)) do
+			flush_suspended_sources(buf)
+		end
+		return
+	end
+	local info = resolve_multibuf(mb)
+	if info then
+		info.suspended = math.max(0, (info.suspended or 0) - 1)
+		flush_suspended_sources(mb)
+	end
+end
+
+--- Call {fn} with syncing suspended. Syncing is resumed even when {fn} fails,
+--- the error is raised again afterwards.
+--- @param fn fun(): any
+--- @param mb integer|nil multibuf id, nil for all multibuffers
+--- @return any result of {fn}
+function M.multibuf_with_suspended(fn, mb)
+	vim.validate("fn", fn, "function")
+	M.multibuf_suspend(mb)
+	local ok, result = pcall(fn)
+	M.multibuf_resume(mb)
+	if not ok then
+		error(result, 0)
+	end
+	return result
+end
+
+--- Ids of the regions edited since the last reload or write, in render order.
+--- @param mb integer multibuf id
+--- @return integer[] region_ids
+function M.multibuf_dirty_regions(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return {}
+	end
+	local ids = {}
+	for _, b in ipairs(info.bufs) do
+		for s_idx, meta in ipairs(b.region_meta) do
+			if region_is_dirty(mb, b, s_idx) then
+				table.insert(ids, meta.id)
+			end
+		end
+	end
+	return ids
+end
+
+--- Whether any region was edited since it was last reloaded or written. Unlike
+--- 'modified' this isn't reset by reloads that keep unwritten edits.
+--- @param mb integer multibuf id
+--- @return boolean
+function M.multibuf_is_modified(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false
+	end
+	for _, b in ipairs(info.bufs) do
+		for s_idx = 1, #b.region_extmark_ids do
+			if region_is_dirty(mb, b, s_idx) then
+				return true
+			end
+		end
+	end
+	return false
+end
+
+--- Status flags of a region. |MultibufRegionStatusChanged| is emitted when
+--- they change.
+--- @param mb integer multibuf id
+--- @param region_id integer stable region id (see |multibuf_get_regions|)
+--- @return MultibufRegionStatus|nil status nil if the region is not part of the multibuf
+function M.multibuf_region_status(mb, region_id)
+	vim.validate("region_id", region_id, "number")
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return nil
+	end
+	return get_region_status(mb, info.bufs[b_idx], s_idx)
+end
+
+--- @param buf integer
+function M.multibuf__wipeout(buf)
+	local wiped_info = multibufs[buf]
+	if wiped_info and (wiped_info.busy or 0) > 0 then
+		-- a reload or write still uses the state, it finishes the cleanup
+		wiped_info.wiped = true
+	elseif wiped_info then
+		close_peek(buf)
+		close_follow_preview(buf)
+		close_outline(buf)
+		for _, b_info in ipairs(multibufs[buf].bufs) do
+			remove_source_extmarks(b_info)
+			for _, meta in ipairs(b_info.region_meta) do
+				clear_span(b_info.buf, meta)
+			end
+		end
+		multibufs[buf] = nil
+		-- sources no other multibuffer shows stop syncing
+		for source_buf, listener_info in pairs(buf_listeners) do
+			list_remove(listener_info.multibufs, buf)
+			if #listener_info.multibufs == 0 then
+				stop_buf_listener(listener_info)
+				buf_listeners[source_buf] = nil
+			end
+		end
+		pending_adds[buf] = nil
+		for tab, mb in pairs(active_multibufs) do
+			if mb == buf then
+				active_multibufs[tab] = nil
+			end
+		end
+	end
+
+	if buf_listeners[buf] then
+		stop_buf_listener(buf_listeners[buf])
+		buf_listeners[buf] = nil
+	end
+
+	-- Remove this buffer from any multibuffer that contains it
+	for mb, info in pairs(multibufs) do
+		-- a running reload or write of it is going through its sources
+		if (info.busy or 0) > 0 then
+			vim.schedule(function()
+				if multibufs[mb] then
+					M.multibuf__wipeout(buf)
+				end
+			end)
+			goto continue
+		end
+		local changed = false
+		for i = #info.bufs, 1, -1 do
+			if info.bufs[i].buf == buf then
+				table.remove(info.bufs, i)
+				changed = true
+			end
+		end
+		if changed then
+			vim.schedule(function()
+				if M.multibuf_is_valid(mb) then
+					M.multibuf_reload(mb)
+				end
+			end)
+		end
+		::continue::
+	end
+end
+
+--- Lines outside the multibuffer give nil. So does the source line when the
+--- region's extmarks are inconsistent and would map it outside the region.
+--- @param mb integer
+--- @param line integer 0-indexed line in multibuffer
+--- @return integer|nil bufnr, integer|nil source_line, integer|nil region_id
+function M.multibuf_get_buf_at_line(mb, line)
+	local info = resolve_multibuf(mb)
+	if not info or type(line) ~= "number" or line ~= line then
+		return nil, nil
+	end
+	line = math.floor(line)
+	if line < 0 or line >= vim.api.nvim_buf_line_count(mb) then
+		return nil, nil
+	end
+	local marks = vim.api.nvim_buf_get_extmarks(
+		mb,
+		M.multibuf__ns,
+		{ line, 0 },
+		{ line, -1 },
+		{ details = true, overlap = true }
+	)
+	for _, m in ipairs(marks) do
+		for _, b in ipairs(info.bufs) do
+			for i, rid in ipairs(b.region_extmark_ids) do
+				if m[1] == rid then
+					local rs, re = get_extmark_range(mb, rid)
+					-- a region ending right above the line still overlaps it
+					if not rs or line >= re then
+						goto next_mark
+					end
+					local region_id = b.region_meta[i] and b.region_meta[i].id
+					local sid = b.source_extmark_ids and b.source_extmark_ids[i]
+					local ss, se
+					if sid then
+						ss, se = get_extmark_range(b.buf, sid)
+					elseif b.pending_regions and b.pending_regions[i] then
+						ss, se = b.pending_regions[i].start_row, b.pending_regions[i].end_row + 1
+					end
+					local source_line = ss and region_row_to_source(mb, b, i, line, rs, re, ss, se)
+					if source_line and (source_line < ss or source_line >= se) then
+						source_line = nil
+					end
+					return b.buf, source_line, region_id
+				end
+			end
+		end
+		::next_mark::
+	end
+	return nil, nil
+end
+
+--- Resolve the source location of a multibuffer line. Returns nil for the
+--- header, titles and expander rows. With {col} the returned `col` is that
+--- multibuffer column mapped to the source: shifted right by the start column
+--- of a column region or the stripped indentation of a dedented region, and
+--- the same column otherwise. Without {col} it comes from the column hints of
+--- the region (see |MultibufRegion|) and is 0 when unknown.
+--- @param mb integer multibuf id
+--- @param line integer 0-indexed line in multibuffer
+--- @param col integer|nil 0-indexed byte column in the multibuffer line
+--- @return MultibufContext|nil
+function M.multibuf_get_context(mb, line, col)
+	vim.validate("mb", mb, "number")
+	vim.validate("line", line, "number")
+	vim.validate("col", col, { "number", "nil" })
+
+	local bufnr, source_line, region_id = M.multibuf_get_buf_at_line(mb, line)
+	if not bufnr or not source_line then
+		return nil
+	end
+
+	local info = resolve_multibuf(mb)
+	local b_idx, s_idx
+	if info and region_id then
+		b_idx, s_idx = find_region_by_id(info, region_id)
+	end
+	local meta = b_idx and s_idx and info.bufs[b_idx].region_meta[s_idx]
+	local source_col
+	if col then
+		-- dedented and column regions show their lines shifted left
+		source_col = col + get_col_offset(meta)
+	else
+		source_col = meta and meta.cols and meta.cols[source_line] or 0
+	end
+	return { buf = bufnr, line = source_line, col = source_col, region_id = region_id }
+end
+
+--- @param ctx MultibufContext
+--- @return string
+local function format_context(ctx)
+	local name = vim.api.nvim_buf_get_name(ctx.buf)
+	if name == "" then
+		name = "[No Name]"
+	else
+		name = vim.fn.fnamemodify(name, ":~:.")
+	end
+	return string.format("%s:%d", name, ctx.line + 1)
+end
+
+--- Format the source location under the cursor of the current window as
+--- `path:line`. Intended for use in 'statusline' or 'winbar'.
+--- @return string context empty when not in a multibuffer or not on a region
+function M.multibuf_statusline_context()
+	local mb = vim.api.nvim_get_current_buf()
+	if not resolve_multibuf(mb) then
+		return ""
+	end
+	local ctx = M.multibuf_get_context(mb, vim.api.nvim_win_get_cursor(0)[1] - 1)
+	return ctx and format_context(ctx) or ""
+end
+
+--- Show the source buffer of a multibuffer line in a floating window scrolled
+--- to that line. The float shows the real source buffer so edits in it sync
+--- like any other source edit. Closes when the cursor moves or on `q`.
+--- @param mb integer multibuf id
+--- @param line integer|nil 0-indexed line in multibuffer, defaults to the cursor line
+--- @param opts { width: integer|nil, height: integer|nil, border: string|string[]|nil }|nil
+--- @return integer|nil win the floating window
+function M.multibuf_peek(mb, line, opts)
+	vim.validate("mb", mb, "number")
+	vim.validate("line", line, { "number", "nil" })
+	vim.validate("opts", opts, { "table", "nil" })
+	opts = opts or {}
+
+	local win = get_buf_win(mb)
+	if not win then
+		return nil
+	end
+	line = line or vim.api.nvim_win_get_cursor(win)[1] - 1
+
+	local ctx = M.multibuf_get_context(mb, line)
+	if not ctx then
+		return nil
+	end
+
+	close_peek(mb)
+
+	local width = opts.width or math.min(100, vim.api.nvim_win_get_width(win) - 4)
+	local height = opts.height or 15
+	local peek_win = vim.api.nvim_open_win(ctx.buf, false, {
+		relative = "cursor",
+		row = 1,
+		col = 0,
+		width = math.max(1, width),
+		height = math.max(1, height),
+		border = opts.border or "rounded",
+		title = " " .. vim.fn.fnamemodify(vim.api.nvim_buf_get_name(ctx.buf), ":~:.") .. " ",
+	})
+	vim.wo[peek_win].cursorline = true
+	vim.api.nvim_win_set_cursor(peek_win, { ctx.line + 1, ctx.col })
+	vim.api.nvim_win_call(peek_win, function()
+		vim.cmd("normal! zz")
+	end)
+	peek_wins[mb] = peek_win
+
+	local group = vim.api.nvim_create_augroup("MultibufPeek" .. mb, { clear = true })
+	-- opening the float doesn't move the cursor, so any later move closes it
+	vim.api.nvim_create_autocmd({ "CursorMoved", "CursorMovedI", "InsertEnter", "BufHidden" }, {
+		group = group,
+		buffer = mb,
+		once = true,
+		callback = function()
+			close_peek(mb)
+		end,
+	})
+	vim.keymap.set("n", "q", function()
+		close_peek(mb)
+	end, { buffer = mb, nowait = true, desc = "Close multibuffer peek" })
+
+	return peek_win
+end
+
+-- cursor moves are settled for this long before the follow preview moves
+local FOLLOW_PREVIEW_DEBOUNCE_MS = 50
+
+--- Show the source of the cursor line of the multibuffer window in its
+--- follow preview. Lines without a source keep the last one.
+--- @param mb integer
+local function update_follow_preview(mb)
+	local preview = follow_previews[mb]
+	local win = get_buf_win(mb)
+	if not preview or not win or not vim.api.nvim_win_is_valid(preview.win) then
+		return
+	end
+	local cursor = vim.api.nvim_win_get_cursor(win)
+	local ctx = M.multibuf_get_context(mb, cursor[1] - 1, cursor[2])
+	if not ctx then
+		return
+	end
+
+	if vim.api.nvim_win_get_buf(preview.win) ~= ctx.buf then
+		vim.api.nvim_win_set_buf(preview.win, ctx.buf)
+		vim.api.nvim_win_set_config(preview.win, {
+			title = " " .. vim.fn.fnamemodify(vim.api.nvim_buf_get_name(ctx.buf), ":~:.") .. " ",
+		})
+	end
+	vim.api.nvim_win_set_cursor(preview.win, { ctx.line + 1, ctx.col })
+	vim.api.nvim_win_call(preview.win, function()
+		vim.cmd("normal! zz")
+	end)
+end
+
+--- Toggle a preview window that keeps showing the source around the cursor
+--- of the multibuffer, like |multibuf_peek| but following cursor moves. It
+--- closes when the cursor leaves the multibuffer.
+--- @param mb integer multibuf id
+--- @param opts { width: integer|nil, height: integer|nil, border: string|string[]|nil }|nil
+--- @return boolean enabled
+function M.multibuf_toggle_follow_preview(mb, opts)
+	vim.validate("mb", mb, "number")
+	vim.validate("opts", opts, { "table", "nil" })
+	opts = opts or {}
+
+	if follow_previews[mb] then
+		close_follow_preview(mb)
+		return false
+	end
+	local win = get_buf_win(mb)
+	if not resolve_multibuf(mb) or not win then
+		return false
+	end
+
+	close_peek(mb)
+	local width = opts.width or math.floor(vim.api.nvim_win_get_width(win) / 2)
+	local height = opts.height or math.floor(vim.api.nvim_win_get_height(win) / 2)
+	-- starts on the multibuffer itself until the first source is shown
+	local preview_win = vim.api.nvim_open_win(mb, false, {
+		relative = "win",
+		win = win,
+		anchor = "NE",
+		row = 0,
+		col = vim.api.nvim_win_get_width(win),
+		width = math.max(1, width),
+		height = math.max(1, height),
+		border = opts.border or "rounded",
+		title = " ",
+		focusable = false,
+	})
+	vim.wo[preview_win].cursorline = true
+	follow_previews[mb] = { win = preview_win, tick = 0 }
+
+	local group = vim.api.nvim_create_augroup("MultibufFollowPreview" .. mb, { clear = true })
+	vim.api.nvim_create_autocmd({ "CursorMoved", "CursorMovedI" }, {
+		group = group,
+		buffer = mb,
+		callback = function()
+			local preview = follow_previews[mb]
+			if not preview then
+				return
+			end
+			preview.tick = preview.tick + 1
+			local tick = preview.tick
+			vim.defer_fn(function()
+				if follow_previews[mb] == preview and preview.tick == tick then
+					update_follow_preview(mb)
+				end
+			end, FOLLOW_PREVIEW_DEBOUNCE_MS)
+		end,
+	})
+	vim.api.nvim_create_autocmd({ "BufLeave", "BufHidden" }, {
+		group = group,
+		buffer = mb,
+		once = true,
+		callback = function()
+			close_follow_preview(mb)
+		end,
+	})
+	vim.api.nvim_create_autocmd("WinClosed", {
+		group = group,
+		pattern = tostring(preview_win),
+		once = true,
+		callback = function()
+			vim.schedule(function()
+				if follow_previews[mb] and follow_previews[mb].win == preview_win then
+					close_follow_preview(mb)
+				end
+			end)
+		end,
+	})
+
+	update_follow_preview(mb)
+	return true
+end
+
+--- Open a panel listing the regions of a multibuffer with their status, kept
+--- up to date on reloads and status changes. <CR> jumps to the region under
+--- the cursor in the window showing the multibuffer and q closes the panel.
+--- @param mb integer multibuf id
+--- @param opts { float: boolean|nil, width: integer|nil, border: string|string[]|nil }|nil
+--- @return integer|nil win the panel window
+function M.multibuf_outline_open(mb, opts)
+	vim.validate("opts", opts, { "table", "nil" })
+	opts = opts or {}
+	if not resolve_multibuf(mb) then
+		return nil
+	end
+	local existing = outlines[mb]
+	if existing and vim.api.nvim_win_is_valid(existing.win) then
+		return existing.win
+	end
+	close_outline(mb)
+
+	local buf = vim.api.nvim_create_buf(false, true)
+	vim.bo[buf].bufhidden = "wipe"
+	vim.bo[buf].filetype = "multibuffer_outline"
+	local mb_win = get_buf_win(mb) or vim.api.nvim_get_current_win()
+	local width = opts.width or 40
+	local win
+	if opts.float then
+		win = vim.api.nvim_open_win(buf, false, {
+			relative = "win",
+			win = mb_win,
+			anchor = "NW",
+			row = 0,
+			col = 0,
+			width = math.max(1, width),
+			height = math.max(1, math.floor(vim.api.nvim_win_get_height(mb_win) / 2)),
+			border = opts.border or "rounded",
+			title = " regions ",
+		})
+	else
+		win = vim.api.nvim_open_win(buf, false, { split = "left", win = mb_win, width = width })
+		vim.wo[win].winfixwidth = true
+	end
+	vim.wo[win].number = false
+	vim.wo[win].relativenumber = false
+	vim.wo[win].signcolumn = "no"
+	vim.wo[win].wrap = false
+	vim.wo[win].cursorline = true
+	outlines[mb] = { win = win, buf = buf }
+
+	vim.keymap.set("n", "<CR>", function()
+		local index = vim.api.nvim_win_get_cursor(0)[1]
+		local target = get_buf_win(mb)
+		if not target then
+			target = vim.fn.win_getid(vim.fn.winnr("#"))
+			if target == 0 or target == win then
+				return
+			end
+			vim.api.nvim_win_set_buf(target, mb)
+		end
+		M.multibuf_jump_to_region(mb, index, { center = true, open_folds = true })
+		vim.api.nvim_set_current_win(target)
+	end, { buffer = buf, desc = "Jump to the region" })
+	vim.keymap.set("n", "q", function()
+		close_outline(mb)
+	end, { buffer = buf, desc = "Close the outline" })
+
+	local group = vim.api.nvim_create_augroup("MultibufOutline" .. mb, { clear = true })
+	vim.api.nvim_create_autocmd("User", {
+		group = group,
+		pattern = { "MultibufRegionStatusChanged", "MultibufRegionsChanged" },
+		callback = function(args)
+			if args.data and args.data.buf == mb then
+				render_outline(mb)
+			end
+		end,
+	})
+	vim.api.nvim_create_autocmd("WinClosed", {
+		group = group,
+		pattern = tostring(win),
+		once = true,
+		callback = function()
+			vim.schedule(function()
+				if outlines[mb] and outlines[mb].win == win then
+					close_outline(mb)
 				end
 			end)
-		end)
-	end
+		end,
+	})
 
-	-- 2. Project Persistent Extmarks (LSP Diagnostics, Gitsigns, etc.)
-	local persistent = vim.api.nvim_buf_get_extmarks(source_buf, -1, { s_start, 0 }, { s_end, -1 }, { details = true })
-	for _, mark in ipairs(persistent) do
-		local _, r, c, d = unpack(mark)
-		if d.ns_id ~= M.multibuf__ns then
-			local tr = target_start + (r - s_start)
-			local ter = d.end_row and (target_start + (d.end_row - s_start))
+	render_outline(mb)
+	return win
+end
 
-			-- Only project if it falls within our target range
-			if tr >= target_start then
-				d.id, d.ns_id, d.end_row, d.ephemeral = nil, nil, ter, true
-				pcall(vim.api.nvim_buf_set_extmark, multibuf, M.multibuf_hl_ns, tr, c, d)
-			end
-		end
-	end
+--- Close the outline panel of a multibuffer, see |multibuf_outline_open|.
+--- @param mb integer multibuf id
+function M.multibuf_outline_close(mb)
+	vim.validate("mb", mb, "number")
+	close_outline(mb)
 end
 
--- ──────── Core Multibuffer Management ────────
+--- @param lines string[]
+--- @param name string
+--- @param filetype string
+--- @return integer buf
+local function create_diff_scratch_buf(lines, name, filetype)
+	local buf = vim.api.nvim_create_buf(false, true)
+	vim.api.nvim_buf_set_lines(buf, 0, -1, true, lines)
+	pcall(vim.api.nvim_buf_set_name, buf, name)
+	vim.api.nvim_set_option_value("bufhidden", "wipe", { buf = buf })
+	vim.api.nvim_set_option_value("modifiable", false, { buf = buf })
+	vim.api.nvim_set_option_value("filetype", filetype, { buf = buf })
+	return buf
+end
 
---- @param multibuf integer
---- @param force_source_buf integer?
---- @param force_source_line integer?
-function M.multibuf_reload(multibuf, force_source_buf, force_source_line)
-	local info = multibufs[multibuf]
+--- Review the edits of a region. Opens a tabpage diffing the lines the region
+--- showed at the last reload against its current lines. Closing either
+--- window closes the other and wipes both scratch buffers.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @return integer|nil win window of the edited lines, nil if the region isn't loaded
+function M.multibuf_diff_source(mb, region_id)
+	vim.validate("region_id", region_id, "number")
+
+	local info = resolve_multibuf(mb)
 	if not info then
-		return
+		return nil
 	end
-	local win = get_buf_win(multibuf)
-	local sc_width = get_signcolumn_width(win)
-	local cursor_pos = win and vim.api.nvim_win_get_cursor(win)
-	local source_buf, source_line
-	if win then
-		if force_source_buf then
-			source_buf, source_line = force_source_buf, force_source_line
-		else
-			source_buf, source_line = M.multibuf_get_buf_at_line(multibuf, cursor_pos[1] - 1)
-		end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return nil
+	end
+	local b_info = info.bufs[b_idx]
+	local snapshot = b_info.snapshots and b_info.snapshots[s_idx]
+	local r_start, r_end = get_extmark_range(mb, b_info.region_extmark_ids[s_idx] or -1)
+	if not snapshot or not r_start then
+		return nil
 	end
 
-	vim.api.nvim_buf_clear_namespace(multibuf, M.multibuf__ns, 0, -1)
+	local lines = vim.api.nvim_buf_get_lines(mb, r_start, r_end, true)
+	local s_start = get_region_rows(b_info, s_idx) or 0
+	local name = string.format(
+		"%s:%d (region %d)",
+		vim.fn.fnamemodify(vim.api.nvim_buf_get_name(b_info.buf), ":~:."),
+		s_start + 1,
+		region_id
+	)
+	local filetype = vim.bo[b_info.buf].filetype
+	local original_buf = create_diff_scratch_buf(snapshot, name .. " original", filetype)
+	local edited_buf = create_diff_scratch_buf(lines, name .. " edited", filetype)
+
+	vim.cmd("tab split")
+	local original_win = vim.api.nvim_get_current_win()
+	vim.api.nvim_win_set_buf(original_win, original_buf)
+	vim.cmd("diffthis")
+	vim.cmd("rightbelow vsplit")
+	local edited_win = vim.api.nvim_get_current_win()
+	vim.api.nvim_win_set_buf(edited_win, edited_buf)
+	vim.cmd("diffthis")
+
+	local group = vim.api.nvim_create_augroup("MultibufDiff" .. edited_buf, { clear = true })
+	vim.api.nvim_create_autocmd("WinClosed", {
+		group = group,
+		pattern = { tostring(original_win), tostring(edited_win) },
+		once = true,
+		callback = function(args)
+			local other = tonumber(args.match) == original_win and edited_win or original_win
+			vim.schedule(function()
+				pcall(vim.api.nvim_win_close, other, true)
+			end)
+			vim.api.nvim_del_augroup_by_id(group)
+		end,
+	})
 
-	local header = info.header or create_multibuf_header()
-	local all_lines = { unpack(header) }
-	local virt_name_indices = {}
-	local virt_expand_lnums = {}
+	return edited_win
+end
 
-	-- 1. Build Text Content
-	for _, buf_info in ipairs(info.bufs) do
-		local has_content = false
-		if buf_info.pending_regions and #buf_info.pending_regions > 0 then
-			has_content = true
-		elseif #buf_info.source_extmark_ids > 0 then
-			has_content = true
-		end
+--- Echo the source location of a multibuffer line as `path:line`. Nothing is
+--- echoed for the header, titles and expander rows. Useful on |CursorHold|.
+--- @param mb integer multibuf id
+--- @param line integer|nil 0-indexed line in multibuffer, defaults to the cursor line
+function M.multibuf_echo_context(mb, line)
+	vim.validate("mb", mb, "number")
+	vim.validate("line", line, { "number", "nil" })
 
-		if has_content then
-			table.insert(virt_name_indices, #all_lines)
-			if buf_info.pending_regions then
-				for _, region in ipairs(buf_info.pending_regions) do
-					table.insert(virt_expand_lnums, #all_lines)
-					local count = (region.end_row - region.start_row) + 1
-					if count > 0 then
-						for _ = 1, count do
-							table.insert(all_lines, "") -- empty line while temporarily loading
-						end
-					end
-				end
-			else
-				for _, source_extmark_id in ipairs(buf_info.source_extmark_ids) do
-					local s_start, s_end = get_extmark_range(buf_info.buf, source_extmark_id)
-					if s_start and s_end then
-						table.insert(virt_expand_lnums, #all_lines)
-						vim.list_extend(all_lines, vim.api.nvim_buf_get_lines(buf_info.buf, s_start, s_end, true))
-					end
-				end
-			end
+	if not line then
+		local win = get_buf_win(mb)
+		if not win then
+			return
 		end
+		line = vim.api.nvim_win_get_cursor(win)[1] - 1
 	end
-	table.insert(virt_expand_lnums, #all_lines)
 
-	vim.api.nvim_set_option_value("modifiable", true, { buf = multibuf })
-	vim.api.nvim_buf_set_lines(multibuf, 0, -1, true, all_lines)
-	vim.api.nvim_set_option_value("modifiable", false, { buf = multibuf })
+	local ctx = M.multibuf_get_context(mb, line)
+	if not ctx then
+		return
+	end
+	vim.api.nvim_echo({ { format_context(ctx) } }, false, {})
+end
 
-	-- 2. Render Structure (Titles, Signs, Expanders)
-	local current_lnum = #header
-	local virt_expand_idx = 1
-	local name_idx_cursor = 1
-	local global_slice_idx = 0
+--- get the line number in the multibuf that points to bufnr optionally
+--- specifically the lnum in the bufnr. if lnum is nil then the first bufnr in
+--- the multibuf thats found is returned
+--- @param mb integer multibuf id
+--- @param bufnr integer the buffer to look for in the mutlibuffer
+--- @param lnum integer|nil optional line number to look for in bufnr
+--- @return integer|nil multibuf_lnum the line number of the multibuf or nil if
+--- the bufnr is not in the multibuf
+function M.multibuf_buf_get_line(mb, bufnr, lnum)
+	vim.validate("mb", mb, "number")
+	vim.validate("bufnr", bufnr, "number")
+	vim.validate("lnum", lnum, { "number", "nil" })
 
-	local function get_expander_sign(direction)
-		local signs = M.user_opts.expander_signs or {}
-		local text = signs[direction]
-		if text and text ~= "" then
-			return text
-		end
+	local info = resolve_multibuf(mb)
+	if not info then
 		return nil
 	end
+	for _, b in ipairs(info.bufs) do
+		if b.buf == bufnr then
+			if b.pending_regions then
+				for i, region in ipairs(b.pending_regions) do
+					local region_id = b.region_extmark_ids[i]
+					if not region_id then
+						goto continue
+					end
 
-	for b_idx, buf_info in ipairs(info.bufs) do
-		local has_content = false
-		if buf_info.pending_regions and #buf_info.pending_regions > 0 then
-			has_content = true
-		elseif #buf_info.source_extmark_ids > 0 then
-			has_content = true
-		end
-
-		if has_content then
-			buf_info.region_extmark_ids = {}
-			vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, virt_name_indices[name_idx_cursor], 0, {
-				virt_lines = render_multibuf_title(buf_info),
-				virt_lines_above = true,
-				virt_lines_leftcol = true,
-				priority = 20001,
-			})
-			name_idx_cursor = name_idx_cursor + 1
-
-			local last_s_end = 0
-			local source_line_count = vim.api.nvim_buf_line_count(buf_info.buf)
+					local ts, te = get_extmark_range(mb, region_id)
+					if not ts then
+						goto continue
+					end
 
-			local slices = {}
-			if buf_info.pending_regions then
-				for _, r in ipairs(buf_info.pending_regions) do
-					table.insert(slices, { s = r.start_row, e = r.end_row + 1 })
+					if lnum == nil then
+						return ts
+					elseif lnum >= region.start_row and lnum <= region.end_row then
+						return source_row_to_region(mb, b, i, lnum, ts, te, region.start_row, region.end_row + 1)
+					end
+					::continue::
 				end
 			else
-				for _, sid in ipairs(buf_info.source_extmark_ids) do
-					local s, e = get_extmark_range(buf_info.buf, sid)
-					if s then
-						table.insert(slices, { s = s, e = e })
+				for i, source_id in ipairs(b.source_extmark_ids) do
+					local region_id = b.region_extmark_ids[i]
+					if not region_id then
+						goto continue
 					end
-				end
-			end
-
-			for s_idx, slice in ipairs(slices) do
-				local s_start, s_end = slice.s, slice.e
-				local slice_len = s_end - s_start
-				local next_s_start = slices[s_idx + 1] and slices[s_idx + 1].s or source_line_count
-
-				global_slice_idx = global_slice_idx + 1
-				local is_odd = (global_slice_idx % 2 ~= 0)
-				local region_hl = is_odd and M.user_opts.region_hl_odd or M.user_opts.region_hl_even
-				local line_nr_hl = is_odd and "MultibufLineNrOdd" or "MultibufLineNrEven"
-				local expander_hl = is_odd and "MultibufExpanderOdd" or "MultibufExpanderEven"
 
-				-- Signs on visible lines
-				for i = 0, slice_len - 1 do
-					local special_sign = nil
-					if sc_width > 0 then
-						local is_first = (i == 0)
-						local is_last = (i == slice_len - 1)
-						local has_gap_above = (s_start > last_s_end)
-						local has_gap_below = (s_end < next_s_start)
+					local ts, te = get_extmark_range(mb, region_id)
+					if not ts then
+						goto continue
+					end
 
-						local needs_above = is_first and has_gap_above
-						local needs_below = is_last and has_gap_below
+					if lnum == nil then
+						return ts
+					end
 
-						if needs_above and needs_below then
-							special_sign = get_expander_sign("both")
-						elseif needs_above then
-							special_sign = get_expander_sign("above")
-						elseif needs_below then
-							special_sign = get_expander_sign("below")
-						end
+					local ss, se = get_extmark_range(bufnr, source_id)
+					if ss and se and lnum >= ss and lnum < se then
+						return source_row_to_region(mb, b, i, lnum, ts, te, ss, se)
 					end
-					place_line_number_signs(
-						multibuf,
-						current_lnum + i,
-						s_start + i,
-						sc_width,
-						special_sign,
-						line_nr_hl,
-						expander_hl
-					)
+					::continue::
 				end
+			end
+		end
+	end
 
-				-- Gap renderer above
-				if s_start > last_s_end then
-					place_expander(multibuf, current_lnum, {
-						expand_direction = (s_idx == 1) and "above" or "both",
-						count = s_start - last_s_end,
-						window = win or 0,
-						bufnr = buf_info.buf,
-						start_row = last_s_end,
-					})
-				end
+	return nil
+end
 
-				-- Gap renderer below (only for last slice of buffer)
-				if (s_idx == #slices) and (s_end < source_line_count) then
-					place_expander(multibuf, current_lnum + slice_len - 1, {
-						expand_direction = "below",
-						count = source_line_count - s_end,
-						window = win or 0,
-						bufnr = buf_info.buf,
-						start_row = s_end,
-					})
-				end
+--- List the regions of a multibuffer in render order. The `id` of a region
+--- is stable for its lifetime while `index` changes as regions are added or
+--- removed.
+--- @param mb integer multibuf id
+--- @return MultibufRegionInfo[]
+function M.multibuf_get_regions(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return {}
+	end
 
-				buf_info.region_extmark_ids[s_idx] =
-					vim.api.nvim_buf_set_extmark(multibuf, M.multibuf__ns, current_lnum, 0, {
-						end_row = current_lnum + slice_len,
-						end_right_gravity = true,
-					})
+	local regions = {}
+	for _, b in ipairs(info.bufs) do
+		for i, meta in ipairs(b.region_meta) do
+			local start_row, end_row = get_region_rows(b, i)
+			if start_row then
+				table.insert(regions, {
+					id = meta.id,
+					index = #regions + 1,
+					buf = b.buf,
+					start_row = start_row,
+					end_row = end_row,
+					unsaved_source = meta.unsaved_source == true,
+					target = meta.target and meta.target.buf,
+					origin = meta.origin,
+					start_col = meta.span and meta.span.start_col,
+					end_col = meta.span and meta.span.end_col,
+					label = meta.label,
+				})
+			end
+		end
+	end
+	return regions
+end
+
+--- Current source range of a region. Unlike |multibuf_get_regions| this
+--- resolves a single region.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @return integer|nil start_row, integer|nil end_row 0-indexed source rows (inclusive)
+function M.multibuf_region_source_range(mb, region_id)
+	vim.validate("region_id", region_id, "number")
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil, nil
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return nil, nil
+	end
+	return get_region_rows(info.bufs[b_idx], s_idx)
+end
+
+--- Source content a region was last reloaded from or written to, the base its
+--- edits are made against. Comparing it with the current source and the
+--- multibuffer tells whether the source changed under the edits.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @return MultibufRegionBase|nil base nil when the region isn't part of {mb} or never loaded
+function M.multibuf_region_base(mb, region_id)
+	vim.validate("region_id", region_id, "number")
 
-				current_lnum, last_s_end, virt_expand_idx = current_lnum + slice_len, s_end, virt_expand_idx + 1
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return nil
+	end
+	local bases = info.bufs[b_idx].bases
+	return bases and bases[s_idx] and vim.deepcopy(bases[s_idx])
+end
+
+local region_ref_methods = {
+	--- @param self MultibufRegionRef
+	range = function(self)
+		return M.multibuf_region_source_range(self.mb, self.id)
+	end,
+	--- @param self MultibufRegionRef
+	lines = function(self)
+		local start_row, end_row = M.multibuf_region_source_range(self.mb, self.id)
+		if not start_row or not vim.api.nvim_buf_is_valid(self.buf) then
+			return nil
+		end
+		vim.fn.bufload(self.buf)
+		return vim.api.nvim_buf_get_lines(self.buf, start_row, end_row + 1, false)
+	end,
+}
+local region_ref_mt = { __index = region_ref_methods }
+
+--- Iterate the regions of a multibuffer like |multibuf_get_regions| without
+--- resolving their ranges up front. The range and lines of a region are read
+--- when asked for, so they reflect source edits made while iterating: >lua
+---   for region in multibuffer.multibuf_each_region(mb) do
+---     local start_row, end_row = region:range()
+---   end
+--- <
+--- @param mb integer multibuf id
+--- @return fun(): MultibufRegionRef|nil
+function M.multibuf_each_region(mb)
+	local info = resolve_multibuf(mb)
+	local refs = {}
+	for _, b in ipairs(info and info.bufs or {}) do
+		for i, meta in ipairs(b.region_meta) do
+			-- same regions and indices as multibuf_get_regions
+			if get_region_rows(b, i) then
+				table.insert(
+					refs,
+					setmetatable({
+						mb = mb,
+						id = meta.id,
+						index = #refs + 1,
+						buf = b.buf,
+						unsaved_source = meta.unsaved_source == true,
+					}, region_ref_mt)
+				)
 			end
 		end
 	end
 
-	vim.api.nvim_set_option_value("modified", false, { buf = multibuf })
-	if win and cursor_pos then
-		local new_line
-		if source_buf and source_line then
-			new_line = M.multibuf_buf_get_line(multibuf, source_buf, source_line)
-		end
+	local idx = 0
+	return function()
+		idx = idx + 1
+		return refs[idx]
+	end
+end
 
-		if new_line then
-			vim.api.nvim_win_set_cursor(win, { new_line + 1, cursor_pos[2] })
-		else
-			-- fallback to old absolute pos, clamped
-			local line_count = vim.api.nvim_buf_line_count(multibuf)
-			local target_line = math.min(cursor_pos[1], line_count)
-			vim.api.nvim_win_set_cursor(win, { target_line, cursor_pos[2] })
-		end
+--- Set or clear the footer of a region. Footers are virtual lines below the
+--- last line of the region and don't change the multibuffer lines.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @param footer any[]|nil virt_lines, nil to remove the footer
+--- @return boolean ok false if the region is not part of the multibuf
+function M.multibuf_set_region_footer(mb, region_id, footer)
+	vim.validate("region_id", region_id, "number")
+	vim.validate("footer", footer, { "table", "nil" })
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false
+	end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return false
 	end
+	info.bufs[b_idx].region_meta[s_idx].footer = footer
+	M.multibuf_reload(mb)
+	return true
 end
 
---- @param opts MultibufSetupOptions
-function M.setup(opts)
-	M.user_opts = vim.tbl_deep_extend("force", M.user_opts, opts)
-	M.multibuf__ns = vim.api.nvim_create_namespace("Multibuf")
-	M.multibuf_hl_ns = vim.api.nvim_create_namespace("MultibufHighlights")
+--- Move many regions at once, e.g. after a formatter reflowed a source. An
+--- update is rejected when its region doesn't exist, its range is invalid or
+--- it would overlap or reorder the neighbouring regions of the same source.
+--- The multibuffer is reloaded once.
+--- @param mb integer multibuf id
+--- @param updates { region_id: integer, start_row: integer, end_row: integer }[]
+--- @return { applied: integer[], rejected: { region_id: integer, reason: string }[] } report
+function M.multibuf_update_regions(mb, updates)
+	vim.validate("updates", updates, "table")
 
-	local function update_highlights()
-		-- Define default highlight groups
-		-- We use 'default = true' so users can override them in their config,
-		-- but we always attempt to define them to ensure they exist and are up to date.
-		vim.api.nvim_set_hl(0, "MultibufRegionEven", { link = "Normal", default = true })
+	local report = { applied = {}, rejected = {} }
+	local info = resolve_multibuf(mb)
+	if not info then
+		for _, update in ipairs(updates) do
+			table.insert(report.rejected, { region_id = update.region_id, reason = "not a multibuffer" })
+		end
+		return report
+	end
 
-		local normal = vim.api.nvim_get_hl(0, { name = "Normal", link = false })
-		if normal.bg then
-			-- Always try to make it darker (subtract from RGB values)
-			local amount = -(M.user_opts.region_hl_odd_adjust or 12)
-			-- If background is already extremely dark (black), we have to go lighter
-			if vim.o.background == "dark" and normal.bg < 0x101010 then
-				amount = math.abs(amount)
-			end
-			local new_bg = adjust_color(normal.bg, amount)
-			vim.api.nvim_set_hl(0, "MultibufRegionOdd", { bg = new_bg, default = true })
-		else
-			vim.api.nvim_set_hl(0, "MultibufRegionOdd", { link = "CursorLine", default = true })
+	--- @type table<integer, { rows: MultibufRegion[], current: MultibufRegion[], updates: table<integer, table> }>
+	local proposed = {}
+	for _, update in ipairs(updates) do
+		local b_idx, s_idx = find_region_by_id(info, update.region_id)
+		local reason
+		if not b_idx or not s_idx then
+			reason = "unknown region"
+		elseif
+			type(update.start_row) ~= "number"
+			or type(update.end_row) ~= "number"
+			or update.start_row < 0
+			or update.end_row < update.start_row
+		then
+			reason = "invalid range"
+		elseif
+			not info.bufs[b_idx].pending_regions
+			and update.end_row >= vim.api.nvim_buf_line_count(info.bufs[b_idx].buf)
+		then
+			reason = "range is past the end of the source"
 		end
 
-		local function get_hl_attr(name, attr)
-			local hl = vim.api.nvim_get_hl(0, { name = name, link = false })
-			if hl[attr] then
-				return hl[attr]
-			end
-			-- fallback to Normal
-			if name ~= "Normal" then
-				return normal[attr]
+		if reason then
+			table.insert(report.rejected, { region_id = update.region_id, reason = reason })
+		else
+			--- @cast b_idx integer
+			--- @cast s_idx integer
+			local p = proposed[b_idx]
+			if not p then
+				p = { rows = {}, current = {}, updates = {} }
+				local b = info.bufs[b_idx]
+				for i = 1, #b.region_meta do
+					local s, e = get_region_rows(b, i)
+					p.current[i] = s and { start_row = s, end_row = e } or nil
+					p.rows[i] = p.current[i]
+				end
+				proposed[b_idx] = p
 			end
-			return nil
+			p.rows[s_idx] = { start_row = update.start_row, end_row = update.end_row }
+			p.updates[s_idx] = update
 		end
-
-		-- These groups MUST be redefined on every colorscheme change because they
-		-- depend on the colors of Normal, LineNr, and Folded which just changed.
-		local even_bg = get_hl_attr("MultibufRegionEven", "bg")
-		local odd_bg = get_hl_attr("MultibufRegionOdd", "bg")
-		local line_nr_fg = get_hl_attr("LineNr", "fg")
-		local folded_fg = get_hl_attr(M.user_opts.expander_sign_hl or "Folded", "fg")
-		local line_nr = vim.api.nvim_get_hl(0, { name = "LineNr", link = false })
-		local folded = vim.api.nvim_get_hl(0, { name = M.user_opts.expander_sign_hl or "Folded", link = false })
-		local cursor_line = vim.api.nvim_get_hl(0, { name = "CursorLine", link = false })
-
-		vim.api.nvim_set_hl(0, "MultibufLineNrEven", { fg = line_nr_fg, bg = even_bg, bold = line_nr.bold })
-		vim.api.nvim_set_hl(0, "MultibufLineNrOdd", { fg = line_nr_fg, bg = odd_bg, bold = line_nr.bold })
-		vim.api.nvim_set_hl(0, "MultibufExpanderEven", { fg = folded_fg, bg = even_bg, bold = folded.bold })
-		vim.api.nvim_set_hl(0, "MultibufExpanderOdd", { fg = folded_fg, bg = odd_bg, bold = folded.bold })
-
-		-- Sign column highlights when cursor is on the line (CursorLine background)
-		vim.api.nvim_set_hl(0, "MultibufLineNrCursorLine", {
-			fg = line_nr_fg,
-			bg = cursor_line.bg,
-			bold = line_nr.bold,
-			sp = cursor_line.sp,
-			special = cursor_line.special,
-		})
-		vim.api.nvim_set_hl(0, "MultibufExpanderCursorLine", {
-			fg = folded_fg,
-			bg = cursor_line.bg,
-			bold = folded.bold,
-			sp = cursor_line.sp,
-			special = cursor_line.special,
-		})
 	end
 
-	update_highlights()
-
-	vim.api.nvim_create_autocmd({ "ColorScheme", "OptionSet" }, {
-		callback = function(args)
-			if args.event == "OptionSet" and args.match ~= "background" then
-				return
+	for b_idx, p in pairs(proposed) do
+		-- reverting a rejected update can make another one invalid, so repeat until stable
+		local changed = true
+		while changed do
+			changed = false
+			for s_idx, update in pairs(p.updates) do
+				local rows, prev, next_rows = p.rows[s_idx], p.rows[s_idx - 1], p.rows[s_idx + 1]
+				if (prev and prev.end_row >= rows.start_row) or (next_rows and next_rows.start_row <= rows.end_row) then
+					p.rows[s_idx] = p.current[s_idx]
+					p.updates[s_idx] = nil
+					table.insert(report.rejected, {
+						region_id = update.region_id,
+						reason = "overlaps or reorders a neighbouring region",
+					})
+					changed = true
+				end
 			end
-			update_highlights()
-		end,
-	})
-
-	-- Decoration provider mirrors source highlights into the multibuffer viewport
-	local function incremental_load_source_and_update(winid, multibuf, top, bot)
-		if not M.multibuf_is_valid(multibuf) then
-			return false
-		end
-		local info = multibufs[multibuf]
-		if not info then
-			return false
 		end
 
-		local need_loadbufs = {}
-
-		local slice_lookup = {}
-		local global_slice_idx = 0
-		for _, b_info in ipairs(info.bufs) do
-			local has_content = false
-			if b_info.pending_regions and #b_info.pending_regions > 0 then
-				has_content = true
-			elseif #b_info.source_extmark_ids > 0 then
-				has_content = true
-			end
-			if has_content then
-				for i, reg_id in ipairs(b_info.region_extmark_ids) do
-					global_slice_idx = global_slice_idx + 1
-					local is_odd = (global_slice_idx % 2 ~= 0)
-					local region_hl = is_odd and M.user_opts.region_hl_odd or M.user_opts.region_hl_even
-					slice_lookup[reg_id] = { b_info = b_info, slice_idx = i, region_hl = region_hl }
-				end
+		local b = info.bufs[b_idx]
+		for s_idx, update in pairs(p.updates) do
+			if b.pending_regions then
+				b.pending_regions[s_idx].start_row = update.start_row
+				b.pending_regions[s_idx].end_row = update.end_row
+			else
+				b.source_extmark_ids[s_idx] = vim.api.nvim_buf_set_extmark(b.buf, M.multibuf__ns, update.start_row, 0, {
+					id = b.source_extmark_ids[s_idx],
+					end_row = update.end_row + 1,
+					end_right_gravity = true,
+				})
 			end
+			table.insert(report.applied, update.region_id)
 		end
+	end
 
-		local cursor_row = -1
-		pcall(function()
-			cursor_row = vim.api.nvim_win_get_cursor(winid)[1] - 1
-		end)
+	if #report.applied > 0 then
+		M.multibuf_reload(mb)
+	end
+	return report
+end
 
-		local slices = vim.api.nvim_buf_get_extmarks(
-			multibuf,
-			M.multibuf__ns,
-			{ top, 0 },
-			{ bot, -1 },
-			{ details = true, overlap = true }
-		)
-		for _, extmark in ipairs(slices) do
-			local lookup = slice_lookup[extmark[1]]
-			if lookup then
-				local b_info = lookup.b_info
-				local i = lookup.slice_idx
-				local region_hl = lookup.region_hl
+--- Remove a region from a multibuffer.
+--- @param mb integer multibuf id
+--- @param region_id integer stable region id (see |multibuf_get_regions|)
+--- @return boolean removed false if the region is not part of the multibuf
+function M.multibuf_remove_region(mb, region_id)
+	vim.validate("region_id", region_id, "number")
 
-				if b_info.pending_regions then
-					if not b_info.loading then
-						b_info.loading = true
-						table.insert(need_loadbufs, b_info)
-					end
-					goto next_extmark
-				end
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false
+	end
 
-				local r_start, r_end = get_extmark_range(multibuf, extmark[1])
-				if not r_start then
-					goto next_extmark
-				end
+	local b_idx, s_idx = find_region_by_id(info, region_id)
+	if not b_idx or not s_idx then
+		return false
+	end
 
-				local v_start, v_end = math.max(top, r_start), math.min(bot + 1, r_end)
-				if v_start < v_end then
-					-- 1. Apply alternating background (skipping cursor line)
-					local function set_region_hl(s, e)
-						if s >= e then
-							return
-						end
-						pcall(vim.api.nvim_buf_set_extmark, multibuf, M.multibuf_hl_ns, s, 0, {
-							end_row = e,
-							hl_group = region_hl,
-							hl_eol = true,
-							line_hl_group = region_hl,
-							ephemeral = true,
-							priority = 0,
-						})
-					end
+	drop_region(mb, info, b_idx, s_idx)
+	M.multibuf_reload(mb)
+	return true
+end
 
-					if cursor_row >= v_start and cursor_row < v_end then
-						set_region_hl(v_start, cursor_row)
-						set_region_hl(cursor_row + 1, v_end)
-					else
-						set_region_hl(v_start, v_end)
-					end
+--- Check the region invariants of a multibuffer: the regions of each source
+--- are ascending and disjoint in the source and the regions in the
+--- multibuffer are ascending and disjoint.
+--- @param mb integer multibuf id
+--- @return boolean ok, string|nil err description of the first violation
+function M.multibuf_validate(mb)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return false, "not a multibuffer"
+	end
 
-					-- 2. Project highlights from source
-					local s_ext_id = b_info.source_extmark_ids[i]
-					if s_ext_id then
-						local s_start, _ = get_extmark_range(b_info.buf, s_ext_id)
-						if s_start then
-							local s_range_start = s_start + (v_start - r_start)
-							local s_range_end = s_range_start + (v_end - v_start)
+	local last_row = 0
+	for _, b in ipairs(info.bufs) do
+		if not b.pending_regions then
+			if #b.region_extmark_ids ~= #b.source_extmark_ids then
+				return false,
+					string.format(
+						"buffer %d has %d source regions but %d multibuffer regions",
+						b.buf,
+						#b.source_extmark_ids,
+						#b.region_extmark_ids
+					)
+			end
 
-							local s_ft = vim.api.nvim_get_option_value("filetype", { buf = b_info.buf })
-							local s_lang = vim.treesitter.language.get_lang(s_ft)
-							if s_lang then
-								project_highlights(multibuf, b_info.buf, s_range_start, s_range_end, v_start)
-							end
-						end
-					end
+			local last_s_end = 0
+			for i, sid in ipairs(b.source_extmark_ids) do
+				local s, e = get_extmark_range(b.buf, sid)
+				if not s then
+					return false, string.format("buffer %d region %d lost its source extmark", b.buf, i)
 				end
+				if s < last_s_end then
+					return false,
+						string.format(
+							"buffer %d region %d starts at %d before previous end %d",
+							b.buf,
+							i,
+							s,
+							last_s_end
+						)
+				end
+				last_s_end = e
 			end
-			::next_extmark::
 		end
 
-		-- Reload to replace placeholders with real content
-		if #need_loadbufs > 0 then
-			vim.schedule(function()
-				for _, b_info in ipairs(need_loadbufs) do
-					load_source_buf(multibuf, b_info)
-				end
-				M.multibuf_reload(multibuf)
-			end)
+		for i, rid in ipairs(b.region_extmark_ids) do
+			local s, e = get_extmark_range(mb, rid)
+			if not s then
+				return false, string.format("buffer %d region %d lost its multibuffer extmark", b.buf, i)
+			end
+			if s < last_row or e < s then
+				return false,
+					string.format("buffer %d region %d at rows %d-%d overlaps row %d", b.buf, i, s, e, last_row)
+			end
+			last_row = e
 		end
+	end
 
-		return true
-	end
-
-	vim.api.nvim_set_decoration_provider(M.multibuf_hl_ns, {
-		on_win = function(_, winid, multibuf, top, bot)
-			return incremental_load_source_and_update(winid, multibuf, top, bot)
-		end,
-	})
-end
-
---- @class CreateMultibufOptions
---- @field header string[]|nil
-
---- @param opts CreateMultibufOptions|nil
---- @return integer mbufnr
-function M.create_multibuf(opts)
-	opts = opts or {}
-	vim.validate("opts.header", opts.header, { "table", "nil" })
-
-	local id = vim.api.nvim_create_buf(true, true)
-	local header = opts.header or create_multibuf_header()
-	local info = { bufs = {}, header = header }
-	vim.api.nvim_set_option_value("buftype", "acwrite", { buf = id })
-	vim.api.nvim_set_option_value("filetype", "multibuffer", { buf = id })
-	vim.api.nvim_set_option_value("modifiable", false, { buf = id })
-	multibufs[id] = info
-
-	vim.api.nvim_create_autocmd({ "BufReadCmd", "BufWriteCmd" }, {
-		buffer = id,
-		callback = function(args)
-			pcall(vim.treesitter.stop, args.buf)
-			M.multibuf_reload(args.buf)
-		end,
-	})
-	vim.api.nvim_create_autocmd("BufWipeout", {
-		buffer = id,
-		callback = function(args)
-			M.multibuf__wipeout(args.buf)
-		end,
-	})
-
-	return id
+	return true, nil
 end
 
---- @param mb integer
---- @param header string[]
-function M.multibuf_set_header(mb, header)
-	local info = multibufs[mb]
+--- Realign a multibuffer whose regions got out of sync with its content, e.g.
+--- after whole regions or the lines around them were deleted. Regions whose
+--- source is gone are dropped, overlapping source regions are merged and
+--- every remaining region is rendered again from its source. Edits that
+--- were not written yet are discarded.
+--- @param mb integer multibuf id
+--- @return { repaired: integer[], dropped: { region_id: integer, reason: string }[], merged: integer, discarded_edits: boolean, ok: boolean, err: string|nil } summary
+function M.multibuf_reconcile(mb)
+	local summary = { repaired = {}, dropped = {}, merged = 0, discarded_edits = false, ok = false, err = nil }
+	local info = resolve_multibuf(mb)
 	if not info then
-		return
+		summary.err = "not a multibuffer"
+		return summary
 	end
-	info.header = header
+	summary.discarded_edits = vim.bo[mb].modified
+
+	for b_idx = #info.bufs, 1, -1 do
+		local b = info.bufs[b_idx]
+		if not vim.api.nvim_buf_is_valid(b.buf) then
+			for _, meta in ipairs(b.region_meta) do
+				table.insert(summary.dropped, { region_id = meta.id, reason = "source buffer was deleted" })
+			end
+			table.remove(info.bufs, b_idx)
+		elseif not b.pending_regions then
+			for s_idx = #b.source_extmark_ids, 1, -1 do
+				local meta = b.region_meta[s_idx]
+				local s, e = get_extmark_range(b.buf, b.source_extmark_ids[s_idx])
+				local reason
+				if not s then
+					reason = "source extmark is gone"
+				elseif e <= s then
+					reason = "source lines were deleted"
+				end
+
+				if reason then
+					if meta then
+						table.insert(summary.dropped, { region_id = meta.id, reason = reason })
+					end
+					drop_region(mb, info, b_idx, s_idx)
+				else
+					local rid = b.region_extmark_ids[s_idx]
+					local r_start, r_end = nil, nil
+					if rid then
+						r_start, r_end = get_extmark_range(mb, rid)
+					end
+					local elision = b.elisions and b.elisions[s_idx]
+					local expected = elision and (elision.head + elision.tail) or (e - s)
+					if meta and (not r_start or r_end - r_start ~= expected) then
+						table.insert(summary.repaired, meta.id)
+					end
+				end
+			end
+
+			-- dropping its last region removed the entry
+			if info.bufs[b_idx] == b then
+				local count = #b.source_extmark_ids
+				merge_buffer_regions(b)
+				summary.merged = summary.merged + count - #b.source_extmark_ids
+				if #b.source_extmark_ids == 0 then
+					table.remove(info.bufs, b_idx)
+				end
+			end
+		end
+	end
+
+	-- a reload starts from the source extmarks and lays out every region again
 	M.multibuf_reload(mb)
+	summary.ok, summary.err = M.multibuf_validate(mb)
+	if summary.ok and info.invalid then
+		info.invalid = nil
+		vim.api.nvim_set_option_value("readonly", false, { buf = mb })
+	end
+	return summary
 end
 
---- @param buf integer
---- @return boolean
-function M.multibuf_is_valid(buf)
-	return multibufs[buf] ~= nil
+--- Move the cursor of the window showing the multibuffer to the first region
+--- of a source buffer. When {source} is a string it is matched as a substring
+--- of the buffer name and the first match in render order wins.
+--- @param mb integer multibuf id
+--- @param source integer|string source buffer handle or part of its name
+--- @return integer|nil multibuf_lnum 0-indexed target line or nil if the
+--- source is not in the multibuf
+function M.multibuf_goto_source(mb, source)
+	vim.validate("mb", mb, "number")
+	vim.validate("source", source, { "number", "string" })
+
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
+	end
+
+	for _, b in ipairs(info.bufs) do
+		local matches
+		if type(source) == "number" then
+			matches = b.buf == source
+		else
+			matches = string.find(vim.api.nvim_buf_get_name(b.buf), source, 1, true) ~= nil
+		end
+
+		local region_id = matches and b.region_extmark_ids[1]
+		local line = region_id and get_extmark_range(mb, region_id)
+		if line then
+			local win = get_buf_win(mb)
+			if win then
+				vim.api.nvim_win_set_cursor(win, { line + 1, 0 })
+			end
+			return line
+		end
+	end
+
+	return nil
 end
 
+--- First multibuffer row and id of every loaded region, in render order.
 --- @param mb integer
---- @param opts MultibufAddBufOptions
-function M.multibuf_add_buf(mb, opts)
-	M.multibuf_add_bufs(mb, { opts })
+--- @param info MultibufInfo
+--- @return { row: integer, id: integer }[]
+local function list_region_starts(mb, info)
+	local starts = {}
+	for _, b in ipairs(info.bufs) do
+		for s_idx, rid in ipairs(b.region_extmark_ids) do
+			local r_start = get_extmark_range(mb, rid)
+			local meta = b.region_meta[s_idx]
+			if r_start and meta then
+				table.insert(starts, { row = r_start, id = meta.id })
+			end
+		end
+	end
+	return starts
 end
 
 --- @param mb integer
-function M.multibuf_clear_bufs(mb)
-	local info = multibufs[mb]
-	if not info then
-		return
+--- @param starts { row: integer, id: integer }[]
+--- @param index integer out of range indices are clamped or wrapped
+--- @param opts MultibufJumpOptions
+--- @return integer|nil region_id, integer|nil index
+local function land_on_region(mb, starts, index, opts)
+	local win = get_buf_win(mb)
+	if not win or #starts == 0 then
+		return nil, nil
 	end
-	info.bufs = {}
-	pending_adds[mb] = nil
-	M.multibuf_reload(mb)
+	if opts.wrap then
+		index = (index - 1) % #starts + 1
+	else
+		index = clamp(index, 1, #starts)
+	end
+
+	vim.api.nvim_win_set_cursor(win, { starts[index].row + 1, 0 })
+	vim.api.nvim_win_call(win, function()
+		if opts.open_folds then
+			vim.cmd("normal! zv")
+		end
+		if opts.center then
+			vim.cmd("normal! zz")
+		end
+	end)
+	return starts[index].id, index
 end
 
---- @param mb integer
---- @param opts_list MultibufAddBufOptions[]
-function M.multibuf_add_bufs(mb, opts_list)
-	local info = multibufs[mb]
+--- Move the cursor of the window showing the multibuffer to the first line
+--- of a region. {index} counts regions in render order like
+--- |multibuf_get_regions| and is clamped to the existing regions.
+--- @param mb integer multibuf id
+--- @param index integer 1-indexed region position
+--- @param opts MultibufJumpOptions|nil
+--- @return integer|nil region_id, integer|nil index the region landed on
+function M.multibuf_jump_to_region(mb, index, opts)
+	vim.validate("index", index, "number")
+	vim.validate("opts", opts, { "table", "nil" })
+
+	local info = resolve_multibuf(mb)
 	if not info then
-		return
+		return nil, nil
 	end
+	return land_on_region(mb, list_region_starts(mb, info), index, opts or {})
+end
 
-	if not pending_adds[mb] then
-		pending_adds[mb] = {}
+--- @param mb integer
+--- @param count integer positive to move forward, negative to move back
+--- @param opts MultibufJumpOptions|nil
+--- @return integer|nil region_id, integer|nil index
+local function jump_regions(mb, count, opts)
+	local info = resolve_multibuf(mb)
+	local win = get_buf_win(mb)
+	if not info or not win then
+		return nil, nil
 	end
 
-	for _, opts in ipairs(opts_list) do
-		table.insert(pending_adds[mb], opts)
+	local starts = list_region_starts(mb, info)
+	local row = vim.api.nvim_win_get_cursor(win)[1] - 1
+	-- like ]c and [c the current region only counts when moving forward
+	local passed = 0
+	for _, start in ipairs(starts) do
+		if start.row < row or (count > 0 and start.row == row) then
+			passed = passed + 1
+		end
 	end
+	local index = count > 0 and passed + count or passed + count + 1
+	return land_on_region(mb, starts, index, opts or {})
+end
 
-	process_pending_adds(mb)
+--- Move to the first line of the [count]th region after the cursor. Stops at
+--- the last region unless `wrap` is set, e.g. for a `]r` mapping: >lua
+---   vim.keymap.set("n", "]r", function()
+---     local mb = vim.api.nvim_get_current_buf()
+---     multibuffer.multibuf_next_region(mb, vim.v.count1)
+---   end)
+--- <
+--- @param mb integer multibuf id
+--- @param count integer|nil defaults to 1
+--- @param opts MultibufJumpOptions|nil
+--- @return integer|nil region_id, integer|nil index the region landed on
+function M.multibuf_next_region(mb, count, opts)
+	vim.validate("count", count, { "number", "nil" })
+	vim.validate("opts", opts, { "table", "nil" })
+	return jump_regions(mb, math.max(1, count or 1), opts)
 end
 
---- @param win integer window handle
---- @param mb integer multibuffer handle
-function M.win_set_multibuf(win, mb)
-	vim.api.nvim_win_set_buf(win, mb)
+--- Move to the first line of the [count]th region before the cursor, the
+--- first line of the current region counts when the cursor is below it.
+--- @param mb integer multibuf id
+--- @param count integer|nil defaults to 1
+--- @param opts MultibufJumpOptions|nil
+--- @return integer|nil region_id, integer|nil index the region landed on
+function M.multibuf_prev_region(mb, count, opts)
+	vim.validate("count", count, { "number", "nil" })
+	vim.validate("opts", opts, { "table", "nil" })
+	return jump_regions(mb, -math.max(1, count or 1), opts)
 end
 
+--- First changed row of every region edited since the last reload or write,
+--- in render order.
 --- @param mb integer
---- @return boolean
-function M.multibuf_is_loading(mb)
-	local info = multibufs[mb]
-	if not info then
-		return false
-	end
-	if pending_adds[mb] and #pending_adds[mb] > 0 then
-		return true
-	end
+--- @param info MultibufInfo
+--- @return integer[] rows 0-indexed multibuffer rows
+local function list_change_rows(mb, info)
+	local rows = {}
 	for _, b in ipairs(info.bufs) do
-		if b.pending_regions then
-			return true
+		for s_idx, rid in ipairs(b.region_extmark_ids) do
+			if region_is_dirty(mb, b, s_idx) then
+				local r_start, r_end = get_extmark_range(mb, rid) --[[@as integer]]
+				local lines = vim.api.nvim_buf_get_lines(mb, r_start, r_end, true)
+				local snapshot = b.snapshots[s_idx]
+				local i = 1
+				while i <= #lines and i <= #snapshot and lines[i] == snapshot[i] do
+					i = i + 1
+				end
+				-- lines deleted from the end of the region show up on its last line
+				table.insert(rows, math.min(r_start + i - 1, math.max(r_start, r_end - 1)))
+			end
 		end
 	end
-	return false
+	return rows
 end
 
---- @param buf integer
-function M.multibuf__wipeout(buf)
-	if multibufs[buf] then
-		multibufs[buf] = nil
-		pending_adds[buf] = nil
+--- @param mb integer
+--- @param from_line integer|nil
+--- @param forward boolean
+--- @return integer|nil line
+local function jump_change(mb, from_line, forward)
+	local info = resolve_multibuf(mb)
+	if not info then
+		return nil
 	end
-
-	if buf_listeners[buf] then
-		pcall(vim.api.nvim_del_autocmd, buf_listeners[buf].change_autocmd_id)
-		buf_listeners[buf] = nil
+	local win = get_buf_win(mb)
+	if not from_line then
+		if not win then
+			return nil
+		end
+		from_line = vim.api.nvim_win_get_cursor(win)[1] - 1
 	end
 
-	-- Remove this buffer from any multibuffer that contains it
-	for mb, info in pairs(multibufs) do
-		local changed = false
-		for i = #info.bufs, 1, -1 do
-			if info.bufs[i].buf == buf then
-				table.remove(info.bufs, i)
-				changed = true
-			end
+	local target
+	for _, row in ipairs(list_change_rows(mb, info)) do
+		if forward and row > from_line then
+			target = row
+			break
+		elseif not forward and row < from_line then
+			target = row
 		end
-		if changed then
-			vim.schedule(function()
-				if M.multibuf_is_valid(mb) then
-					M.multibuf_reload(mb)
+	end
+	if target and win then
+		vim.api.nvim_win_set_cursor(win, { target + 1, 0 })
+	end
+	return target
+end
+
+--- Move to the first changed line of the next region edited since the last
+--- reload or write, skipping unchanged regions.
+--- @param mb integer multibuf id
+--- @param from_line integer|nil 0-indexed line to search from, defaults to the cursor line
+--- @return integer|nil line 0-indexed line moved to, nil when there is no change after {from_line}
+function M.multibuf_next_change(mb, from_line)
+	vim.validate("from_line", from_line, { "number", "nil" })
+	return jump_change(mb, from_line, true)
+end
+
+--- Like |multibuf_next_change| but moves to the change before {from_line}.
+--- @param mb integer multibuf id
+--- @param from_line integer|nil 0-indexed line to search from, defaults to the cursor line
+--- @return integer|nil line 0-indexed line moved to, nil when there is no change before {from_line}
+function M.multibuf_prev_change(mb, from_line)
+	vim.validate("from_line", from_line, { "number", "nil" })
+	return jump_change(mb, from_line, false)
+end
+
+-- per region state of a source entry, kept aligned when regions move between entries
+local ENTRY_REGION_KEYS = {
+	"pending_regions",
+	"source_extmark_ids",
+	"region_extmark_ids",
+	"region_meta",
+	"snapshots",
+	"fingerprints",
+	"bases",
+	"elisions",
+	"conflicts",
+}
+
+--- Move regions {from} to {to} of {b_info} into a new entry of the same
+--- source, together with their state.
+--- @param b_info MultibufBufInfo
+--- @param from integer
+--- @param to integer
+--- @return MultibufBufInfo entry
+local function split_entry(b_info, from, to)
+	local entry = {
+		buf = b_info.buf,
+		virt_expand_extmark_ids = {},
+		title = b_info.title,
+		id = b_info.id,
+		readonly = b_info.readonly,
+		source_line_count = b_info.source_line_count,
+		changedtick = b_info.changedtick,
+	}
+	for _, key in ipairs(ENTRY_REGION_KEYS) do
+		local map = b_info[key]
+		if map then
+			local taken, rest = {}, {}
+			for i, v in pairs(map) do
+				if i < from then
+					rest[i] = v
+				elseif i > to then
+					rest[i - (to - from + 1)] = v
+				else
+					taken[i - from + 1] = v
 				end
-			end)
+			end
+			entry[key], b_info[key] = taken, rest
 		end
 	end
+	return entry
 end
 
---- @param mb integer
---- @param line integer 0-indexed line in multibuffer
---- @return integer|nil bufnr, integer|nil source_line
-function M.multibuf_get_buf_at_line(mb, line)
-	local info = multibufs[mb]
-	if not info then
-		return nil, nil
-	end
-	local marks = vim.api.nvim_buf_get_extmarks(
-		mb,
-		M.multibuf__ns,
-		{ line, 0 },
-		{ line, -1 },
-		{ details = true, overlap = true }
-	)
-	for _, m in ipairs(marks) do
-		for _, b in ipairs(info.bufs) do
-			for i, rid in ipairs(b.region_extmark_ids) do
-				if m[1] == rid then
-					local rs, _ = get_extmark_range(mb, rid)
-					if not rs then
-						goto next_mark
-					end
-					local sid = b.source_extmark_ids and b.source_extmark_ids[i]
-					if sid then
-						local ss, _ = get_extmark_range(b.buf, sid)
-						if ss then
-							return b.buf, ss + (line - rs)
-						end
-					elseif b.pending_regions and b.pending_regions[i] then
-						return b.buf, b.pending_regions[i].start_row + (line - rs)
+--- Join {entries} with neighbouring entries of the same source, title and id
+--- when their regions are still in source order, e.g. after a region moved
+--- back between them.
+--- @param info MultibufInfo
+--- @param entries table<MultibufBufInfo, boolean>
+local function join_entries(info, entries)
+	local b_idx = 1
+	while b_idx < #info.bufs do
+		local a, b = info.bufs[b_idx], info.bufs[b_idx + 1]
+		local a_end = not a.pending_regions
+			and a.source_extmark_ids[#a.source_extmark_ids]
+			and select(2, get_extmark_range(a.buf, a.source_extmark_ids[#a.source_extmark_ids]))
+		local b_start = not b.pending_regions
+			and b.source_extmark_ids[1]
+			and get_extmark_range(b.buf, b.source_extmark_ids[1])
+		local joinable = (entries[a] or entries[b]) and a.buf == b.buf and a.title == b.title and a.id == b.id
+		if joinable and a_end and b_start and a_end <= b_start then
+			local offset = #a.region_meta
+			for _, key in ipairs(ENTRY_REGION_KEYS) do
+				if b[key] then
+					a[key] = a[key] or {}
+					for i, v in pairs(b[key]) do
+						a[key][offset + i] = v
 					end
-					return b.buf, nil
 				end
 			end
+			table.remove(info.bufs, b_idx + 1)
+			entries[a] = true
+		else
+			b_idx = b_idx + 1
 		end
-		::next_mark::
 	end
-	return nil, nil
 end
 
---- get the line number in the multibuf that points to bufnr optionally
---- specifically the lnum in the bufnr. if lnum is nil then the first bufnr in
---- the multibuf thats found is returned
---- @param mb integer multibuf id
---- @param bufnr integer the buffer to look for in the mutlibuffer
---- @param lnum integer|nil optional line number to look for in bufnr
---- @return integer|nil multibuf_lnum the line number of the multibuf or nil if
---- the bufnr is not in the multibuf
-function M.multibuf_buf_get_line(mb, bufnr, lnum)
-	vim.validate("mb", mb, "number")
-	vim.validate("bufnr", bufnr, "number")
-	vim.validate("lnum", lnum, { "number", "nil" })
+--- Pick a region of {mb} with |vim.ui.select()| and jump to its first line.
+--- A multibuffer not shown in a window is shown in the current one first.
+--- @param mb integer|nil multibuf id, the current or active multibuffer when nil
+function M.multibuf_select_region(mb)
+	vim.validate("mb", mb, { "number", "nil" })
+	mb = mb or M.multibuf_handle_of(vim.api.nvim_get_current_buf()) or M.multibuf_get_active()
+	local info = resolve_multibuf(mb)
+	if not mb or not info then
+		vim.notify("multibuffer: no multibuffer to pick a region from", vim.log.levels.WARN)
+		return
+	end
+	local regions = M.multibuf_get_regions(mb)
+	if #regions == 0 then
+		vim.notify("multibuffer: no regions", vim.log.levels.INFO)
+		return
+	end
+	vim.ui.select(regions, {
+		prompt = "Jump to region",
+		format_item = format_region_item,
+	}, function(region)
+		-- cancelled, or the multibuffer went away while picking
+		if not region or not M.multibuf_is_valid(mb) then
+			return
+		end
+		if not get_buf_win(mb) then
+			M.win_set_multibuf(0, mb)
+		end
+		for i, start in ipairs(list_region_starts(mb, info)) do
+			if start.id == region.id then
+				M.multibuf_jump_to_region(mb, i)
+				return
+			end
+		end
+		vim.notify(string.format("multibuffer: region %d isn't loaded", region.id), vim.log.levels.WARN)
+	end)
+end
 
-	local info = multibufs[mb]
+--- Move a region so it renders at the position of the region at
+--- {target_line}: before it when moving up, after it when moving down. Regions
+--- of a source render in source order, so the region gets its own entry and
+--- title unless it ends up next to regions of its source it follows.
+--- Unwritten edits move with their regions.
+--- @param mb integer multibuf id
+--- @param region_id integer
+--- @param target_line integer 0-indexed multibuffer line
+--- @return integer|nil index, integer|nil line the new 1-indexed position and first 0-indexed line of the region
+function M.multibuf_move_region_to_line(mb, region_id, target_line)
+	vim.validate("region_id", region_id, "number")
+	vim.validate("target_line", target_line, "number")
+
+	local info = resolve_multibuf(mb)
 	if not info then
-		return nil
+		return nil, nil
+	end
+	local starts = list_region_starts(mb, info)
+	local order = {}
+	for i, start in ipairs(starts) do
+		order[start.id] = i
+	end
+	-- snap to the start of the region the line is in
+	local target = starts[1]
+	for _, start in ipairs(starts) do
+		if start.row <= target_line then
+			target = start
+		end
+	end
+	if not order[region_id] or not target then
+		return nil, nil
 	end
-	for _, b in ipairs(info.bufs) do
-		if b.buf == bufnr then
-			if b.pending_regions then
-				for i, region in ipairs(b.pending_regions) do
-					local region_id = b.region_extmark_ids[i]
-					if not region_id then
-						goto continue
-					end
-
-					local ts, _ = get_extmark_range(mb, region_id)
-					if not ts then
-						goto continue
-					end
-
-					if lnum == nil then
-						return ts
-					elseif lnum >= region.start_row and lnum <= region.end_row then
-						return ts + (lnum - region.start_row)
-					end
-					::continue::
-				end
-			else
-				for i, source_id in ipairs(b.source_extmark_ids) do
-					local region_id = b.region_extmark_ids[i]
-					if not region_id then
-						goto continue
-					end
 
-					local ts, _ = get_extmark_range(mb, region_id)
-					if not ts then
-						goto continue
-					end
+	if target.id ~= region_id then
+		local moving_down = order[region_id] < order[target.id]
+		local b_idx, s_idx = find_region_by_id(info, region_id) --[[@as integer]]
+		local moved = split_entry(info.bufs[b_idx], s_idx, s_idx)
+		if #info.bufs[b_idx].region_meta == 0 then
+			table.remove(info.bufs, b_idx)
+		end
 
-					if lnum == nil then
-						return ts
-					end
+		local t_idx, t_s_idx = find_region_by_id(info, target.id) --[[@as integer]]
+		local t_info = info.bufs[t_idx]
+		-- the regions behind the insertion point get an entry of their own
+		local split_at = moving_down and t_s_idx + 1 or t_s_idx
+		local entries = { [moved] = true }
+		if split_at > 1 and split_at <= #t_info.region_meta then
+			local tail = split_entry(t_info, split_at, #t_info.region_meta)
+			table.insert(info.bufs, t_idx + 1, tail)
+			entries[tail] = true
+		end
+		table.insert(info.bufs, split_at > 1 and t_idx + 1 or t_idx, moved)
+		join_entries(info, entries)
+		M.multibuf_reload(mb, { keep_edits = true })
+	end
 
-					local ss, se = get_extmark_range(bufnr, source_id)
-					if ss and se and lnum >= ss and lnum < se then
-						return ts + (lnum - ss)
-					end
-					::continue::
-				end
-			end
+	for i, start in ipairs(list_region_starts(mb, info)) do
+		if start.id == region_id then
+			return i, start.row
 		end
 	end
+	return nil, nil
+end
 
-	return nil
+--- Get the foldable ranges of the source buffers mapped to multibuffer rows.
+--- Always empty unless `fold_regions` is enabled in setup.
+--- @param mb integer multibuf id
+--- @return MultibufRegion[] folds 0-indexed multibuffer ranges (inclusive)
+function M.multibuf_get_folds(mb)
+	local info = resolve_multibuf(mb)
+	if not info or not info.folds then
+		return {}
+	end
+	return vim.deepcopy(info.folds)
+end
+
+--- 'foldexpr' for multibuffer windows that mirrors the source folds.
+--- e.g. `foldexpr=v:lua.require'multibuffer'.multibuf_foldexpr(v:lnum)`
+--- @param lnum integer 1-indexed line number (|v:lnum|)
+--- @return string
+function M.multibuf_foldexpr(lnum)
+	local info = multibufs[vim.api.nvim_get_current_buf()]
+	if not info or not info.fold_levels then
+		return "0"
+	end
+	return info.fold_levels[lnum - 1] or "0"
 end
 
 --- Expand or shrink a slice in a multibuffer.
@@ -1283,7 +7976,7 @@ function M.multibuf_slice_expand(mb, delta_top, delta_bot, line)
 		return
 	end
 
-	local info = multibufs[mb]
+	local info = resolve_multibuf(mb)
 	local b_info = info.bufs[b_idx]
 
 	if b_info.pending_regions then
@@ -1293,6 +7986,7 @@ function M.multibuf_slice_expand(mb, delta_top, delta_bot, line)
 
 		if region.start_row > region.end_row then
 			table.remove(b_info.pending_regions, s_idx)
+			table.remove(b_info.region_meta, s_idx)
 			if #b_info.pending_regions == 0 then
 				table.remove(info.bufs, b_idx)
 			end
@@ -1314,8 +8008,9 @@ function M.multibuf_slice_expand(mb, delta_top, delta_bot, line)
 		local ne = math.min(line_count, e + delta_bot)
 
 		if ns >= ne then
-			vim.api.nvim_buf_del_extmark(b_info.buf, M.multibuf__ns, sid)
+			remove_source_extmark(b_info.buf, M.multibuf__ns, sid)
 			table.remove(b_info.source_extmark_ids, s_idx)
+			table.remove(b_info.region_meta, s_idx)
 			if #b_info.source_extmark_ids == 0 then
 				table.remove(info.bufs, b_idx)
 			end
@@ -1332,7 +8027,7 @@ function M.multibuf_slice_expand(mb, delta_top, delta_bot, line)
 	end
 
 	merge_buffer_regions(b_info)
-	M.multibuf_reload(mb, source_buf, source_line)
+	M.multibuf_reload(mb, { cursor_buf = source_buf, cursor_line = source_line })
 end
 
 --- @param mb integer
@@ -1352,7 +8047,13 @@ end
 --- @param bufnr integer
 --- @return any[]
 function M.default_render_multibuf_title(bufnr)
-	return { { { "" } }, { { " " .. vim.api.nvim_buf_get_name(bufnr) .. "  ", "TabLine" } }, { { "" } } }
+	local name = vim.api.nvim_buf_get_name(bufnr)
+	if is_help_buf(bufnr) then
+		-- runtime paths are long and all look alike, show the help file name
+		name = vim.fn.fnamemodify(name, ":t")
+	end
+	local hl = (is_help_buf(bufnr) or not vim.bo[bufnr].modifiable) and "MultibufRegionReadonly" or "MultibufTitle"
+	return { { { "" } }, { { " " .. name .. "  ", hl } }, { { "" } } }
 end
 
 --- @param opts multibuffer.RenderExpandLinesOptions
@@ -1378,9 +8079,9 @@ function M.default_render_expand_lines(opts)
 		for _, line in ipairs(lines) do
 			local chunks = {}
 			if textoff > 0 then
-				table.insert(chunks, { string.rep(" ", textoff), "LineNr" })
+				table.insert(chunks, { string.rep(" ", textoff), "MultibufLineNr" })
 			end
-			table.insert(chunks, { expand_tabs(line, ts), "Comment" })
+			table.insert(chunks, { expand_tabs(line, ts), "MultibufContext" })
 			table.insert(all_virt_lines, chunks)
 		end
 		return all_virt_lines
@@ -1389,7 +8090,7 @@ function M.default_render_expand_lines(opts)
 	local signs = M.user_opts.expander_signs or { above = "↑", below = "↓", both = "↕" }
 	local text = string.format(" --- [ %s %i ] ", signs[opts.expand_direction], opts.count)
 	local width = vim.api.nvim_win_get_width(opts.window)
-	return { { { text, "Folded" }, { string.rep("-", width - #text) .. " ", "Folded" } } }
+	return { { { text, "MultibufSeparator" }, { string.rep("-", width - #text) .. " ", "MultibufSeparator" } } }
 end
 
 return M
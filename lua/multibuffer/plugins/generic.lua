@@ -102,11 +102,11 @@ function M.multibuf_generic_search(opts)
 		local cursor = vim.api.nvim_win_get_cursor(winid)
 		local winline = vim.fn.winline()
 
-		local buf, line = api.multibuf_get_buf_at_line(mbuf, cursor[1])
-		if buf then
-			vim.api.nvim_set_current_buf(buf)
-			vim.api.nvim_win_set_cursor(0, { line, cursor_col or cursor[2] })
-			vim.fn.winrestview({ topline = line - winline + 1 })
+		local ctx = api.multibuf_get_context(mbuf, cursor[1] - 1, cursor_col)
+		if ctx then
+			vim.api.nvim_set_current_buf(ctx.buf)
+			vim.api.nvim_win_set_cursor(0, { ctx.line + 1, ctx.col })
+			vim.fn.winrestview({ topline = ctx.line + 1 - winline + 1 })
 		end
 	end
 
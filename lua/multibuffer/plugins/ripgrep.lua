@@ -20,7 +20,7 @@ vim.api.nvim_set_decoration_provider(M.search_hl_ns, {
 					for _, m in ipairs(matches) do
 						vim.api.nvim_buf_set_extmark(mbuf, M.search_hl_ns, line, m.start, {
 							end_col = m["end"],
-							hl_group = "Search",
+							hl_group = "MultibufMatch",
 							ephemeral = true,
 						})
 					end
@@ -236,6 +236,7 @@ function M.multibuf_ripgrep(opts)
 						local last = merged_regions[#merged_regions]
 						if region.start_row <= last.end_row then
 							last.end_row = math.max(last.end_row, region.end_row)
+							last.cols = vim.tbl_extend("keep", last.cols, region.cols)
 						else
 							table.insert(merged_regions, region)
 						end
@@ -352,6 +353,7 @@ function M.multibuf_ripgrep(opts)
 						local region = {
 							start_row = match_lnum - 1 - M.multibuffer_expand,
 							end_row = match_lnum - 1 + M.multibuffer_expand,
+							cols = { [match_lnum - 1] = msg.data.submatches[1] and msg.data.submatches[1].start or 0 },
 						}
 						assert(regions_by_filename[path])
 						table.insert(regions_by_filename[path], region)
@@ -398,11 +400,11 @@ function M.multibuf_ripgrep(opts)
 		local cursor = vim.api.nvim_win_get_cursor(winid)
 		local winline = vim.fn.winline()
 
-		local buf, line = api.multibuf_get_buf_at_line(mbuf, cursor[1])
-		if buf then
-			vim.api.nvim_set_current_buf(buf)
-			vim.api.nvim_win_set_cursor(0, { line, cursor_col or cursor[2] })
-			vim.fn.winrestview({ topline = line - winline + 1 })
+		local ctx = api.multibuf_get_context(mbuf, cursor[1] - 1, cursor_col)
+		if ctx then
+			vim.api.nvim_set_current_buf(ctx.buf)
+			vim.api.nvim_win_set_cursor(0, { ctx.line + 1, ctx.col })
+			vim.fn.winrestview({ topline = ctx.line + 1 - winline + 1 })
 		end
 	end
 
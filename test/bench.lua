@@ -0,0 +1,123 @@
+-- Times the core multibuffer operations. Run through bench.nu, which
+-- generates the fixtures and passes them in MULTIBUF_BENCH_* variables.
+
+local api = require("multibuffer")
+api.setup({})
+
+local fixtures = vim.split(assert(os.getenv("MULTIBUF_BENCH_FILES")), "\n", { trimempty = true })
+local regions_per_file = tonumber(os.getenv("MULTIBUF_BENCH_REGIONS")) or 10
+local iterations = tonumber(os.getenv("MULTIBUF_BENCH_ITERATIONS")) or 5
+local output = assert(os.getenv("MULTIBUF_BENCH_OUTPUT"))
+
+-- regions need a window to load, make it large enough to show all of them
+vim.o.lines = 200
+vim.o.columns = 200
+
+--- @param samples number[]
+--- @return number
+local function median(samples)
+	table.sort(samples)
+	return samples[math.ceil(#samples / 2)]
+end
+
+--- @param mb integer
+local function wait_loaded(mb)
+	local loaded = vim.wait(10000, function()
+		vim.cmd("redraw")
+		return not api.multibuf_is_loading(mb)
+	end, 10)
+	if not loaded then
+		error("multibuffer: regions did not load within 10s")
+	end
+end
+
+--- @param buf integer
+--- @return MultibufRegion[]
+local function spread_regions(buf)
+	local line_count = vim.api.nvim_buf_line_count(buf)
+	local step = math.max(1, math.floor(line_count / regions_per_file))
+	local regions = {}
+	for i = 0, regions_per_file - 1 do
+		local start_row = math.min(i * step, line_count - 1)
+		table.insert(regions, { start_row = start_row, end_row = math.min(start_row + 4, line_count - 1) })
+	end
+	return regions
+end
+
+local sources = {}
+for _, path in ipairs(fixtures) do
+	local buf = vim.fn.bufadd(path)
+	vim.fn.bufload(buf)
+	table.insert(sources, buf)
+end
+
+--- @type table<string, number[]>
+local samples = {}
+local order = { "create", "add_bufs", "reload", "write", "edit_sync" }
+for _, name in ipairs(order) do
+	samples[name] = {}
+end
+
+--- @param name string
+--- @param fn fun()
+local function time(name, fn)
+	local start = vim.uv.hrtime()
+	fn()
+	table.insert(samples[name], (vim.uv.hrtime() - start) / 1e6)
+end
+
+for _ = 1, iterations do
+	local mb
+	time("create", function()
+		mb = api.create_multibuf()
+	end)
+	api.win_set_multibuf(0, mb)
+
+	time("add_bufs", function()
+		local opts_list = {}
+		for _, buf in ipairs(sources) do
+			table.insert(opts_list, { buf = buf, regions = spread_regions(buf) })
+		end
+		api.multibuf_add_bufs(mb, opts_list)
+		wait_loaded(mb)
+	end)
+
+	time("reload", function()
+		api.multibuf_reload(mb)
+	end)
+
+	-- one changed line per region
+	for _, region in ipairs(api.multibuf_get_regions(mb)) do
+		local line = api.multibuf_buf_get_line(mb, region.buf, region.start_row)
+		if line then
+			vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited " .. region.id })
+		end
+	end
+	time("write", function()
+		api.multibuf_write(mb)
+	end)
+
+	time("edit_sync", function()
+		for _, buf in ipairs(sources) do
+			vim.api.nvim_buf_set_lines(buf, 0, 1, true, { "synced" })
+			vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+		end
+	end)
+
+	vim.api.nvim_buf_delete(mb, { force = true })
+	for _, buf in ipairs(sources) do
+		vim.api.nvim_buf_call(buf, function()
+			vim.cmd("silent! edit!")
+		end)
+	end
+end
+
+local results = {}
+for _, name in ipairs(order) do
+	table.insert(results, { name = name, median_ms = median(samples[name]) })
+end
+
+local file = assert(io.open(output, "w"))
+file:write(vim.json.encode(results))
+file:close()
+vim.cmd("qa!")
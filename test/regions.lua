@@ -0,0 +1,1597 @@
+-- Headless checks of multibuffers, their regions and writing them back. Run
+-- through regions.nu, which fails when one of them does.
+
+local api = require("multibuffer")
+api.setup({})
+
+-- regions need a window to load
+vim.o.lines = 50
+vim.o.columns = 200
+
+--- @type string[]
+local failures = {}
+--- @type { msg: string, level: integer|nil }[]
+local notifications = {}
+vim.notify = function(msg, level)
+	table.insert(notifications, { msg = msg, level = level })
+end
+
+--- @param name string
+--- @param fn fun()
+local function check(name, fn)
+	notifications = {}
+	local ok, err = xpcall(fn, debug.traceback)
+	if not ok then
+		table.insert(failures, name .. ": " .. err)
+	end
+	io.stdout:write(string.format("%s %s\n", ok and "ok" or "FAIL", name))
+	vim.cmd("silent! %bwipeout!")
+end
+
+--- @param actual any
+--- @param expected any
+--- @param what string
+local function expect_eq(actual, expected, what)
+	if not vim.deep_equal(actual, expected) then
+		error(string.format("%s: expected %s, got %s", what, vim.inspect(expected), vim.inspect(actual)), 2)
+	end
+end
+
+--- @param pattern string
+local function expect_notified(pattern)
+	for _, notification in ipairs(notifications) do
+		if notification.msg:find(pattern) then
+			return
+		end
+	end
+	error(string.format("no notification matching %q in %s", pattern, vim.inspect(notifications)), 2)
+end
+
+--- A loaded buffer of a temporary file with {lines}.
+--- @param lines string[]
+--- @return integer
+local function source(lines)
+	local path = vim.fn.tempname()
+	vim.fn.writefile(lines, path)
+	local buf = vim.fn.bufadd(path)
+	vim.fn.bufload(buf)
+	return buf
+end
+
+--- Show {mb} in the current window and wait for its regions to load through
+--- the decoration provider.
+--- @param mb integer
+local function wait_loaded(mb)
+	api.win_set_multibuf(0, mb)
+	local loaded = vim.wait(5000, function()
+		vim.cmd("redraw")
+		return not api.multibuf_is_loading(mb)
+	end, 10)
+	if not loaded then
+		error("regions did not load within 5s", 2)
+	end
+end
+
+--- A loaded multibuffer showing {regions} of {buf} in the current window.
+--- @param buf integer
+--- @param regions MultibufRegion[]
+--- @return integer
+local function show(buf, regions)
+	local mb = api.create_multibuf()
+	api.multibuf_add_buf(mb, { buf = buf, regions = regions })
+	wait_loaded(mb)
+	return mb
+end
+
+--- The module state a wipeout cleans up, read from the upvalues of
+--- multibuf__wipeout since it isn't exposed.
+--- @return table<string, any>
+local function get_state()
+	local state = {}
+	local i = 1
+	while true do
+		local name, value = debug.getupvalue(api.multibuf__wipeout, i)
+		if not name then
+			return state
+		end
+		state[name] = value
+		i = i + 1
+	end
+end
+
+--- @param mb integer wiped multibuffer
+--- @param buf integer its only source
+local function expect_cleaned_up(mb, buf)
+	local state = get_state()
+	expect_eq(state.multibufs[mb], nil, "multibufs entry")
+	expect_eq(state.buf_listeners[buf], nil, "buf_listeners entry")
+	expect_eq(state.pending_adds[mb], nil, "pending_adds entry")
+	for _, notification in ipairs(notifications) do
+		if notification.level == vim.log.levels.ERROR then
+			error("error notification: " .. notification.msg)
+		end
+	end
+end
+
+--- @param buf integer
+--- @return string[]
+local function get_lines(buf)
+	return vim.api.nvim_buf_get_lines(buf, 0, -1, true)
+end
+
+--- A loaded multibuffer showing the entries {opts_list} in the current window.
+--- @param opts_list MultibufAddBufOptions[]
+--- @return integer
+local function show_bufs(opts_list)
+	local mb = api.create_multibuf()
+	api.multibuf_add_bufs(mb, opts_list)
+	wait_loaded(mb)
+	return mb
+end
+
+--- The line {row} of {buf} is rendered as in {mb}.
+--- @param mb integer
+--- @param buf integer
+--- @param row integer
+--- @return string
+local function get_shown_line(mb, buf, row)
+	local line = assert(api.multibuf_buf_get_line(mb, buf, row), "row isn't shown")
+	return vim.api.nvim_buf_get_lines(mb, line, line + 1, true)[1]
+end
+
+--- Run {fn} with the setup options {opts}, the previous options are put back
+--- afterwards.
+--- @param opts MultibufSetupOptions
+--- @param fn fun()
+local function with_opts(opts, fn)
+	local saved = vim.deepcopy(api.user_opts)
+	api.setup(opts)
+	local ok, err = xpcall(fn, debug.traceback)
+	api.user_opts = saved
+	if not ok then
+		error(err, 0)
+	end
+end
+
+-- columns 14..27 are `compute(1, 2)`
+local SPAN_LINE = "local value = compute(1, 2) -- keep"
+
+check("column region writes only its columns", function()
+	local buf = source({ SPAN_LINE })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line, line + 1, true)[1], "compute(1, 2)", "shown columns")
+
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "compute(10, 20)" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "local value = compute(10, 20) -- keep" }, "source")
+end)
+
+check("column region emptied removes only its columns", function()
+	local buf = source({ SPAN_LINE })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "" })
+	local _, results = api.multibuf_write(mb)
+	expect_eq(results[1].status, "written", "status")
+	expect_eq(get_lines(buf), { "local value =  -- keep" }, "source")
+end)
+
+check("line break in a column region writes whole lines", function()
+	local buf = source({ SPAN_LINE })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+
+	-- <CR> after `compute(`
+	vim.api.nvim_buf_set_text(mb, line, 8, line, 8, { "", "" })
+	api.multibuf_write(mb)
+	expect_notified("got a line break")
+	expect_eq(get_lines(buf), { "local value = compute(", "1, 2) -- keep" }, "source")
+	expect_eq(api.multibuf_get_regions(mb)[1].start_col, nil, "start_col after the write")
+end)
+
+check("column region conflicts when its source line shrank", function()
+	local buf = source({ SPAN_LINE })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "compute(3, 4)" })
+	vim.api.nvim_buf_set_text(buf, 0, 20, 0, #SPAN_LINE, {})
+	local _, results = api.multibuf_write(mb)
+	expect_eq({ results[1].status, results[1].reason }, { "failed", "conflict" }, "result")
+	expect_notified("changed under its columns")
+	expect_eq(get_lines(buf), { "local value = comput" }, "source")
+end)
+
+check("column region keeps its columns through serialize and restore", function()
+	local buf = source({ SPAN_LINE })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+	local restored = api.multibuf_restore(assert(api.multibuf_serialize(mb)))
+	wait_loaded(restored)
+	local region = api.multibuf_get_regions(restored)[1]
+	expect_eq({ region.start_col, region.end_col }, { 14, 27 }, "restored columns")
+
+	local line = assert(api.multibuf_buf_get_line(restored, buf, 0))
+	vim.api.nvim_buf_set_lines(restored, line, line + 1, true, { "compute(5, 6)" })
+	api.multibuf_write(restored)
+	expect_eq(get_lines(buf), { "local value = compute(5, 6) -- keep" }, "source")
+end)
+
+check("column region snaps columns inside a multibyte character", function()
+	-- `│` is bytes 2..4 and `é` bytes 7..8
+	local buf = source({ "ab│cdé│fg" })
+	local mb = show(buf, { { start_row = 0, start_col = 3, end_col = 8 } })
+	local region = api.multibuf_get_regions(mb)[1]
+	expect_eq({ region.start_col, region.end_col }, { 2, 7 }, "snapped columns")
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line, line + 1, true)[1], "│cd", "shown columns")
+
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "│xy" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "ab│xyé│fg" }, "source")
+end)
+
+check("column region of several lines round-trips", function()
+	-- columns 4..11 are the name column
+	local buf = source({ "1 | Jürgen | a", "2 | Ana     | b" })
+	local mb = show(buf, { { start_row = 0, end_row = 1, start_col = 4, end_col = 11 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line, line + 2, true), { "Jürgen", "Ana    " }, "shown columns")
+
+	vim.api.nvim_buf_set_lines(mb, line + 1, line + 2, true, { "Zoë" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1 | Jürgen | a", "2 | Zoë | b" }, "source")
+end)
+
+check("undo after a refresh doesn't bring back stale lines", function()
+	local a = source({ "a1", "a2" })
+	local b = source({ "b1", "b2" })
+	local mb = api.create_multibuf()
+	api.multibuf_add_bufs(mb, {
+		{ buf = a, regions = { { start_row = 0, end_row = 0 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 0 } } },
+	})
+	wait_loaded(mb)
+	local line_a = assert(api.multibuf_buf_get_line(mb, a, 0))
+	vim.api.nvim_buf_set_lines(mb, line_a, line_a + 1, true, { "edited a1" })
+
+	vim.api.nvim_buf_set_lines(b, 0, 1, true, { "changed b1" })
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = b })
+	local line_b = assert(api.multibuf_buf_get_line(mb, b, 0))
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line_b, line_b + 1, true)[1], "changed b1", "refreshed region")
+
+	vim.api.nvim_buf_call(mb, function()
+		vim.cmd("silent! undo")
+	end)
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line_b, line_b + 1, true)[1], "changed b1", "region after undo")
+
+	api.multibuf_write(mb)
+	expect_eq(get_lines(b), { "changed b1", "b2" }, "refreshed source")
+	expect_eq(get_lines(a), { "edited a1", "a2" }, "edited source")
+end)
+
+check("wiping a multibuffer from a TextChanged handler of its source", function()
+	local buf = source({ "1", "2", "3" })
+	local mb
+	-- created before the multibuffer listens, so it runs before the sync
+	vim.api.nvim_create_autocmd("TextChanged", {
+		buffer = buf,
+		once = true,
+		callback = function()
+			vim.api.nvim_buf_delete(mb, { force = true })
+		end,
+	})
+	mb = show(buf, { { start_row = 0, end_row = 0 } })
+
+	vim.api.nvim_buf_set_lines(buf, 0, 1, true, { "changed 1" })
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+	expect_eq(vim.api.nvim_buf_is_valid(mb), false, "multibuffer valid")
+	expect_cleaned_up(mb, buf)
+end)
+
+check("wiping a multibuffer while a TextChanged sync reloads it", function()
+	local buf = source({ "1", "2", "3" })
+	local mb = show(buf, { { start_row = 0, end_row = 0 }, { start_row = 2, end_row = 2 } })
+	-- fires from inside the reload once the regions grew into each other
+	vim.api.nvim_create_autocmd("User", {
+		pattern = "MultibufRegionsChanged",
+		once = true,
+		callback = function()
+			vim.api.nvim_buf_delete(mb, { force = true })
+		end,
+	})
+
+	vim.api.nvim_buf_set_lines(buf, 1, 2, true, {})
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+	expect_eq(vim.api.nvim_buf_is_valid(mb), false, "multibuffer valid")
+	expect_cleaned_up(mb, buf)
+	-- the wipe during the reload also scheduled a pass that must find nothing left
+	vim.wait(100)
+	expect_cleaned_up(mb, buf)
+end)
+
+check("reconcile drops a column region together with its column mark", function()
+	local buf = source({ SPAN_LINE, "next" })
+	local mb = show(buf, { { start_row = 0, start_col = 14, end_col = 27 } })
+
+	-- without a sync, as if the change was made where autocmds don't fire
+	vim.api.nvim_buf_set_lines(buf, 0, 1, true, {})
+	local summary = api.multibuf_reconcile(mb)
+	expect_eq(#summary.dropped, 1, "dropped regions")
+	expect_eq(api.multibuf_get_regions(mb), {}, "regions")
+	expect_eq(vim.api.nvim_buf_get_extmarks(buf, api.multibuf__ns, 0, -1, {}), {}, "source extmarks")
+end)
+
+check("multibuf_select_region jumps to the picked region", function()
+	local buf = source({ "first", "second", "third", "fourth" })
+	local mb = api.multibuf_from_selections({
+		{ buf = buf, start_row = 0, end_row = 0, label = "one" },
+		{ buf = buf, start_row = 3, end_row = 3, label = "four" },
+	})
+	wait_loaded(mb)
+	local before = vim.api.nvim_win_get_cursor(0)
+
+	local select = vim.ui.select
+	local items, pick
+	vim.ui.select = function(choices, opts, on_choice)
+		items = vim.tbl_map(opts.format_item, choices)
+		on_choice(pick and choices[pick], pick)
+	end
+	local ok, err = pcall(function()
+		api.multibuf_select_region(mb)
+		expect_eq(vim.api.nvim_win_get_cursor(0), before, "cursor after cancelling")
+		pick = 2
+		api.multibuf_select_region(mb)
+	end)
+	vim.ui.select = select
+	assert(ok, err)
+
+	expect_eq(items[2]:find("fourth  (four)", 1, true) ~= nil, true, "item " .. vim.inspect(items[2]))
+	local cursor = vim.api.nvim_win_get_cursor(0)
+	expect_eq(assert(api.multibuf_get_context(mb, cursor[1] - 1)).line, 3, "source line under the cursor")
+end)
+
+check("user extmarks follow their source line across a reload", function()
+	local a = source({ "a1", "a2" })
+	local b = source({ "b1", "b2" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 1 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 1 } } },
+	})
+	local ns = vim.api.nvim_create_namespace("multibuf_test_user")
+	local row = assert(api.multibuf_buf_get_line(mb, b, 1))
+	local mark = vim.api.nvim_buf_set_extmark(mb, ns, row, 0, { sign_text = "U" })
+
+	-- the region of `a` grows, the lines of `b` move down
+	vim.api.nvim_buf_set_lines(a, 1, 1, true, { "a1.5" })
+	api.multibuf_reload(mb)
+	local moved = vim.api.nvim_buf_get_extmark_by_id(mb, ns, mark, {})[1]
+	expect_eq(moved, row + 1, "sign row")
+	expect_eq(vim.api.nvim_buf_get_lines(mb, moved, moved + 1, true)[1], "b2", "line under the sign")
+end)
+
+check("sync_events without TextChangedI leaves insert mode edits for later", function()
+	with_opts({ sync_events = { "TextChanged" } }, function()
+		local buf = source({ "one", "two" })
+		local mb = show(buf, { { start_row = 0, end_row = 1 } })
+
+		vim.api.nvim_buf_set_lines(buf, 0, 1, true, { "typed" })
+		vim.api.nvim_exec_autocmds("TextChangedI", { buffer = buf })
+		expect_eq(get_shown_line(mb, buf, 0), "one", "line after TextChangedI")
+		vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+		expect_eq(get_shown_line(mb, buf, 0), "typed", "line after TextChanged")
+	end)
+end)
+
+check("multibuf_from_selections assembles selections of two buffers", function()
+	local a = source({ "a1", "a2", "a3" })
+	local b = source({ "b1", "b2" })
+	local mb = api.multibuf_from_selections({
+		{ buf = a, start_row = 0, end_row = 1, label = "first" },
+		{ buf = b, start_row = 1, end_row = 1 },
+		{ buf = b, start_row = 5, end_row = 9 },
+	})
+	expect_notified("skipping selection 3")
+	expect_eq(vim.api.nvim_get_current_buf(), mb, "current buffer")
+	expect_eq(api.multibuf_get_active(), mb, "active multibuffer")
+	wait_loaded(mb)
+
+	local regions = vim.tbl_map(function(region)
+		return { region.buf, region.start_row, region.end_row, region.label }
+	end, api.multibuf_get_regions(mb))
+	expect_eq(regions, { { a, 0, 1, "first" }, { b, 1, 1, nil } }, "regions")
+	expect_eq({ get_shown_line(mb, a, 1), get_shown_line(mb, b, 1) }, { "a2", "b2" }, "shown lines")
+end)
+
+--- @param mb integer
+--- @return (MultibufContext|false)[]
+local function get_all_contexts(mb)
+	local contexts = {}
+	for line = 0, vim.api.nvim_buf_line_count(mb) - 1 do
+		contexts[line + 1] = api.multibuf_get_context(mb, line, 0) or false
+	end
+	return contexts
+end
+
+check("toggling titles keeps the line mapping", function()
+	local a = source({ "a1", "a2", "a3" })
+	local b = source({ "b1", "b2" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 2 } } },
+		{ buf = b, regions = { { start_row = 1, end_row = 1 } } },
+	})
+	local lines = vim.api.nvim_buf_get_lines(mb, 0, -1, true)
+	local contexts = get_all_contexts(mb)
+
+	api.multibuf_set_titles(mb, false)
+	expect_eq(vim.api.nvim_buf_get_lines(mb, 0, -1, true), lines, "lines without titles")
+	expect_eq(get_all_contexts(mb), contexts, "contexts without titles")
+	api.multibuf_set_titles(mb, true)
+	expect_eq(get_all_contexts(mb), contexts, "contexts with titles again")
+end)
+
+check("one undo in a source reverts a whole write", function()
+	local original = { "1", "2", "3", "4", "5", "6" }
+	local buf = source(original)
+	local mb = show(buf, { { start_row = 0 }, { start_row = 2 }, { start_row = 4 } })
+	for _, row in ipairs({ 0, 2, 4 }) do
+		local line = assert(api.multibuf_buf_get_line(mb, buf, row))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited " .. row })
+	end
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "edited 0", "2", "edited 2", "4", "edited 4", "6" }, "written source")
+
+	vim.api.nvim_buf_call(buf, function()
+		vim.cmd("silent undo")
+	end)
+	expect_eq(get_lines(buf), original, "source after one undo")
+end)
+
+--- @param s string
+--- @param part string
+local function expect_contains(s, part)
+	if not s:find(part, 1, true) then
+		error(string.format("%q doesn't contain %q", s, part), 2)
+	end
+end
+
+check("setup rejects malformed options naming each of them", function()
+	local before = vim.deepcopy(api.user_opts)
+	local ok, err = pcall(api.setup, { bogus = 1, fold_regions = "yes", region_hl_odd_adjust = 300 })
+	expect_eq(ok, false, "setup succeeded")
+	expect_contains(err, "invalid setup options")
+	expect_contains(err, "bogus: unknown option")
+	expect_contains(err, "fold_regions: expected boolean, got string")
+	expect_contains(err, "region_hl_odd_adjust: must be between 0 and 255")
+	expect_eq(api.user_opts, before, "options after the failed setup")
+
+	with_opts({ region_hl_even = "NoSuchGroup" }, function()
+		expect_notified('region_hl_even: highlight group "NoSuchGroup" does not exist')
+	end)
+end)
+
+check("region footers render below the region and leave contexts alone", function()
+	local buf = source({ "1", "2", "3", "4" })
+	local mb = show(buf, { { start_row = 0, end_row = 1 }, { start_row = 3, end_row = 3 } })
+	local contexts = get_all_contexts(mb)
+	local region = api.multibuf_get_regions(mb)[1]
+
+	local footer = { { { " footer", "Comment" } } }
+	expect_eq(api.multibuf_set_region_footer(mb, region.id, footer), true, "footer set")
+	local last = assert(api.multibuf_buf_get_line(mb, buf, 1))
+	local found = false
+	local marks = vim.api.nvim_buf_get_extmarks(mb, api.multibuf_structure_ns, 0, -1, { details = true })
+	for _, mark in ipairs(marks) do
+		if vim.deep_equal(mark[4].virt_lines, footer) then
+			expect_eq(mark[2], last, "footer row")
+			found = true
+		end
+	end
+	expect_eq(found, true, "footer rendered")
+	expect_eq(get_all_contexts(mb), contexts, "contexts with a footer")
+end)
+
+check("a line added at the end of a region lands before the edit of the next one", function()
+	with_opts({ auto_merge = false }, function()
+		local buf = source({ "1", "2", "3", "4" })
+		-- entries of their own so the touching regions stay apart, the later
+		-- region renders first
+		local mb = show_bufs({
+			{ buf = buf, regions = { { start_row = 2, end_row = 3 } } },
+			{ buf = buf, regions = { { start_row = 0, end_row = 1 } } },
+		})
+		local a_last = assert(api.multibuf_buf_get_line(mb, buf, 1))
+		vim.api.nvim_buf_set_lines(mb, a_last, a_last + 1, true, { "2", "added" })
+		local b_first = assert(api.multibuf_buf_get_line(mb, buf, 2))
+		vim.api.nvim_buf_set_lines(mb, b_first, b_first + 1, true, { "edited 3" })
+
+		api.multibuf_write(mb)
+		expect_eq(get_lines(buf), { "1", "2", "added", "edited 3", "4" }, "written source")
+	end)
+end)
+
+check("writing adjacent regions changes their source once", function()
+	with_opts({ auto_merge = false }, function()
+		local original = {}
+		local entries = {}
+		for row = 0, 29 do
+			table.insert(original, tostring(row))
+		end
+		local buf = source(original)
+		for row = 0, 29 do
+			table.insert(entries, { buf = buf, regions = { { start_row = row, end_row = row } } })
+		end
+		local mb = show_bufs(entries)
+		expect_eq(#api.multibuf_get_regions(mb), 30, "regions")
+		for row = 0, 29 do
+			local line = assert(api.multibuf_buf_get_line(mb, buf, row))
+			vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited " .. row })
+		end
+
+		local changes = 0
+		vim.api.nvim_buf_attach(buf, false, {
+			on_lines = function()
+				changes = changes + 1
+			end,
+		})
+		api.multibuf_write(mb)
+		expect_eq(changes, 1, "source changes")
+		expect_eq(get_lines(buf)[30], "edited 29", "last written line")
+
+		vim.api.nvim_buf_call(buf, function()
+			vim.cmd("silent undo")
+		end)
+		expect_eq(get_lines(buf), original, "source after one undo")
+	end)
+end)
+
+check("multibuf_reload still takes a cursor buffer and line", function()
+	local buf = source({ "1", "2", "3", "4", "5" })
+	local mb = show(buf, { { start_row = 0, end_row = 1 }, { start_row = 3, end_row = 4 } })
+	vim.api.nvim_win_set_cursor(0, { 1, 0 })
+	api.multibuf_reload(mb, buf, 4)
+	expect_eq(vim.api.nvim_win_get_cursor(0)[1], assert(api.multibuf_buf_get_line(mb, buf, 4)) + 1, "cursor line")
+
+	vim.api.nvim_win_set_cursor(0, { 1, 0 })
+	api.multibuf_reload(mb, { cursor_buf = buf, cursor_line = 3 })
+	expect_eq(vim.api.nvim_win_get_cursor(0)[1], assert(api.multibuf_buf_get_line(mb, buf, 3)) + 1, "cursor line")
+end)
+
+check("rebuilding the structural marks keeps the mirrored ones", function()
+	local buf = source({ "1", "2", "3", "4" })
+	local mb = show(buf, { { start_row = 0, end_row = 3 } })
+	local row = assert(api.multibuf_buf_get_line(mb, buf, 2))
+	local mark = vim.api.nvim_buf_set_extmark(mb, api.multibuf_mirror_ns, row, 0, { sign_text = "E" })
+	local structure = vim.api.nvim_buf_get_extmarks(mb, api.multibuf_structure_ns, 0, -1, {})
+	expect_eq(#structure > 0, true, "structural marks before the reload")
+
+	vim.api.nvim_buf_set_lines(buf, 0, 0, true, { "0" })
+	api.multibuf_reload(mb)
+	local moved = vim.api.nvim_buf_get_extmark_by_id(mb, api.multibuf_mirror_ns, mark, {})[1]
+	expect_eq(moved, assert(api.multibuf_buf_get_line(mb, buf, 3)), "mirrored sign row")
+	structure = vim.api.nvim_buf_get_extmarks(mb, api.multibuf_structure_ns, 0, -1, {})
+	expect_eq(#structure > 0, true, "structural marks after the reload")
+end)
+
+check("edits to a source and its multibuffer in one go are both kept", function()
+	local buf = source({ "1", "2", "3", "4" })
+	local mb = show(buf, { { start_row = 0, end_row = 1 } })
+	local region = api.multibuf_get_regions(mb)[1]
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+	vim.api.nvim_buf_call(mb, function()
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited 1" })
+		vim.api.nvim_buf_set_lines(buf, 3, 4, true, { "source 4" })
+	end)
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+
+	-- the sync waits for the write
+	expect_eq(vim.api.nvim_buf_get_lines(mb, line, line + 1, true), { "edited 1" }, "multibuffer line")
+	expect_eq(assert(api.multibuf_region_status(mb, region.id)).conflict, true, "conflict")
+
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "edited 1", "2", "3", "source 4" }, "written source")
+	expect_eq(assert(api.multibuf_region_status(mb, region.id)).conflict, false, "conflict after the write")
+	expect_eq(vim.bo[mb].modified, false, "multibuffer modified")
+end)
+
+check("multibuf_is_modified follows typing, reloads and writes", function()
+	local buf = source({ "1", "2", "3" })
+	local mb = show(buf, { { start_row = 0, end_row = 2 } })
+	expect_eq(api.multibuf_is_modified(mb), false, "modified after loading")
+
+	vim.api.nvim_win_set_cursor(0, { assert(api.multibuf_buf_get_line(mb, buf, 1)) + 1, 0 })
+	vim.cmd("normal! A typed")
+	expect_eq(api.multibuf_is_modified(mb), true, "modified after typing")
+	api.multibuf_reload(mb, { keep_edits = true })
+	expect_eq(api.multibuf_is_modified(mb), true, "modified after a reload keeping the edit")
+
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "2 typed", "3" }, "written source")
+	expect_eq(api.multibuf_is_modified(mb), false, "modified after the write")
+end)
+
+check("an on_pre_write hook returning false cancels the write", function()
+	local summaries = {}
+	with_opts({
+		on_pre_write = function(_, summary)
+			table.insert(summaries, summary)
+			return false
+		end,
+	}, function()
+		local buf = source({ "1", "2" })
+		local mb = show(buf, { { start_row = 0, end_row = 1 } })
+		local line = assert(api.multibuf_buf_get_line(mb, buf, 0))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+
+		local written, results = api.multibuf_write(mb)
+		expect_eq(written, {}, "written sources")
+		expect_eq({ results[1].status, results[1].reason }, { "skipped", "cancelled" }, "result")
+		expect_eq(get_lines(buf), { "1", "2" }, "source")
+		expect_eq(#summaries, 1, "hook calls")
+		expect_eq(summaries[1].sources, { buf }, "sources in the summary")
+		expect_eq(api.multibuf_is_modified(mb), true, "edits kept")
+	end)
+end)
+
+--- The local function {name} of the plugin, read from the upvalues of {fn}.
+--- @param fn function
+--- @param name string
+--- @return function
+local function get_local(fn, name)
+	local i = 1
+	while true do
+		local upvalue, value = debug.getupvalue(fn, i)
+		if not upvalue then
+			error(name .. " isn't an upvalue", 2)
+		elseif upvalue == name then
+			return value
+		end
+		i = i + 1
+	end
+end
+
+check("get_extmark_range tells the kinds of broken ranges apart", function()
+	local get_extmark_range = get_local(api.multibuf_get_buf_at_line, "get_extmark_range")
+	local buf = source({ "1", "2", "3" })
+	local ns = api.multibuf__ns
+
+	local range = vim.api.nvim_buf_set_extmark(buf, ns, 0, 0, { end_row = 2 })
+	expect_eq({ get_extmark_range(buf, range) }, { 0, 2 }, "range")
+	local empty = vim.api.nvim_buf_set_extmark(buf, ns, 1, 0, { end_row = 1 })
+	expect_eq({ get_extmark_range(buf, empty) }, { 1, 1 }, "zero-width range")
+	local point = vim.api.nvim_buf_set_extmark(buf, ns, 2, 0, {})
+	expect_eq({ get_extmark_range(buf, point) }, { 2, 3, "no_end" }, "range without an end")
+
+	vim.api.nvim_buf_del_extmark(buf, ns, range)
+	expect_eq({ get_extmark_range(buf, range) }, { nil, nil, "deleted" }, "deleted range")
+	vim.cmd("bwipeout! " .. buf)
+	expect_eq({ get_extmark_range(buf, point) }, { nil, nil, "invalid_buffer" }, "range of a wiped buffer")
+end)
+
+check("dirty signs mark an edited region until it is reverted", function()
+	with_opts({ dirty_signs = true }, function()
+		local buf = source({ "1", "2", "3", "4" })
+		local mb = show(buf, { { start_row = 0, end_row = 1 }, { start_row = 3, end_row = 3 } })
+		local function get_signs()
+			return vim.tbl_map(function(mark)
+				return mark[2]
+			end, vim.api.nvim_buf_get_extmarks(mb, api.multibuf_dirty_ns, 0, -1, {}))
+		end
+		local function wait_signs(expected)
+			vim.api.nvim_exec_autocmds("TextChanged", { buffer = mb })
+			vim.wait(1000, function()
+				return vim.deep_equal(get_signs(), expected)
+			end, 10)
+			expect_eq(get_signs(), expected, "dirty sign rows")
+		end
+		expect_eq(get_signs(), {}, "dirty sign rows after loading")
+
+		local first = assert(api.multibuf_buf_get_line(mb, buf, 0))
+		local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+		wait_signs({ first })
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "2" })
+		wait_signs({})
+	end)
+end)
+
+check("binary and very large sources are refused", function()
+	local mb = api.create_multibuf()
+	local binary = vim.fn.tempname()
+	local file = assert(io.open(binary, "wb"))
+	file:write("ELF\0\1\2\0")
+	file:close()
+	local ok, err = pcall(api.multibuf_add_buf, mb, { buf = vim.fn.bufadd(binary), regions = { { start_row = 0 } } })
+	expect_eq(ok, false, "binary file added")
+	expect_contains(err, "looks like a binary file")
+
+	local loaded = source({ "text" })
+	vim.bo[loaded].binary = true
+	ok, err = pcall(api.multibuf_add_buf, mb, { buf = loaded, regions = { { start_row = 0 } } })
+	expect_eq(ok, false, "'binary' buffer added")
+	expect_contains(err, "has 'binary' set")
+
+	-- text up front and a hole behind it, 60 MiB without writing them
+	local large = vim.fn.tempname()
+	file = assert(io.open(large, "wb"))
+	file:write(string.rep("line\n", 2000))
+	file:seek("set", 60 * 1024 * 1024)
+	file:write("\n")
+	file:close()
+	local buf = vim.fn.bufadd(large)
+	ok, err = pcall(api.multibuf_add_buf, mb, { buf = buf, regions = { { start_row = 0 } } })
+	expect_eq(ok, false, "large file added")
+	expect_contains(err, "need `force = true`")
+	ok, err = pcall(api.multibuf_add_buf, mb, { buf = buf, regions = { { start_row = 0 } }, force = true })
+	expect_eq(ok, true, "large file added with force: " .. tostring(err))
+	os.remove(large)
+end)
+
+check("multibuf_diff_source diffs an edited region against its source", function()
+	local buf = source({ "1", "2", "3" })
+	local mb = show(buf, { { start_row = 0, end_row = 2 } })
+	local region = api.multibuf_get_regions(mb)[1]
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+
+	local edited_win = assert(api.multibuf_diff_source(mb, region.id))
+	local wins = vim.api.nvim_tabpage_list_wins(0)
+	expect_eq(#wins, 2, "diff windows")
+	local original_win = wins[1] == edited_win and wins[2] or wins[1]
+	local edited_buf = vim.api.nvim_win_get_buf(edited_win)
+	local original_buf = vim.api.nvim_win_get_buf(original_win)
+	expect_eq(get_lines(original_buf), { "1", "2", "3" }, "original lines")
+	expect_eq(get_lines(edited_buf), { "1", "edited", "3" }, "edited lines")
+	expect_eq({ vim.wo[original_win].diff, vim.wo[edited_win].diff }, { true, true }, "'diff'")
+	vim.api.nvim_win_call(edited_win, function()
+		expect_eq(vim.fn.diff_hlID(1, 1), 0, "highlight of an unchanged line")
+		expect_eq(vim.fn.diff_hlID(2, 1) ~= 0, true, "changed line highlighted")
+	end)
+
+	vim.api.nvim_win_close(edited_win, true)
+	vim.wait(1000, function()
+		return not vim.api.nvim_win_is_valid(original_win)
+	end, 10)
+	expect_eq(vim.api.nvim_win_is_valid(original_win), false, "original window open")
+	expect_eq(vim.api.nvim_buf_is_valid(edited_buf), false, "edited buffer")
+	expect_eq(vim.api.nvim_buf_is_valid(original_buf), false, "original buffer")
+end)
+
+check("saving an unchanged source doesn't reload its multibuffers", function()
+	with_opts({ sync_events = { "TextChanged", "BufWritePost" } }, function()
+		local buf = source({ "1", "2" })
+		local mb = show(buf, { { start_row = 0, end_row = 1 } })
+		local tick = vim.api.nvim_buf_get_changedtick(mb)
+		vim.api.nvim_buf_call(buf, function()
+			vim.cmd("silent write")
+		end)
+		expect_eq(vim.api.nvim_buf_get_changedtick(mb), tick, "multibuffer changedtick after a save")
+
+		vim.api.nvim_buf_set_lines(buf, 0, 1, true, { "changed" })
+		vim.api.nvim_buf_call(buf, function()
+			vim.cmd("silent write")
+		end)
+		expect_eq(get_shown_line(mb, buf, 0), "changed", "line after saving a change")
+	end)
+end)
+
+check("line numbers of short and long sources share a width", function()
+	local signcolumn = vim.wo.signcolumn
+	vim.wo.signcolumn = "auto:9"
+	local long_lines = {}
+	for i = 1, 20000 do
+		long_lines[i] = tostring(i)
+	end
+	local short = source({ "1", "2", "3", "4", "5", "6" })
+	local long = source(long_lines)
+	local entries = {
+		{ buf = short, regions = { { start_row = 2, end_row = 4 } } },
+		{ buf = long, regions = { { start_row = 15000, end_row = 15002 } } },
+	}
+	-- number signs on the middle line of each region, away from the expanders
+	local function get_widths(mb)
+		local widths = {}
+		for _, row in ipairs({ api.multibuf_buf_get_line(mb, short, 3), api.multibuf_buf_get_line(mb, long, 15001) }) do
+			local marks = vim.api.nvim_buf_get_extmarks(
+				mb,
+				api.multibuf_structure_ns,
+				{ row, 0 },
+				{ row, -1 },
+				{ details = true, type = "sign" }
+			)
+			local count = 0
+			for _, mark in ipairs(marks) do
+				if mark[4].priority < 1000 then
+					count = count + 1
+				end
+			end
+			table.insert(widths, count)
+		end
+		return widths
+	end
+
+	local widths = get_widths(show_bufs(entries))
+	expect_eq(widths[1], widths[2], "uniform widths")
+	with_opts({ line_number_width = "region" }, function()
+		widths = get_widths(show_bufs(entries))
+		expect_eq(widths[1] < widths[2], true, "per region widths " .. vim.inspect(widths))
+	end)
+	vim.wo.signcolumn = signcolumn
+end)
+
+check("multibuf_each_region resolves ranges after a source edit", function()
+	local buf = source({ "1", "2", "3", "4", "5", "6", "7" })
+	local mb = show(buf, { { start_row = 1, end_row = 2 }, { start_row = 4, end_row = 5 } })
+	local ids = vim.tbl_map(function(region)
+		return region.id
+	end, api.multibuf_get_regions(mb))
+
+	local seen, ranges, lines = {}, {}, {}
+	for region in api.multibuf_each_region(mb) do
+		table.insert(seen, region.id)
+		if region.index == 1 then
+			vim.api.nvim_buf_set_lines(buf, 0, 0, true, { "new 1", "new 2" })
+		end
+		table.insert(ranges, { region:range() })
+		table.insert(lines, region:lines())
+	end
+	expect_eq(seen, ids, "region ids")
+	expect_eq(ranges, { { 3, 4 }, { 6, 7 } }, "ranges")
+	expect_eq(lines, { { "2", "3" }, { "5", "6" } }, "lines")
+end)
+
+check("multibuffer names that are taken get a suffix", function()
+	local first, first_name = api.create_multibuf({ title = "review" })
+	expect_eq(first_name, "multibuf://review", "first name")
+	local _, second_name = api.create_multibuf({ title = "review" })
+	expect_eq(second_name, "multibuf://review (2)", "name while the first exists")
+
+	vim.cmd("bwipeout! " .. first)
+	local recreated, recreated_name = api.create_multibuf({ title = "review" })
+	expect_eq(recreated_name, "multibuf://review", "name after wiping the first")
+	expect_eq(vim.api.nvim_buf_get_name(recreated), recreated_name, "buffer name")
+
+	local other = vim.api.nvim_create_buf(true, true)
+	vim.api.nvim_buf_set_name(other, "multibuf://taken")
+	local _, taken_name = api.create_multibuf({ title = "taken" })
+	expect_eq(taken_name, "multibuf://taken (2)", "name taken by another buffer")
+end)
+
+check("writing one edited region changes only its source", function()
+	local a = source({ "a1", "a2" })
+	local b = source({ "b1", "b2" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 1 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 1 } } },
+	})
+	local a_region = api.multibuf_get_regions(mb)[1]
+	local ticks = { vim.api.nvim_buf_get_changedtick(a), vim.api.nvim_buf_get_changedtick(b) }
+	local line = assert(api.multibuf_buf_get_line(mb, a, 1))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+	expect_eq(api.multibuf_dirty_regions(mb), { a_region.id }, "dirty regions")
+
+	local written = api.multibuf_write(mb)
+	expect_eq(written, { a }, "written sources")
+	expect_eq(vim.api.nvim_buf_get_changedtick(a) > ticks[1], true, "changedtick of the edited source moved")
+	expect_eq(vim.api.nvim_buf_get_changedtick(b), ticks[2], "changedtick of the other source")
+	expect_eq(api.multibuf_dirty_regions(mb), {}, "dirty regions after the write")
+end)
+
+check("multibuf_add_batch adds a mixed batch of two sources", function()
+	local a = source({ "a1", "a2", "a3" })
+	local b = source({ "b1", "b2" })
+	local mb = api.create_multibuf()
+	local ids, failures = api.multibuf_add_batch(mb, {
+		{ source_buf = a, region = { start_row = 0 }, label = "first" },
+		{ source_buf = b, region = { start_row = 1 }, readonly = true },
+		{ source_buf = a, region = { start_row = 9 } },
+		{ source_buf = a, region = { start_row = 2 } },
+	})
+	wait_loaded(mb)
+
+	expect_eq(#ids, 3, "added regions")
+	expect_eq(#failures, 1, "failures")
+	expect_eq(failures[1].index, 3, "failed item")
+	expect_notified("skipping batch item 3")
+	local regions = api.multibuf_get_regions(mb)
+	expect_eq(
+		vim.tbl_map(function(region)
+			return { region.buf, region.start_row }
+		end, regions),
+		{ { a, 0 }, { a, 2 }, { b, 1 } },
+		"regions"
+	)
+	expect_eq(regions[1].label, "first", "label")
+	expect_eq(assert(api.multibuf_region_status(mb, regions[3].id)).readonly, true, "readonly")
+end)
+
+check("removing regions deletes their source extmarks", function()
+	local buf = source({ "1", "2", "3", "4", "5" })
+	local function count_marks()
+		return #vim.api.nvim_buf_get_extmarks(buf, api.multibuf__ns, 0, -1, {})
+	end
+	local baseline = count_marks()
+
+	local mb = show(buf, { { start_row = 0 }, { start_row = 2 }, { start_row = 4, start_col = 0, end_col = 1 } })
+	expect_eq(count_marks() > baseline, true, "source extmarks placed")
+	for _, region in ipairs(api.multibuf_get_regions(mb)) do
+		expect_eq(api.multibuf_remove_region(mb, region.id), true, "region removed")
+	end
+	expect_eq(count_marks(), baseline, "source extmarks after removing the regions")
+
+	api.multibuf_add_buf(mb, { buf = buf, regions = { { start_row = 1 } } })
+	wait_loaded(mb)
+	api.multibuf_clear_bufs(mb)
+	expect_eq(count_marks(), baseline, "source extmarks after clearing")
+end)
+
+check("multibuf_force_reload picks up direct source edits", function()
+	local a = source({ "a1", "a2" })
+	local b = source({ "b1", "b2" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 1 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 1 } } },
+	})
+	vim.api.nvim_buf_set_lines(a, 1, 2, true, { "a2 changed" })
+	api.multibuf_force_reload(mb)
+	expect_eq(get_shown_line(mb, a, 1), "a2 changed", "reloaded line")
+
+	-- unwritten edits survive and the changed source is flagged
+	local line = assert(api.multibuf_buf_get_line(mb, b, 0))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "b1 edited" })
+	vim.api.nvim_buf_set_lines(b, 1, 2, true, { "b2 changed" })
+	api.multibuf_force_reload(mb)
+	expect_eq(get_shown_line(mb, b, 0), "b1 edited", "edited line")
+	local region = api.multibuf_get_regions(mb)[2]
+	expect_eq(assert(api.multibuf_region_status(mb, region.id)).conflict, true, "conflict")
+end)
+
+check("multibuf_restore relocates moved regions and flags changed ones", function()
+	local buf = source({ "x", "y", "target a", "target b", "z", "q", "keep", "w" })
+	local mb = show(buf, { { start_row = 2, end_row = 3 }, { start_row = 6, end_row = 6 } })
+	local state = assert(api.multibuf_serialize(mb))
+	vim.api.nvim_buf_set_lines(buf, 0, 0, true, { "new" })
+	vim.api.nvim_buf_set_lines(buf, 7, 8, true, { "gone" })
+
+	local restored, report = api.multibuf_restore(state)
+	wait_loaded(restored)
+	expect_eq(
+		vim.tbl_map(function(r)
+			return { r.from, r.to }
+		end, report.relocated),
+		{ { 2, 3 } },
+		"relocated"
+	)
+	expect_eq(#report.stale, 1, "stale regions")
+	expect_eq(report.stale[1].start_row, 6, "stale start row")
+	expect_eq(report.missing, {}, "missing")
+	expect_eq(
+		vim.tbl_map(function(region)
+			return { region.start_row, region.end_row }
+		end, api.multibuf_get_regions(restored)),
+		{ { 3, 4 }, { 6, 6 } },
+		"restored regions"
+	)
+	expect_eq(get_shown_line(restored, buf, 3), "target a", "relocated line")
+end)
+
+check("multibuf_referencing lists the multibuffers sharing a source", function()
+	local shared = source({ "1", "2" })
+	local other = source({ "3" })
+	local first = show(shared, { { start_row = 0 } })
+	local second = show_bufs({
+		{ buf = other, regions = { { start_row = 0 } } },
+		{ buf = shared, regions = { { start_row = 1 } } },
+	})
+	local expected = { first, second }
+	table.sort(expected)
+	expect_eq(api.multibuf_referencing(shared), expected, "multibuffers of the shared source")
+	expect_eq(api.multibuf_referencing(vim.api.nvim_buf_get_name(shared)), expected, "looked up by path")
+	expect_eq(api.multibuf_referencing(other), { second }, "multibuffers of the other source")
+	expect_eq(api.multibuf_referencing("/no/such/file"), {}, "unknown path")
+
+	vim.cmd("bwipeout! " .. first)
+	expect_eq(api.multibuf_referencing(shared), { second }, "after wiping one")
+end)
+
+check("writing the last region of a noeol source keeps the missing newline", function()
+	local path = vim.fn.tempname()
+	vim.fn.writefile({ "1", "2" }, path, "b")
+	local buf = vim.fn.bufadd(path)
+	vim.fn.bufload(buf)
+	expect_eq(vim.bo[buf].endofline, false, "'endofline' after loading")
+	-- or saving adds the newline back
+	vim.bo[buf].fixendofline = false
+	local mb = show(buf, { { start_row = 0, end_row = 1 } })
+
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "2 edited", "3" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "2 edited", "3" }, "written source")
+	expect_eq(vim.bo[buf].endofline, false, "'endofline' after the write")
+	expect_eq(vim.bo[buf].fixendofline, false, "'fixendofline' after the write")
+
+	vim.api.nvim_buf_call(buf, function()
+		vim.cmd("silent write")
+	end)
+	local file = assert(io.open(path, "rb"))
+	local content = file:read("*a")
+	file:close()
+	expect_eq(content, "1\n2 edited\n3", "saved file")
+end)
+
+check("multibuf_rebind_source moves regions onto a reopened source", function()
+	local path = vim.fn.tempname()
+	vim.fn.writefile({ "1", "2", "3", "4" }, path)
+	local old = vim.fn.bufadd(path)
+	vim.fn.bufload(old)
+	local mb = show(old, { { start_row = 0 }, { start_row = 2, end_row = 3 } })
+
+	vim.cmd("bwipeout! " .. old)
+	local new = vim.fn.bufadd(path)
+	vim.fn.bufload(new)
+	expect_eq(new ~= old, true, "new buffer handle")
+	expect_eq(api.multibuf_rebind_source(mb, old, path), 2, "rebound regions")
+	expect_eq(
+		vim.tbl_map(function(region)
+			return { region.buf, region.start_row, region.end_row }
+		end, api.multibuf_get_regions(mb)),
+		{ { new, 0, 0 }, { new, 2, 3 } },
+		"regions"
+	)
+	expect_eq(api.multibuf_referencing(new), { mb }, "multibuffers of the new source")
+
+	local line = assert(api.multibuf_buf_get_line(mb, new, 2))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(new), { "1", "2", "edited", "4" }, "written source")
+end)
+
+check("lines added at the end of the last region are written once", function()
+	local buf = source({ "1", "2" })
+	local mb = show(buf, { { start_row = 0, end_row = 1 } })
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "2", "3", "4" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "2", "3", "4" }, "source after the first write")
+	expect_eq(api.multibuf_get_regions(mb)[1].end_row, 3, "region end")
+
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "2", "3", "4" }, "source after the second write")
+	api.multibuf_reload(mb)
+	expect_eq(get_shown_line(mb, buf, 3), "4", "last line after a reload")
+
+	line = assert(api.multibuf_buf_get_line(mb, buf, 3))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "4 edited" })
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "2", "3", "4 edited" }, "source after editing an added line")
+end)
+
+check("virt_text line numbers match the source lines", function()
+	with_opts({ line_number_mode = "virt_text" }, function()
+		local buf = source({ "1", "2", "3", "4", "5", "6" })
+		local mb = show(buf, { { start_row = 0, end_row = 1 }, { start_row = 4, end_row = 5 } })
+		local function expect_numbers(rows)
+			for _, row in ipairs(rows) do
+				local line = assert(api.multibuf_buf_get_line(mb, buf, row))
+				local marks = vim.api.nvim_buf_get_extmarks(
+					mb,
+					api.multibuf_structure_ns,
+					{ line, 0 },
+					{ line, -1 },
+					{ details = true }
+				)
+				local numbers = {}
+				for _, mark in ipairs(marks) do
+					if mark[4].virt_text_pos == "right_align" then
+						table.insert(numbers, vim.trim(mark[4].virt_text[1][1]))
+					end
+				end
+				expect_eq(numbers, { tostring(row + 1) }, "number of source row " .. row)
+				expect_eq(get_shown_line(mb, buf, row), get_lines(buf)[row + 1], "line of source row " .. row)
+			end
+		end
+		expect_numbers({ 0, 1, 4, 5 })
+
+		vim.api.nvim_buf_set_lines(buf, 0, 0, true, { "0" })
+		api.multibuf_reload(mb)
+		expect_numbers({ 1, 2, 5, 6 })
+	end)
+end)
+
+check("titles stay on the first line of their entry after deletions", function()
+	local a = source({ "a1", "a2", "", "a4" })
+	local b = source({ "b1", "b2", "", "b4" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 3 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 3 } } },
+	})
+	local function get_title_rows()
+		local rows = {}
+		local marks = vim.api.nvim_buf_get_extmarks(mb, api.multibuf_structure_ns, 0, -1, { details = true })
+		for _, mark in ipairs(marks) do
+			if mark[4].virt_lines_above and mark[4].priority == 20001 and #mark[4].virt_lines > 0 then
+				table.insert(rows, mark[2])
+			end
+		end
+		return rows
+	end
+	local function expect_title_rows(expected, what)
+		vim.wait(1000, function()
+			return vim.deep_equal(get_title_rows(), expected)
+		end, 10)
+		expect_eq(get_title_rows(), expected, what)
+	end
+	local a_first = assert(api.multibuf_buf_get_line(mb, a, 0))
+
+	vim.api.nvim_win_set_cursor(0, { assert(api.multibuf_buf_get_line(mb, b, 0)) + 1, 0 })
+	vim.cmd("normal! dd")
+	expect_title_rows({ a_first, assert(api.multibuf_buf_get_line(mb, b, 1)) }, "title rows after dd")
+
+	-- deletes a4, b2 and the blank line after them, across the entry boundary
+	vim.api.nvim_win_set_cursor(0, { assert(api.multibuf_buf_get_line(mb, a, 3)) + 1, 0 })
+	vim.cmd("normal! dap")
+	local b_last = a_first + 3
+	expect_eq(vim.api.nvim_buf_get_lines(mb, b_last, b_last + 1, true), { "b4" }, "line after dap")
+	expect_title_rows({ a_first, b_last }, "title rows after dap")
+end)
+
+check("a sync going around two multibuffers showing each other stops with a warning", function()
+	local buf = source({ "x" })
+	local a = show(buf, { { start_row = 0 } })
+	local b = show(a, { { start_row = assert(api.multibuf_buf_get_line(a, buf, 0)) } })
+	api.multibuf_add_buf(a, { buf = b, regions = { { start_row = vim.api.nvim_buf_line_count(b) - 1 } } })
+	api.multibuf_force_reload(a)
+	expect_eq(api.multibuf_referencing(a), { b }, "multibuffers showing a")
+	expect_eq(api.multibuf_referencing(b), { a }, "multibuffers showing b")
+
+	-- the sync events of a chain of edits, as they fire one after the other
+	vim.api.nvim_buf_set_lines(buf, 0, 1, true, { "y" })
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = buf })
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = a })
+	vim.api.nvim_exec_autocmds("TextChanged", { buffer = b })
+	expect_notified("stopped syncing the cycle")
+	expect_eq(get_shown_line(a, buf, 0), "y", "line of the source")
+end)
+
+check("multibuf_write reports the outcome of every region", function()
+	local a = source({ "1", "2", "3", "4", "5" })
+	local b = source({ SPAN_LINE })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0 }, { start_row = 2, readonly = true }, { start_row = 4 } } },
+		{ buf = b, regions = { { start_row = 0, start_col = 14, end_col = 27 } } },
+	})
+	local ids = vim.tbl_map(function(region)
+		return region.id
+	end, api.multibuf_get_regions(mb))
+	for _, row in ipairs({ 0, 2 }) do
+		local line = assert(api.multibuf_buf_get_line(mb, a, row))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+	end
+	local line = assert(api.multibuf_buf_get_line(mb, b, 0))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "compute(3, 4)" })
+	vim.api.nvim_buf_set_text(b, 0, 20, 0, #SPAN_LINE, {})
+
+	local written, results = api.multibuf_write(mb)
+	expect_eq(written, { a }, "written sources")
+	local outcomes = {}
+	for _, result in ipairs(results) do
+		outcomes[result.region] = { result.status, result.reason }
+	end
+	expect_eq(outcomes, {
+		[ids[1]] = { "written" },
+		[ids[2]] = { "skipped", "readonly" },
+		[ids[3]] = { "skipped", "clean" },
+		[ids[4]] = { "failed", "conflict" },
+	}, "outcomes")
+	expect_eq(get_lines(a), { "edited", "2", "3", "4", "5" }, "written source")
+end)
+
+check("wrapping multibuffers set the wrap options of their window only", function()
+	vim.cmd("new")
+	local win = vim.api.nvim_get_current_win()
+	local plain = vim.api.nvim_get_current_buf()
+	vim.wo[win].wrap, vim.wo[win].breakindent = false, false
+	local function get_options()
+		return { vim.wo[win].wrap, vim.wo[win].breakindent, vim.wo[win].showbreak }
+	end
+	local before = get_options()
+
+	local buf = source({ string.rep("long ", 100) })
+	local mb = api.create_multibuf({ wrap = true })
+	api.multibuf_add_buf(mb, { buf = buf, regions = { { start_row = 0 } } })
+	wait_loaded(mb)
+	expect_eq(get_options(), { true, true, "↪ " }, "options of a wrapping multibuffer")
+	api.multibuf_set_wrap(mb, false)
+	expect_eq(vim.wo[win].wrap, false, "'wrap' after turning wrapping off")
+	api.multibuf_set_wrap(mb, true)
+
+	vim.api.nvim_win_set_buf(win, plain)
+	expect_eq(get_options(), before, "options of the buffer shown next")
+	vim.cmd("close")
+end)
+
+check("a write refreshes the other multibuffers of its source", function()
+	local buf = source({ "1", "2", "3" })
+	local a = show(buf, { { start_row = 0, end_row = 2 } })
+	local b = show(buf, { { start_row = 0, end_row = 2 } })
+	local line = assert(api.multibuf_buf_get_line(a, buf, 0))
+	vim.api.nvim_buf_set_lines(a, line, line + 1, true, { "from a" })
+	api.multibuf_write(a)
+	expect_eq(get_shown_line(b, buf, 0), "from a", "line in the other multibuffer")
+	api.multibuf_write(b)
+	expect_eq(get_lines(buf), { "from a", "2", "3" }, "source after writing the other multibuffer")
+
+	-- unwritten edits of the same lines in b are kept and flagged
+	line = assert(api.multibuf_buf_get_line(b, buf, 1))
+	vim.api.nvim_buf_set_lines(b, line, line + 1, true, { "from b" })
+	line = assert(api.multibuf_buf_get_line(a, buf, 1))
+	vim.api.nvim_buf_set_lines(a, line, line + 1, true, { "again from a" })
+	api.multibuf_write(a)
+	expect_eq(get_shown_line(b, buf, 1), "from b", "unwritten line in the other multibuffer")
+	local region = api.multibuf_get_regions(b)[1]
+	expect_eq(assert(api.multibuf_region_status(b, region.id)).conflict, true, "conflict")
+end)
+
+check("adding the same current line twice keeps one region", function()
+	local buf = source({ "1", "2", "3", "4", "5" })
+	local mb = api.create_multibuf()
+	vim.api.nvim_win_set_buf(0, buf)
+	vim.api.nvim_win_set_cursor(0, { 3, 0 })
+	expect_eq(api.multibuf_add_current_line(mb), mb, "multibuffer of the first add")
+	expect_eq(api.multibuf_add_current_line(mb), mb, "multibuffer of the second add")
+	api.multibuf_add_current_line(mb, 1)
+	wait_loaded(mb)
+
+	expect_eq(
+		vim.tbl_map(function(region)
+			return { region.buf, region.start_row, region.end_row }
+		end, api.multibuf_get_regions(mb)),
+		{ { buf, 1, 3 } },
+		"regions"
+	)
+end)
+
+check("the columns layout names the source of ten single line regions", function()
+	with_opts({ layout = "columns" }, function()
+		local entries = {}
+		for i = 1, 5 do
+			local buf = source({ "file " .. i .. " a", "gap", "file " .. i .. " b" })
+			table.insert(entries, { buf = buf, regions = { { start_row = 0 }, { start_row = 2 } } })
+		end
+		local mb = show_bufs(entries)
+		expect_eq(#api.multibuf_get_regions(mb), 10, "regions")
+
+		for _, entry in ipairs(entries) do
+			local name = vim.fn.pathshorten(vim.fn.fnamemodify(vim.api.nvim_buf_get_name(entry.buf), ":~:."))
+			for _, row in ipairs({ 0, 2 }) do
+				local line = assert(api.multibuf_buf_get_line(mb, entry.buf, row))
+				local marks = vim.api.nvim_buf_get_extmarks(
+					mb,
+					api.multibuf_structure_ns,
+					{ line, 0 },
+					{ line, -1 },
+					{ details = true }
+				)
+				local columns = {}
+				for _, mark in ipairs(marks) do
+					local chunks = mark[4].virt_text
+					if chunks and chunks[2] and chunks[2][1] == " │ " then
+						table.insert(columns, vim.trim(chunks[1][1]))
+					end
+				end
+				expect_eq(columns, { name }, "source column of row " .. row)
+				expect_eq(get_shown_line(mb, entry.buf, row), get_lines(entry.buf)[row + 1], "line of row " .. row)
+			end
+		end
+
+		local buf = entries[3].buf
+		local line = assert(api.multibuf_buf_get_line(mb, buf, 2))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+		api.multibuf_write(mb)
+		expect_eq(get_lines(buf), { "file 3 a", "gap", "edited" }, "written source")
+	end)
+end)
+
+check("writing after every source was wiped keeps the multibuffer modified", function()
+	local a = source({ "a1" })
+	local b = source({ "b1" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0 } } },
+		{ buf = b, regions = { { start_row = 0 } } },
+	})
+	local line = assert(api.multibuf_buf_get_line(mb, a, 0))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+	vim.cmd("bwipeout! " .. a .. " " .. b)
+
+	local written = api.multibuf_write(mb)
+	expect_eq(written, {}, "written sources")
+	expect_notified("2 region%(s%) could not be resolved")
+	expect_notified("multibuf_validate%(%) or multibuf_reload%(%)")
+	expect_eq(vim.bo[mb].modified, true, "multibuffer modified")
+end)
+
+check("malformed add_buf options name the field at fault", function()
+	local buf = source({ "1", "2", "3" })
+	local mb = api.create_multibuf()
+	local cases = {
+		{ { regions = {} }, "buf must be a buffer handle, got nil" },
+		{ { buf = buf }, "regions must be a list of regions, got nil" },
+		{ { buf = buf, regions = { {} } }, "regions[1]: start_row is missing" },
+		{ { buf = buf, regions = { { start_row = 0 }, { start_row = -1 } } }, "regions[2]: start_row must be" },
+		{ { buf = buf, regions = { { start_row = 2, end_row = 1 } } }, "end_row 1 is before start_row 2" },
+		{ { buf = buf, regions = { { start_row = 0, start_col = 1 } } }, "start_col and end_col must both be" },
+		{ { buf = buf, regions = { { start_row = 0, label = 1 } } }, "label must be a string, got number" },
+		{ { buf = buf, regions = { "0" } }, "regions[1]: expected a table, got string" },
+	}
+	for i, case in ipairs(cases) do
+		local ok, err = pcall(api.multibuf_add_buf, mb, case[1])
+		expect_eq(ok, false, "case " .. i .. " added")
+		expect_contains(err, "multibuffer: " .. case[2])
+	end
+
+	local ok, err = pcall(api.multibuf_add_bufs, mb, { { buf = buf, regions = {} }, { buf = buf, regions = { {} } } })
+	expect_eq(ok, false, "list added")
+	expect_contains(err, "opts_list[2]: regions[1]: start_row is missing")
+
+	-- a region of only a start_row is that line
+	api.multibuf_add_buf(mb, { buf = buf, regions = { { start_row = 1 } } })
+	wait_loaded(mb)
+	local region = api.multibuf_get_regions(mb)[1]
+	expect_eq({ region.start_row, region.end_row }, { 1, 1 }, "single line region")
+end)
+
+check("auto_remove_empty_regions drops a region emptied in its source", function()
+	with_opts({ auto_remove_empty_regions = true }, function()
+		local buf = source({ "1", "2", "3", "4", "5" })
+		local mb = show(buf, { { start_row = 0 }, { start_row = 2, end_row = 3 } })
+		local regions = api.multibuf_get_regions(mb)
+		local changed
+		vim.api.nvim_create_autocmd("User", {
+			pattern = "MultibufRegionsChanged",
+			once = true,
+			callback = function(args)
+				changed = args.data
+			end,
+		})
+
+		vim.api.nvim_buf_set_lines(buf, 2, 4, true, {})
+		api.multibuf_reload(mb, { source = buf })
+		local left = api.multibuf_get_regions(mb)
+		expect_eq(#left, 1, "regions left")
+		expect_eq(left[1].id, regions[1].id, "region left")
+		expect_eq(changed and changed.removed, { regions[2].id }, "removed regions in the event")
+		expect_eq(#vim.api.nvim_buf_get_extmarks(buf, api.multibuf__ns, 0, -1, {}), 1, "source extmarks")
+		expect_eq(api.multibuf_referencing(buf), { mb }, "multibuffers of the source")
+	end)
+
+	-- off by default, the region stays
+	local buf = source({ "1", "2", "3", "4" })
+	local mb = show(buf, { { start_row = 0 }, { start_row = 2 } })
+	vim.api.nvim_buf_set_lines(buf, 2, 3, true, {})
+	api.multibuf_reload(mb, { source = buf })
+	expect_eq(#api.multibuf_get_regions(mb), 2, "regions with the option off")
+end)
+
+check("multibuf_get_context answers every line of oddly shaped regions", function()
+	local lines = {}
+	for i = 1, 30 do
+		lines[i] = "    line " .. i
+	end
+	local a = source(lines)
+	local b = source({ SPAN_LINE, "x", "y" })
+	local wiped = source({ "gone 1", "gone 2" })
+	local mb = show_bufs({
+		{
+			buf = a,
+			regions = {
+				{ start_row = 0, end_row = 19, max_lines = 4 },
+				{ start_row = 22, end_row = 23, context = { before = 1, after = 1 } },
+				{ start_row = 27, end_row = 28, transform = "dedent" },
+			},
+		},
+		{ buf = b, regions = { { start_row = 0, start_col = 14, end_col = 27 }, { start_row = 2 } } },
+		{ buf = wiped, regions = { { start_row = 0, end_row = 1 } } },
+	})
+	-- an emptied region and a source whose extmarks are gone
+	vim.api.nvim_buf_set_lines(b, 2, 3, true, {})
+	vim.cmd("bwipeout! " .. wiped)
+
+	local line_count = vim.api.nvim_buf_line_count(mb)
+	for line = 0, line_count + 5 do
+		-- false asks without a column
+		for _, col in ipairs({ false, 0, 3, 200 }) do
+			local ok, ctx = pcall(api.multibuf_get_context, mb, line, col or nil)
+			if not ok then
+				error(string.format("line %d col %s: %s", line, vim.inspect(col), ctx))
+			end
+			if ctx then
+				expect_eq(ctx.line >= 0 and ctx.col >= 0, true, "context of line " .. line)
+			end
+		end
+	end
+end)
+
+check("the previewed layout matches the regions after the reload", function()
+	local lines = {}
+	for i = 1, 20 do
+		lines[i] = tostring(i)
+	end
+	local a = source(lines)
+	local b = source({ "b1", "b2", "b3" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 9, max_lines = 4 }, { start_row = 12, end_row = 13 } } },
+		{ buf = b, regions = { { start_row = 1, end_row = 2 } } },
+	})
+	-- the second region of `a` grows and `b` loses a line
+	vim.api.nvim_buf_set_lines(a, 13, 13, true, { "13.5" })
+	vim.api.nvim_buf_set_lines(b, 1, 2, true, {})
+
+	local layout = assert(api.multibuf_preview_layout(mb))
+	api.multibuf_reload(mb)
+	expect_eq(layout.line_count, vim.api.nvim_buf_line_count(mb), "line count")
+
+	local rendered = {}
+	for _, b_info in ipairs(get_state().multibufs[mb].bufs) do
+		for _, rid in ipairs(b_info.region_extmark_ids) do
+			local mark = vim.api.nvim_buf_get_extmark_by_id(mb, api.multibuf__ns, rid, { details = true })
+			table.insert(rendered, { buf = b_info.buf, start_row = mark[1], end_row = mark[3].end_row - 1 })
+		end
+	end
+	expect_eq(
+		vim.tbl_map(function(region)
+			return { buf = region.buf, start_row = region.start_row, end_row = region.end_row }
+		end, layout.regions),
+		rendered,
+		"region rows"
+	)
+	expect_eq(layout.regions[1].elided, true, "first region elided")
+end)
+
+check("next and previous change skip unchanged regions", function()
+	local lines = {}
+	for i = 1, 15 do
+		lines[i] = tostring(i)
+	end
+	local buf = source(lines)
+	local mb = show(buf, {
+		{ start_row = 0, end_row = 2 },
+		{ start_row = 4, end_row = 6 },
+		{ start_row = 8, end_row = 10 },
+		{ start_row = 12, end_row = 14 },
+	})
+	local changed = {}
+	for _, row in ipairs({ 5, 13 }) do
+		local line = assert(api.multibuf_buf_get_line(mb, buf, row))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited " .. row })
+		table.insert(changed, line)
+	end
+
+	expect_eq(api.multibuf_next_change(mb, 0), changed[1], "first change")
+	expect_eq(vim.api.nvim_win_get_cursor(0)[1], changed[1] + 1, "cursor line")
+	expect_eq(api.multibuf_next_change(mb, changed[1]), changed[2], "second change")
+	expect_eq(api.multibuf_next_change(mb, changed[2]), nil, "change after the last one")
+	expect_eq(api.multibuf_prev_change(mb, vim.api.nvim_buf_line_count(mb) - 1), changed[2], "last change")
+	expect_eq(api.multibuf_prev_change(mb, changed[2]), changed[1], "change before the last one")
+	expect_eq(api.multibuf_prev_change(mb, changed[1]), nil, "change before the first one")
+end)
+
+check("add_glob skips a file with an invalid region and adds the rest", function()
+	local dir = vim.fn.tempname()
+	vim.fn.mkdir(dir, "p")
+	vim.fn.writefile({ "good 1", "good 2" }, dir .. "/a.txt")
+	vim.fn.writefile({ "bad 1", "bad 2" }, dir .. "/b.txt")
+	local bad_path = vim.fn.fnamemodify(dir .. "/b.txt", ":p")
+	local mb = api.create_multibuf()
+	local bufs, skipped = api.multibuf_add_glob(mb, dir .. "/*.txt", function(buf)
+		if vim.api.nvim_buf_get_name(buf) == bad_path then
+			return { { start_row = 1, end_row = 0 } }
+		end
+		return { { start_row = 0, end_row = 1 } }
+	end)
+	wait_loaded(mb)
+
+	expect_eq(#bufs, 1, "added buffers")
+	expect_eq(#skipped, 1, "skipped files")
+	expect_contains(skipped[1].reason, "end_row 0 is before start_row 1")
+	expect_notified("skipped 1 file")
+	expect_eq(vim.fn.bufnr(bad_path), -1, "buffer of the skipped file")
+	expect_contains(table.concat(get_lines(mb), "\n"), "good 2")
+end)
+
+check("auto_write false leaves :write to the user", function()
+	local buf = source({ "1", "2", "3" })
+	local mb = api.create_multibuf({ auto_write = false })
+	api.multibuf_add_buf(mb, { buf = buf, regions = { { start_row = 0, end_row = 2 } } })
+	wait_loaded(mb)
+	expect_eq(#vim.api.nvim_get_autocmds({ event = "BufWriteCmd", buffer = mb }), 0, "BufWriteCmd autocmds")
+
+	local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+	vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited" })
+	-- an acwrite buffer without a BufWriteCmd can't be written
+	expect_eq(pcall(vim.cmd.write), false, ":write succeeded")
+	expect_eq(get_lines(buf), { "1", "2", "3" }, "source after :write")
+
+	api.multibuf_write(mb)
+	expect_eq(get_lines(buf), { "1", "edited", "3" }, "source after multibuf_write")
+end)
+
+check("multibuf_region_base holds the source at the last reload", function()
+	local buf = source({ "1", "2", "3", "4" })
+	local mb = show(buf, { { start_row = 1, end_row = 2 } })
+	local id = api.multibuf_get_regions(mb)[1].id
+	local base = assert(api.multibuf_region_base(mb, id))
+	expect_eq(base.lines, { "2", "3" }, "base lines")
+	expect_eq({ base.start_row, base.end_row }, { 1, 2 }, "base rows")
+	expect_eq(base.hash, vim.fn.sha256("2\n3"), "base hash")
+	base.lines[1] = "changed"
+	expect_eq(api.multibuf_region_base(mb, id).lines, { "2", "3" }, "base after changing the copy")
+
+	vim.api.nvim_buf_set_lines(buf, 2, 3, true, { "three" })
+	api.multibuf_reload(mb)
+	wait_loaded(mb)
+	local reloaded = assert(api.multibuf_region_base(mb, id))
+	expect_eq(reloaded.lines, { "2", "three" }, "base lines after the reload")
+	expect_eq(reloaded.changedtick, vim.api.nvim_buf_get_changedtick(buf), "base changedtick")
+	expect_eq(api.multibuf_region_base(mb, id + 1000), nil, "base of an unknown region")
+end)
+
+check("moving a region changes the render order and writes to its own source", function()
+	local a = source({ "a1", "a2" })
+	local b = source({ "b1", "b2" })
+	local mb = show_bufs({
+		{ buf = a, regions = { { start_row = 0, end_row = 1 } } },
+		{ buf = b, regions = { { start_row = 0, end_row = 1 } } },
+	})
+	local regions = api.multibuf_get_regions(mb)
+	expect_eq({ regions[1].buf, regions[2].buf }, { a, b }, "order before the move")
+
+	local target = assert(api.multibuf_buf_get_line(mb, a, 0))
+	local index = api.multibuf_move_region_to_line(mb, regions[2].id, target)
+	wait_loaded(mb)
+	expect_eq(index, 1, "new position")
+	regions = api.multibuf_get_regions(mb)
+	expect_eq({ regions[1].buf, regions[2].buf }, { b, a }, "order after the move")
+	expect_eq(
+		assert(api.multibuf_buf_get_line(mb, b, 0)) < assert(api.multibuf_buf_get_line(mb, a, 0)),
+		true,
+		"b rendered first"
+	)
+
+	for _, buf in ipairs({ a, b }) do
+		local line = assert(api.multibuf_buf_get_line(mb, buf, 1))
+		vim.api.nvim_buf_set_lines(mb, line, line + 1, true, { "edited " .. get_lines(buf)[1] })
+	end
+	api.multibuf_write(mb)
+	expect_eq(get_lines(a), { "a1", "edited a1" }, "first source")
+	expect_eq(get_lines(b), { "b1", "edited b1" }, "second source")
+end)
+
+check("run_guarded raises errors again unless the multibuffer was wiped out", function()
+	local run_guarded = get_local(api.multibuf_reload, "run_guarded")
+	local buf = source({ "1", "2" })
+	local mb = show(buf, { { start_row = 0, end_row = 1 } })
+	local info = get_state().multibufs[mb]
+
+	local ok, err = pcall(run_guarded, mb, info, function()
+		error("boom")
+	end)
+	expect_eq(ok, false, "call succeeded")
+	expect_contains(err, "boom")
+	expect_contains(err, "stack traceback")
+	expect_eq(info.busy, 0, "busy count")
+
+	ok = pcall(run_guarded, mb, info, function()
+		vim.api.nvim_buf_delete(mb, { force = true })
+		error("using the wiped buffer")
+	end)
+	expect_eq(ok, true, "call succeeded after the wipeout")
+	expect_cleaned_up(mb, buf)
+end)
+
+if #failures > 0 then
+	io.stderr:write(table.concat(failures, "\n\n") .. "\n")
+	vim.cmd("cquit 1")
+end
+vim.cmd("qa!")